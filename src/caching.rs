@@ -1,91 +1,188 @@
-use crate::global::*;
 use crate::rules;
-use crate::strategy::{self, persistent_caches};
-use crate::yahtzee_bonus_rules as bonus;
+use crate::solver::{self, Table};
+use crate::strategy::{persistent_caches, Expectation, State, StateIndex};
 
-use std::fs::{write, File};
-use std::io::{BufReader, Read, Write};
-use std::iter::repeat;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{ensure, Result};
 use clap::crate_version;
-use flate2::{bufread::DeflateDecoder, write::DeflateEncoder, Compression};
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use serde_json::{from_slice, to_vec};
 
-/// Persistent caches
+/// Header of persistent caches, followed by the tables as they are, see `solver::Table`, with the
+/// expectation values in little-endian
 #[derive(Serialize, Deserialize)]
-struct Caches {
+struct Header {
     /// Version information for compatibility
     version: String,
-    /// Actual caches
-    caches: persistent_caches::Caches,
+    /// Rules, see `rules::Rules::cache_key`, and numbers of states in their tables, see
+    /// `strategy::State::index_count`, in order
+    tables: Vec<(rules::CacheKey, usize)>,
 }
 
-/// Populate all caches by (transitively) calling all cachable functions with their entire domains
-fn warm_up_caches() {
-    repeat(false)
-        .zip(bonus::ALL_VARIANTS.iter().cloned())
-        .chain([(true, bonus::NONE)].iter().cloned())
-        .for_each(|(extreme, yahtzee_bonus)| {
-            let rules = rules::build_rules(extreme, yahtzee_bonus);
-            let state = strategy::State::new_from_rules(&rules);
-            let hand = PartialHand(Vec::new());
-            strategy::choose_reroll(&state, &hand, REROLLS, &rules);
-        });
+/// Most states of a table to load into memory when restoring - larger tables, e.g. of Maxi Yatzy
+/// with about 90 million states or Yahtzee Extreme with over a billion, are read from the file on
+/// each look-up
+const MAX_LOADED_STATES: StateIndex = 1 << 24;
+
+/// Built-in rules small enough to pre-cache, see `solver::MAX_LAYER_STATES`
+fn pre_cached_rules() -> Vec<rules::Rules> {
+    let all_rules = rules::build_all_rules().into_iter();
+    all_rules
+        // Games with several columns are estimated from playing a single one
+        .map(|rules| match rules.columns.len() {
+            1 => rules,
+            _ => rules.column_rules(),
+        })
+        .filter(|rules| {
+            let start = State::new_from_rules(rules);
+            solver::layer_states(&start, rules) <= solver::MAX_LAYER_STATES
+        })
+        .collect()
 }
 
-/// Dump caches to file
+/// Solve all turn-start states of rules and write them to file, each layer as soon as it is solved
 /// # Arguments
-/// * `filename` - to dump to
+/// * `filename` - to write to
+/// * `version` - version to write into header
+/// * `all_rules` - rules to solve, see `solver::solve_layers`
 /// # Returns
-/// Result - serialization, I/O can fail
-fn dump_caches(filename: &str) -> Result<()> {
-    let caches = Caches {
-        version: String::from(crate_version!()),
-        caches: persistent_caches::dump_caches(),
+/// Result - serialization, solving, I/O can fail
+fn write_caches(filename: &str, version: &str, all_rules: &[rules::Rules]) -> Result<()> {
+    let header = Header {
+        version: String::from(version),
+        tables: all_rules
+            .iter()
+            .map(|rules| (rules.cache_key(), State::index_count(rules)))
+            .collect(),
     };
-    let serialized = to_vec(&caches)?;
-    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(&serialized)?;
-    let compressed = encoder.finish()?;
-    write(filename, compressed)?;
+    let serialized = to_vec(&header)?;
+
+    // States not solved stay NaN
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writer.write_all(&(serialized.len() as u64).to_le_bytes())?;
+    writer.write_all(&serialized)?;
+    for &(_, len) in &header.tables {
+        for _ in 0..len {
+            writer.write_all(&Expectation::NAN.to_le_bytes())?;
+        }
+    }
+    let mut file = writer.into_inner()?;
+
+    let mut offset = (size_of::<u64>() + serialized.len()) as u64;
+    for (rules, &(_, len)) in all_rules.iter().zip(&header.tables) {
+        let start = State::new_from_rules(rules);
+        solver::solve_layers(&start, rules, |layer| {
+            for (first, expectations) in layer.blocks() {
+                let position = offset + (first * size_of::<Expectation>()) as u64;
+                let bytes = expectations.iter().flat_map(|e| e.to_le_bytes());
+                file.seek(SeekFrom::Start(position))?;
+                file.write_all(&bytes.collect::<Vec<_>>())?;
+            }
+            Ok(())
+        })?;
+        offset += (len * size_of::<Expectation>()) as u64;
+    }
+    file.flush()?;
     Ok(())
 }
 
-/// Populate all caches and dump to specified file
-/// See `dump_caches` for signature
+/// Read a number of bytes, without trusting it for allocation
+/// # Arguments
+/// * `reader` - to read from
+/// * `len` - number of bytes
+/// # Returns
+/// Result - fails if there are fewer bytes, I/O can fail
+fn read_exactly(reader: &mut impl Read, len: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    ensure!(bytes.len() == len, "Caches are truncated");
+    Ok(bytes)
+}
+
+/// Read caches from file
+/// # Arguments
+/// * `filename` - to read from
+/// * `max_loaded` - most states of a table to load into memory, larger ones are read from the file
+///   on each look-up, see `solver::Table`
+/// # Returns
+/// Result - deserialization, I/O can fail
+/// * Version from header
+/// * Caches
+fn read_caches(
+    filename: &str,
+    max_loaded: StateIndex,
+) -> Result<(String, persistent_caches::Caches)> {
+    let mut reader = BufReader::new(File::open(filename)?);
+    let file_len = reader.get_ref().metadata()?.len();
+    let header_len = read_exactly(&mut reader, size_of::<u64>())?;
+    let header_len = u64::from_le_bytes(header_len.try_into().unwrap()) as usize;
+    let header: Header = from_slice(&read_exactly(&mut reader, header_len)?)?;
+
+    let mut caches = persistent_caches::Caches::new();
+    let mut offset = (size_of::<u64>() + header_len) as u64;
+    for (key, len) in header.tables {
+        let size = size_of::<Expectation>() as u64;
+        ensure!(
+            len as u64 <= (file_len - offset) / size,
+            "Caches are truncated"
+        );
+        let table = match len <= max_loaded {
+            true => {
+                reader.seek(SeekFrom::Start(offset))?;
+                let bytes = read_exactly(&mut reader, len * size_of::<Expectation>())?;
+                let expectations = bytes
+                    .chunks_exact(size_of::<Expectation>())
+                    .map(|bytes| Expectation::from_le_bytes(bytes.try_into().unwrap()));
+                Table::Memory(expectations.collect())
+            }
+            _ => Table::File {
+                file: Mutex::new(File::open(filename)?),
+                offset,
+                len,
+            },
+        };
+        caches.insert(key, Arc::new(table));
+        offset += len as u64 * size;
+    }
+    ensure!(offset == file_len, "Caches have trailing data");
+    Ok((header.version, caches))
+}
+
+/// Solve all pre-cached rules and write their tables to file
+/// # Arguments
+/// * `filename` - to write to
+/// # Returns
+/// Result - serialization, solving, I/O can fail
 pub fn pre_cache(filename: &str) -> Result<()> {
-    warm_up_caches();
-    dump_caches(filename)
+    write_caches(filename, crate_version!(), &pre_cached_rules())
 }
 
 /// Restore caches from file
 /// # Arguments
 /// * `filename` - to restore from
 /// # Returns
-/// Result - decompression, deserialization, I/O can fail
+/// Result - deserialization, I/O can fail
 pub fn restore_caches(filename: &str) -> Result<()> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    let mut deflater = DeflateDecoder::new(reader);
-    let mut serialized = Vec::new();
-    deflater.read_to_end(&mut serialized)?;
-    let caches: Caches = from_slice(&serialized)?;
+    let (cache_version, caches) = read_caches(filename, MAX_LOADED_STATES)?;
 
     let version = crate_version!();
-    let mut req = VersionReq::parse(&format!("~{}", caches.version))?;
+    let mut req = VersionReq::parse(&format!("~{}", cache_version))?;
     // Minor releases are forwards and backwards compatible
     req.comparators[0].patch = Some(0);
     ensure!(
         req.matches(&Version::parse(version)?),
         "Caches were created on version {}, this is version {}",
-        caches.version,
+        cache_version,
         version
     );
 
-    persistent_caches::populate_caches(caches.caches);
+    persistent_caches::populate_caches(caches);
     Ok(())
 }
 
@@ -93,161 +190,115 @@ pub fn restore_caches(filename: &str) -> Result<()> {
 mod tests {
     use super::*;
 
-    use std::collections::HashMap;
-    use std::env::temp_dir;
-    use std::fs::remove_file;
+    use crate::global::LS;
+    use crate::solver::tests::solve;
+    use crate::strategy;
+    use crate::strategy::tests::{very_simple_rules, very_simple_state};
+    use crate::yahtzee_bonus_rules as bonus;
 
-    use rayon::iter::{IntoParallelIterator, ParallelIterator};
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write, OpenOptions};
 
-    macro_rules! assert_subset {
-        ($cache:expr, $comparison:expr) => {
-            $cache
-                .into_par_iter()
-                .for_each(|(k, v)| assert_eq!($comparison.get(&k).unwrap(), &v));
-        };
+    #[test]
+    fn test_pre_cached_rules() {
+        let pre_cached = pre_cached_rules();
+        for rules in &pre_cached {
+            let start = State::new_from_rules(rules);
+            assert!(solver::layer_states(&start, rules) <= solver::MAX_LAYER_STATES);
+        }
+        let is_pre_cached = |rules: rules::Rules| pre_cached.contains(&rules);
+        assert!(is_pre_cached(rules::build_rules(
+            false,
+            bonus::FORCED_JOKER
+        )));
+        assert!(is_pre_cached(rules::build_yatzy_rules(false)));
+        assert!(is_pre_cached(rules::build_yatzy_rules(true)));
+        assert!(is_pre_cached(rules::build_rules(true, bonus::NONE)));
+        assert!(!is_pre_cached(rules::build_rules(true, bonus::KNIFFEL)));
     }
 
     #[test]
-    fn test_dump_caches() {
-        // Smallest possible call to all cached strategy functions
-        let rules = rules::build_rules(false, bonus::FORCED_JOKER);
-        let us_fields = rules.fields[US].len();
-        let mut state = strategy::State::new_from_rules(&rules);
-        state.used = [
-            vec![true; rules.fields[US].len()],
-            [[true].repeat(us_fields - 1), vec![false]].concat(),
-        ];
-        let hand = PartialHand(Vec::new());
-        let rerolls = 1;
-        let reroll_recomm = strategy::choose_reroll(&state, &hand, rerolls, &rules);
-        let reroll_key = format!(
-            "{}{}{},{}",
-            state.compact_fmt(),
-            rules.short_name,
-            hand.compact_fmt(),
-            rerolls
-        );
+    fn test_write_caches() {
+        let rules = very_simple_rules();
+        let table = solve(&State::new_from_rules(&rules), &rules);
 
-        let test_file = temp_dir().join("optimal-yahtzee-extreme-test_dump_caches");
+        let test_file = temp_dir().join("optimal-yahtzee-extreme-test_write_caches");
         let test_filename = test_file.to_str().unwrap();
-        dump_caches(test_filename).unwrap();
-
-        let file = File::open(test_filename).unwrap();
-        let reader = BufReader::new(file);
-        let mut deflater = DeflateDecoder::new(reader);
-        let mut serialized = Vec::new();
-        deflater.read_to_end(&mut serialized).unwrap();
-        let caches: Caches = from_slice(&serialized).unwrap();
-
-        assert_eq!(caches.version, crate_version!());
-        let comparison = persistent_caches::dump_caches();
-        // Because other test functions might have modified the caches,
-        // check for subset rather than equality
-        assert_subset!(
-            caches.caches.probability_to_roll,
-            comparison.probability_to_roll
-        );
-        assert_subset!(
-            caches.caches.choose_reroll.clone(),
-            comparison.choose_reroll
-        );
-        assert_subset!(caches.caches.choose_field, comparison.choose_field);
-        let cached_reroll = caches.caches.choose_reroll.get(&reroll_key).unwrap();
-        assert_eq!(cached_reroll, &reroll_recomm);
+        write_caches(
+            test_filename,
+            crate_version!(),
+            std::slice::from_ref(&rules),
+        )
+        .unwrap();
 
-        remove_file(test_filename).unwrap();
-    }
+        // Loaded into memory, or read from the file on each look-up
+        for max_loaded in [StateIndex::MAX, 0] {
+            let (version, caches) = read_caches(test_filename, max_loaded).unwrap();
+            assert_eq!(version, crate_version!());
+            let cached_table = &caches[&rules.cache_key()];
+            assert_eq!(matches!(**cached_table, Table::Memory(_)), max_loaded > 0);
+            assert!(cached_table.iter().eq(table.iter()));
+        }
 
-    fn write_caches(filename: &str, caches: &Caches) {
-        let serialized = to_vec(caches).unwrap();
-        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&serialized).unwrap();
-        let compressed = encoder.finish().unwrap();
-        write(filename, compressed).unwrap();
+        remove_file(test_filename).unwrap();
     }
 
     #[test]
     fn test_restore_caches() {
-        // Dummy test data
-        let hand = PartialHand(Vec::new());
-        let rules = rules::build_rules(false, bonus::FORCED_JOKER);
-        let probabilities_to_roll = strategy::ProbabilitiesToRoll {
-            table: HashMap::new(),
-        };
-        let mut state = strategy::State::new_from_rules(&rules);
-        state.used = [
-            vec![true; rules.fields[US].len()],
-            vec![true; rules.fields[LS].len()],
-        ];
-        let rerolls = 0;
-        let expectation = 0.0;
-        let reroll_recomm = strategy::RerollRecomm {
-            hand: hand.clone(),
-            state: state.clone(),
-            expectation,
-        };
-        let field_recomm = strategy::FieldRecomm {
-            section: US,
-            field: 0,
-            state: state.clone(),
-            expectation,
-        };
-
-        // Save some `rustfmt` lines
-        let roll_key = String::from(rules.short_name);
-        let roll_test = probabilities_to_roll.clone();
-        let reroll_key = format!(
-            "{}{}{},{}",
-            state.compact_fmt(),
-            rules.short_name,
-            hand.compact_fmt(),
-            rerolls
-        );
-        let reroll_recomm_test = reroll_recomm.clone();
-        let field_key = format!(
-            "{}{}{}",
-            state.compact_fmt(),
-            rules.short_name,
-            hand.compact_fmt(),
-        );
-        let field_recomm_test = field_recomm.clone();
+        // Rules of this test only, as the nonsense value below is looked up by them
+        let mut rules = very_simple_rules();
+        rules.fields[LS][0].name = String::from("Nonsense");
+        let rules = rules.fingerprinted();
+        let state = very_simple_state();
 
         // Set another patch level, should still work
         let mut version = Version::parse(crate_version!()).unwrap();
         version.patch += 1;
 
-        let mut caches = Caches {
-            version: version.to_string(),
-            caches: persistent_caches::Caches {
-                // simple nonsense results -- have nothing, get nothing
-                probability_to_roll: HashMap::from([(roll_key, roll_test)]),
-                choose_reroll: HashMap::from([(reroll_key, reroll_recomm_test)]),
-                choose_field: HashMap::from([(field_key, field_recomm_test)]),
-            },
-        };
-
         let test_file = temp_dir().join("optimal-yahtzee-extreme-test_restore_caches");
         let test_filename = test_file.to_str().unwrap();
+        write_caches(
+            test_filename,
+            &version.to_string(),
+            std::slice::from_ref(&rules),
+        )
+        .unwrap();
 
-        write_caches(test_filename, &caches);
-        restore_caches(test_filename).unwrap();
+        // Simple nonsense value -- can only be found by looking it up
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(test_filename)
+            .unwrap();
+        let mut header_len = [0; size_of::<u64>()];
+        file.read_exact(&mut header_len).unwrap();
+        let offset = size_of::<u64>() as u64 + u64::from_le_bytes(header_len);
+        let position = offset + (state.index(&rules) * size_of::<Expectation>()) as u64;
+        file.seek(SeekFrom::Start(position)).unwrap();
+        file.write_all(&42.0_f64.to_le_bytes()).unwrap();
 
-        assert_eq!(
-            strategy::probability_to_roll(hand.clone(), &rules.dice),
-            probabilities_to_roll,
-        );
-        assert_eq!(
-            strategy::choose_reroll(&state, &hand, rerolls, &rules),
-            reroll_recomm,
-        );
-        assert_eq!(strategy::choose_field(&state, &hand, &rules), field_recomm);
+        restore_caches(test_filename).unwrap();
+        assert_eq!(strategy::turn_start_expectation(&state, &rules), 42.0);
 
         // Test version mismatch
         version.minor += 1;
-        caches.version = version.to_string();
-        write_caches(test_filename, &caches);
+        write_caches(
+            test_filename,
+            &version.to_string(),
+            std::slice::from_ref(&rules),
+        )
+        .unwrap();
         assert!(restore_caches(test_filename).is_err());
 
+        // Test truncation
+        let mut bytes = Vec::new();
+        File::open(test_filename)
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        write(test_filename, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(read_caches(test_filename, MAX_LOADED_STATES).is_err());
+
         remove_file(test_filename).unwrap();
     }
 }
@@ -0,0 +1,389 @@
+/// Probability distribution of final score when following the strategy
+use crate::global::*;
+use crate::rules;
+use crate::solver;
+use crate::strategy::{self, ArchFloat, State, StateIndex};
+
+use std::collections::BTreeMap;
+
+use cached::proc_macro::cached;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+/// Probability distribution of future score
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution {
+    /// Probability mass function, indexed by score
+    pub pmf: Vec<ArchFloat>,
+    /// Probability to receive the upper section bonus in every column (including having received it
+    /// already)
+    pub upper_bonus: ArchFloat,
+}
+
+impl Distribution {
+    /// Expectation value of score
+    pub fn mean(&self) -> ArchFloat {
+        let enumerated = self.pmf.iter().enumerate();
+        enumerated.map(|(score, p)| score as ArchFloat * p).sum()
+    }
+
+    /// Variance of score
+    pub fn variance(&self) -> ArchFloat {
+        let mean = self.mean();
+        let enumerated = self.pmf.iter().enumerate();
+        enumerated
+            .map(|(score, p)| (score as ArchFloat - mean).powi(2) * p)
+            .sum()
+    }
+
+    /// Percentile of score
+    /// # Arguments
+    /// * `quantile` - e.g. 0.5 for the median
+    /// # Returns
+    /// Smallest score that is reached with at most a probability of `quantile` - or more
+    /// precisely, the smallest score where the cumulative probability is at least `quantile`
+    pub fn percentile(&self, quantile: ArchFloat) -> Score {
+        let mut cumulative = 0.0;
+        for (score, p) in self.pmf.iter().enumerate() {
+            cumulative += p;
+            // Leave room for rounding errors in adding up
+            if cumulative >= quantile - 1e-9 {
+                return score as Score;
+            }
+        }
+        (self.pmf.len() - 1) as Score
+    }
+
+    /// Distribution shifted by points, e.g. to add back points already banked
+    pub fn shift(&self, points: Score) -> Self {
+        Distribution {
+            pmf: [vec![0.0; points as usize], self.pmf.clone()].concat(),
+            upper_bonus: self.upper_bonus,
+        }
+    }
+
+    /// Utility of own final score when playing against an opponent with this distribution of
+    /// final score
+    /// # Returns
+    /// Probability to win by own final score, counting ties as half a win
+    pub fn win_utility(&self) -> rules::Utility {
+        let mut below = 0.0;
+        let mut utility = Vec::new();
+        for p in &self.pmf {
+            utility.push(below + p / 2.0);
+            below += p;
+        }
+        // Beating every score
+        utility.push(below);
+        utility.into()
+    }
+}
+
+/// Probabilities of the outcomes of a turn when following the strategy
+/// # Arguments
+/// * `state` - state at the beginning of the turn, with at least one field unused
+/// * `rules` - rules to be used
+/// # Returns
+/// States after the turn by packed index, see `State::index`, with points gained and probabilities
+fn turn_outcomes(state: &State, rules: &rules::Rules) -> Vec<(StateIndex, Score, ArchFloat)> {
+    let rolls = solver::rolls(&rules.dice);
+    let next = strategy::after_turn_expectation(rules);
+    solver::turn_outcomes(state, rules, &rolls, next)
+}
+
+/// Most states to propagate a distribution over during play, see `solver::state_count`, so that it
+/// takes seconds at most - with more, it is estimated, see `estimate`
+pub const MAX_PROPAGATED_STATES: StateIndex = 1 << 16;
+
+/// Calculate probability distribution of future score
+/// Probability mass is propagated forward turn by turn, over all states reachable when following
+/// the strategy for the objective of `rules`, e.g. maximizing expected score. Each state is held
+/// by its packed index, with a dense probability mass function of the points gained so far, and
+/// only the states of the current turn are kept.
+/// # Arguments
+/// * `state` - state at the beginning of a turn
+/// * `rules` - rules to be used
+/// # Returns
+/// Distribution of points gained from now on, i.e. without points already banked
+pub fn distribution(state: &State, rules: &rules::Rules) -> Distribution {
+    let turns = state.turns_left(rules);
+    let mut mass: BTreeMap<StateIndex, Vec<ArchFloat>> =
+        BTreeMap::from([(state.index(rules), vec![1.0])]);
+    for _ in 0..turns {
+        let indices: Vec<_> = mass.keys().copied().collect();
+        let outcomes: Vec<_> = indices
+            .par_iter()
+            .map(|&index| turn_outcomes(&State::from_index(index, rules), rules))
+            .collect();
+        let mut next = BTreeMap::new();
+        for (pmf, outcomes) in mass.values().zip(outcomes) {
+            for (index, gained, q) in outcomes {
+                let gained = gained as usize;
+                let next_pmf: &mut Vec<_> = next.entry(index).or_default();
+                if next_pmf.len() < pmf.len() + gained {
+                    next_pmf.resize(pmf.len() + gained, 0.0);
+                }
+                for (score, p) in pmf.iter().enumerate() {
+                    next_pmf[score + gained] += p * q;
+                }
+            }
+        }
+        mass = next;
+    }
+
+    let mut distribution = Distribution {
+        pmf: Vec::new(),
+        upper_bonus: 0.0,
+    };
+    for (index, pmf) in mass {
+        if distribution.pmf.len() < pmf.len() {
+            distribution.pmf.resize(pmf.len(), 0.0);
+        }
+        for (score, p) in pmf.iter().enumerate() {
+            distribution.pmf[score] += p;
+        }
+        let state = State::from_index(index, rules);
+        if rules.us_bonus != rules::USBonusRules::NONE
+            && (state.upper_scores.iter()).all(|&score| score >= rules.us_bonus.threshold)
+        {
+            distribution.upper_bonus += pmf.iter().sum::<ArchFloat>();
+        }
+    }
+    distribution
+}
+
+/// Add a probability mass function shifted by points to another one
+/// # Arguments
+/// * `sum` - to add to, grown as needed
+/// * `pmf` - to add
+/// * `points` - to shift by
+/// * `factor` - to weigh `pmf` by, e.g. a probability
+fn add_shifted(sum: &mut Vec<ArchFloat>, pmf: &[ArchFloat], points: usize, factor: ArchFloat) {
+    if sum.len() < pmf.len() + points {
+        sum.resize(pmf.len() + points, 0.0);
+    }
+    for (score, p) in pmf.iter().enumerate() {
+        sum[score + points] += p * factor;
+    }
+}
+
+/// Probability mass function of the sum of two independent scores
+fn convolve(a: &[ArchFloat], b: &[ArchFloat]) -> Vec<ArchFloat> {
+    let mut sum = Vec::new();
+    for (points, &p) in b.iter().enumerate().filter(|&(_, &p)| p > 0.0) {
+        add_shifted(&mut sum, a, points, p);
+    }
+    sum
+}
+
+/// Estimate probability distribution of future score, where calculating it is infeasible, e.g.
+/// to evaluate an objective other than expected score early in the game, see
+/// `solver::MAX_SOLVED_STATES`
+/// Every use of a field left is taken as a turn played for that field alone, independently of the
+/// others, see `solver::field_distribution`. The upper section bonus is awarded as far as these
+/// turns reach the threshold, while chips, rerolls carried over and the Yahtzee bonus are left out.
+/// As choosing the field after rolling scores more, the distribution is shifted to the expected
+/// score if it is pre-cached, see `crate::caching`.
+/// # Arguments
+/// * `state` - state at the beginning of a turn
+/// * `rules` - rules to be used
+/// # Returns
+/// Distribution of points gained from now on, see `distribution`
+#[cached(
+    key = "(rules::CacheKey, StateIndex)",
+    convert = r#"{ (rules.cache_key(), State { banked: 0, ..state.clone() }.index(rules)) }"#
+)]
+pub fn estimate(state: &State, rules: &rules::Rules) -> Distribution {
+    let threshold = rules.us_bonus.threshold;
+    let mut pmf = vec![1.0];
+    let mut upper_bonus = 1.0;
+    let columns = state.used.iter().zip(&state.upper_scores);
+    for ((used, &upper_score), &multiplier) in columns.zip(&rules.columns) {
+        // Points gained in the column by upper section score, capped at the threshold
+        let mut upper = BTreeMap::from([(upper_score, vec![1.0])]);
+        let mut lower = vec![1.0];
+        for (section, fields) in used.iter().enumerate() {
+            for (field, &uses) in fields.iter().enumerate() {
+                let field_pmf = solver::field_distribution(section, field, rules);
+                for _ in uses..rules.capacity {
+                    if section == LS {
+                        lower = convolve(&lower, &field_pmf);
+                        continue;
+                    }
+                    let mut next = BTreeMap::new();
+                    for (score, points) in &upper {
+                        let outcomes = field_pmf.iter().enumerate();
+                        for (gained, &q) in outcomes.filter(|&(_, &q)| q > 0.0) {
+                            let score = (score + gained as Score).min(threshold);
+                            add_shifted(next.entry(score).or_default(), points, gained, q);
+                        }
+                    }
+                    upper = next;
+                }
+            }
+        }
+
+        // Apply the upper section bonus as soon as the threshold is crossed
+        let mut column = Vec::new();
+        let mut column_bonus = 0.0;
+        for (score, points) in upper {
+            let bonus = match upper_score < threshold && score >= threshold {
+                true => rules.us_bonus.bonus,
+                _ => 0,
+            };
+            add_shifted(&mut column, &points, bonus as usize, 1.0);
+            if score >= threshold {
+                column_bonus += points.iter().sum::<ArchFloat>();
+            }
+        }
+        upper_bonus *= column_bonus;
+        let column = convolve(&column, &lower);
+        let mut multiplied = vec![0.0; (column.len() - 1) * multiplier as usize + 1];
+        for (points, p) in column.into_iter().enumerate() {
+            multiplied[points * multiplier as usize] += p;
+        }
+        pmf = convolve(&pmf, &multiplied);
+    }
+    let mut estimate = Distribution {
+        pmf,
+        upper_bonus: match rules.us_bonus == rules::USBonusRules::NONE {
+            true => 0.0,
+            _ => upper_bonus,
+        },
+    };
+
+    let mut expectation_rules = rules.clone();
+    expectation_rules.objective = rules::Objective::Expectation;
+    let mut expectation_state = state.clone();
+    expectation_state.banked = 0;
+    if let Some(expectation) = strategy::look_up(&expectation_state, &expectation_rules) {
+        // Shift by whole points, splitting the mass between two for the fraction
+        let shift = (expectation - estimate.mean()).max(0.0);
+        let (points, fraction) = (shift.floor() as usize, shift.fract());
+        let mut shifted = Vec::new();
+        add_shifted(&mut shifted, &estimate.pmf, points, 1.0 - fraction);
+        add_shifted(&mut shifted, &estimate.pmf, points + 1, fraction);
+        estimate.pmf = shifted;
+    }
+    estimate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::strategy::persistent_caches;
+    use crate::strategy::tests::{simple_rules, very_simple_rules, very_simple_state};
+
+    use crate::yahtzee_bonus_rules as bonus;
+
+    use float_cmp::approx_eq;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_distribution_very_simple() {
+        let rules = very_simple_rules();
+        let distribution = distribution(&very_simple_state(), &rules);
+        // Only fail if all four throws, including one with a chip, are 1
+        let p = 1.0 - 0.5_f64.powi(4);
+        assert_eq!(distribution.pmf.len(), 2);
+        assert!(approx_eq!(ArchFloat, distribution.pmf[1], p));
+        assert!(approx_eq!(ArchFloat, distribution.mean(), p));
+        assert!(approx_eq!(
+            ArchFloat,
+            distribution.variance(),
+            p * (1.0 - p)
+        ));
+        assert_eq!(distribution.percentile(0.05), 0);
+        assert_eq!(distribution.percentile(0.5), 1);
+        assert_eq!(distribution.shift(2).percentile(0.5), 3);
+
+        let utility = distribution.win_utility();
+        let expected = [(1.0 - p) / 2.0, 1.0 - p + p / 2.0, 1.0];
+        assert_eq!(utility.0.len(), expected.len());
+        for (&u, expected) in utility.0.iter().zip(expected) {
+            assert!(approx_eq!(ArchFloat, u, expected));
+        }
+    }
+
+    #[test]
+    fn test_distribution_simple() {
+        let rules = simple_rules();
+        let state = State::new_from_rules(&rules);
+        let distribution = distribution(&state, &rules);
+        let total: ArchFloat = distribution.pmf.iter().sum();
+        assert!(approx_eq!(ArchFloat, total, 1.0, epsilon = 1e-9));
+        // Following the strategy must yield its expectation value
+        let expectation = strategy::turn_start_expectation(&state, &rules);
+        assert!(approx_eq!(
+            ArchFloat,
+            distribution.mean(),
+            expectation,
+            epsilon = 1e-9
+        ));
+        // Any two coins score in Aces or Twos, which is enough for the upper section bonus
+        assert!(approx_eq!(ArchFloat, distribution.upper_bonus, 1.0));
+    }
+
+    #[test]
+    fn test_distribution_regular() {
+        let rules = rules::build_rules(false, bonus::FORCED_JOKER);
+        // Late in the game with Yahtzee scored, and Sixes and Chance left
+        let mut state = State::new_from_rules(&rules);
+        state.used = vec![[
+            [vec![1; 5], vec![0]].concat(),
+            [vec![1; 6], vec![0]].concat(),
+        ]];
+        state.upper_scores = vec![40];
+        state.scored_yahtzee = true;
+        let distribution = distribution(&state, &rules);
+        let total: ArchFloat = distribution.pmf.iter().sum();
+        assert!(approx_eq!(ArchFloat, total, 1.0, epsilon = 1e-9));
+        let expectation = strategy::turn_start_expectation(&state, &rules);
+        assert!(approx_eq!(
+            ArchFloat,
+            distribution.mean(),
+            expectation,
+            epsilon = 1e-9
+        ));
+        assert!(distribution.upper_bonus > 0.0 && distribution.upper_bonus < 1.0);
+    }
+
+    #[test]
+    fn test_estimate() {
+        // Playing the only field alone is exact, as long as there are no chips
+        let mut rules = very_simple_rules();
+        rules.chips = 0;
+        let state = State::new_from_rules(&rules);
+        let estimated = estimate(&state, &rules);
+        let exact = distribution(&state, &rules);
+        assert_eq!(estimated.pmf.len(), exact.pmf.len());
+        for (&p, q) in estimated.pmf.iter().zip(exact.pmf) {
+            assert!(approx_eq!(ArchFloat, p, q));
+        }
+        assert_eq!(estimated.upper_bonus, 0.0);
+
+        // Choosing fields after rolling scores more, as does the Yahtzee bonus
+        let rules = simple_rules();
+        let state = State::new_from_rules(&rules);
+        let estimated = estimate(&state, &rules);
+        let total: ArchFloat = estimated.pmf.iter().sum();
+        assert!(approx_eq!(ArchFloat, total, 1.0, epsilon = 1e-9));
+        assert!(estimated.upper_bonus > 0.9 && estimated.upper_bonus < 1.0);
+        let expectation = strategy::turn_start_expectation(&state, &rules);
+        assert!(estimated.mean() < expectation);
+
+        // Shifted to the expected score once it is pre-cached
+        let mut rules = simple_rules();
+        rules.fields[LS][4].name = String::from("Pre-cached Chance");
+        let rules = rules.fingerprinted();
+        let table = solver::tests::solve(&state, &rules);
+        persistent_caches::populate_caches([(rules.cache_key(), Arc::new(table))].into());
+        let estimated = estimate(&state, &rules);
+        assert!(approx_eq!(
+            ArchFloat,
+            estimated.mean(),
+            expectation,
+            epsilon = 1e-9
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+/// Fairness of physical dice, from the rolls entered in a game
+use crate::global::*;
+
+/// Number of times a face came up
+pub type Count = u32;
+
+/// Rolls entered, counted per face for each kind of dice
+#[derive(Clone, Debug, PartialEq)]
+pub struct RollLog {
+    /// Kind of die and its faces, with the count of each face in the same order
+    counts: Vec<(Die, Faces, Vec<Count>)>,
+    /// Dice kept from the previous roll, which were not rolled again, `None` if unknown
+    kept: Option<PartialHand>,
+    /// Rolls not logged, as the dice kept before them were unknown
+    skipped: Count,
+}
+
+/// Chi-square goodness-of-fit test of the rolls of one kind of dice
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitTest {
+    /// Kind of die tested
+    pub die: Die,
+    /// Pips with the count observed and the count expected from the probability of the face
+    pub faces: Vec<(Pip, Count, f64)>,
+    /// Test statistic, the sum of squared deviations relative to the expected counts
+    pub chi_square: f64,
+    /// Degrees of freedom, one less than the faces
+    pub degrees: usize,
+    /// Probability of a deviation at least as large with fair dice, i.e. dice as in the rules,
+    /// withheld if any expected count is below `MIN_EXPECTED_COUNT`
+    pub p_value: Option<f64>,
+}
+
+/// Smallest expected count of every face for the chi-square approximation to hold, as a rule of
+/// thumb
+pub const MIN_EXPECTED_COUNT: f64 = 5.0;
+
+impl FitTest {
+    /// Dice rolled in total
+    pub fn rolled(&self) -> Count {
+        self.faces.iter().map(|&(_, count, _)| count).sum()
+    }
+}
+
+impl RollLog {
+    /// Empty log for rules `dice`
+    pub fn new(dice: &Dice) -> Self {
+        RollLog {
+            counts: dice
+                .0
+                .iter()
+                .map(|(die, faces, _)| (*die, faces.clone(), vec![0; faces.0.len()]))
+                .collect(),
+            kept: Some(PartialHand(Vec::new())),
+            skipped: 0,
+        }
+    }
+
+    /// Log the dice of a roll, leaving out those kept from the previous roll
+    /// If the dice kept from the previous roll are unknown, the roll is skipped.
+    /// # Arguments
+    /// * `hand` - full hand entered, with pips on the faces of the dice
+    /// * `kept` - dice kept for the next roll, empty at the end of the turn, `None` if unknown,
+    ///   e.g. when only a reroll was recommended
+    pub fn log(&mut self, hand: &PartialHand, kept: Option<PartialHand>) {
+        let Some(previous) = std::mem::replace(&mut self.kept, kept) else {
+            self.skipped += 1;
+            return;
+        };
+        let mut rolled = hand.0.clone();
+        // If the hand does not contain the dice kept, they were not kept after all
+        let mut without_kept = rolled.clone();
+        let all_kept = previous.0.iter().all(|die| {
+            let index = without_kept.iter().position(|other| other == die);
+            index.map(|index| without_kept.remove(index)).is_some()
+        });
+        if all_kept {
+            rolled = without_kept;
+        }
+        for (die, pip) in rolled {
+            let entry = self.counts.iter_mut().find(|(other, _, _)| *other == die);
+            if let Some((_, faces, counts)) = entry {
+                if let Some(face) = faces.0.iter().position(|&(other, _)| other == pip) {
+                    counts[face] += 1;
+                }
+            }
+        }
+    }
+
+    /// Start a new turn, e.g. after setting the state, with no dice kept
+    pub fn new_turn(&mut self) {
+        self.kept = Some(PartialHand(Vec::new()));
+    }
+
+    /// Rolls skipped, see `log`
+    pub fn skipped(&self) -> Count {
+        self.skipped
+    }
+
+    /// Test the rolls of each kind of dice against the faces in the rules
+    /// # Returns
+    /// Tests for each kind of dice rolled at least once, see `FitTest::p_value` for too few rolls
+    pub fn fit_tests(&self) -> Vec<FitTest> {
+        self.counts
+            .iter()
+            .filter(|(_, _, counts)| counts.iter().any(|&count| count > 0))
+            .map(|(die, faces, counts)| {
+                let rolled = counts.iter().sum::<Count>() as f64;
+                let faces = faces
+                    .0
+                    .iter()
+                    .zip(counts)
+                    .map(|(&(pip, probability), &count)| (pip, count, rolled * probability))
+                    .collect::<Vec<_>>();
+                let chi_square = faces
+                    .iter()
+                    .map(|&(_, count, expected)| (count as f64 - expected).powi(2) / expected)
+                    .sum::<f64>();
+                let degrees = faces.len() - 1;
+                let reliable = faces
+                    .iter()
+                    .all(|&(_, _, expected)| expected >= MIN_EXPECTED_COUNT);
+                FitTest {
+                    die: *die,
+                    faces,
+                    chi_square,
+                    degrees,
+                    p_value: reliable.then(|| chi_square_p_value(chi_square, degrees)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Iterations and relative precision for the incomplete gamma function
+const GAMMA_ITERATIONS: usize = 1000;
+const GAMMA_EPSILON: f64 = 1e-14;
+
+/// Probability of a chi-square distributed value of at least `chi_square`
+fn chi_square_p_value(chi_square: f64, degrees: usize) -> f64 {
+    match degrees {
+        // A die with a single face cannot deviate
+        0 => 1.0,
+        _ => gamma_q(degrees as f64 / 2.0, chi_square / 2.0),
+    }
+}
+
+/// Natural logarithm of the gamma function, by the Lanczos approximation as in Numerical Recipes
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    let mut y = x;
+    for coefficient in COEFFICIENTS {
+        y += 1.0;
+        series += coefficient / y;
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// Regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefix = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        // Series for the lower function, converging quickly here
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut n = a;
+        for _ in 0..GAMMA_ITERATIONS {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * GAMMA_EPSILON {
+                break;
+            }
+        }
+        (1.0 - sum * prefix).max(0.0)
+    } else {
+        // Continued fraction for the upper function, by the modified Lentz method
+        let tiny = f64::MIN_POSITIVE / GAMMA_EPSILON;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut fraction = d;
+        for i in 1..GAMMA_ITERATIONS {
+            let numerator = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = numerator * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + numerator / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            fraction *= delta;
+            if (delta - 1.0).abs() < GAMMA_EPSILON {
+                break;
+            }
+        }
+        prefix * fraction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chi_square_p_value() {
+        // Critical values at 5% and 1%
+        assert!((chi_square_p_value(3.841, 1) - 0.05).abs() < 1e-4);
+        assert!((chi_square_p_value(11.070, 5) - 0.05).abs() < 1e-4);
+        assert!((chi_square_p_value(21.666, 9) - 0.01).abs() < 1e-4);
+        // Exponential distribution with two degrees of freedom
+        for x in [0.5, 2.0, 10.0] {
+            assert!((chi_square_p_value(x, 2) - (-x / 2.0).exp()).abs() < 1e-9);
+        }
+        assert_eq!(chi_square_p_value(0.0, 5), 1.0);
+        assert_eq!(chi_square_p_value(3.0, 0), 1.0);
+    }
+
+    #[test]
+    fn test_roll_log() {
+        let dice = Dice(vec![
+            (D6, Faces::fair(1, 6), 2),
+            (D10, Faces::fair(0, 9), 1),
+        ]);
+        let mut log = RollLog::new(&dice);
+        assert!(log.fit_tests().is_empty());
+
+        // Keep the 6, which is not counted again in the next roll
+        log.log(
+            &PartialHand(vec![(D6, 6), (D6, 1), (D10, 0)]),
+            Some(PartialHand(vec![(D6, 6)])),
+        );
+        log.log(
+            &PartialHand(vec![(D6, 6), (D6, 6), (D10, 0)]),
+            Some(PartialHand(Vec::new())),
+        );
+        let tests = log.fit_tests();
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].die, D6);
+        assert_eq!(tests[0].rolled(), 3);
+        assert_eq!(tests[0].faces[0], (1, 1, 0.5));
+        assert_eq!(tests[0].faces[5], (6, 2, 0.5));
+        assert_eq!(tests[0].degrees, 5);
+        // Far too few rolls for a p-value
+        assert_eq!(tests[0].p_value, None);
+        assert_eq!(tests[1].rolled(), 2);
+
+        // A kept die missing from the hand means nothing was kept
+        log.log(
+            &PartialHand(vec![(D6, 6), (D6, 1), (D10, 0)]),
+            Some(PartialHand(vec![(D6, 2)])),
+        );
+        log.new_turn();
+        log.log(
+            &PartialHand(vec![(D6, 6), (D6, 1), (D10, 0)]),
+            Some(PartialHand(Vec::new())),
+        );
+        assert_eq!(log.fit_tests()[0].rolled(), 7);
+
+        // With the dice kept unknown, the next roll is skipped
+        log.log(&PartialHand(vec![(D6, 6), (D6, 1), (D10, 0)]), None);
+        log.log(
+            &PartialHand(vec![(D6, 6), (D6, 2), (D10, 0)]),
+            Some(PartialHand(Vec::new())),
+        );
+        assert_eq!(log.skipped(), 1);
+        assert_eq!(log.fit_tests()[0].rolled(), 9);
+    }
+
+    #[test]
+    fn test_fit_tests() {
+        // Coin loaded towards 2, tested as fair
+        let mut log = RollLog::new(&Dice::single(COIN, Faces::fair(1, 2), 1));
+        for pip in [[1].repeat(30), [2].repeat(70)].concat() {
+            log.log(
+                &PartialHand(vec![(COIN, pip)]),
+                Some(PartialHand(Vec::new())),
+            );
+        }
+        let test = &log.fit_tests()[0];
+        assert!((test.chi_square - 16.0).abs() < 1e-9);
+        assert!(test.p_value.unwrap() < 1e-4);
+
+        // Same rolls with the coin as loaded in the rules
+        let mut loaded = RollLog::new(&Dice::single(
+            COIN,
+            Faces::weighted([(1, 0.3), (2, 0.7)]),
+            1,
+        ));
+        loaded.counts[0].2 = log.counts[0].2.clone();
+        let test = &loaded.fit_tests()[0];
+        assert!(test.chi_square.abs() < 1e-9);
+        assert!((test.p_value.unwrap() - 1.0).abs() < 1e-9);
+    }
+}
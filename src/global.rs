@@ -1,10 +1,14 @@
 /// Global types and constants
+use std::hash::{Hash, Hasher};
+
 use serde::{Deserialize, Serialize};
 
 /// Number on a die (1-6 for d6)
 pub type Pip = u8;
-/// Die with minimum and maximum, e.g. (1, 6) for d6
-pub type Die = (Pip, Pip);
+/// Kind of die, identifying its faces in `Dice`, e.g. `D6`
+pub type Die = u8;
+/// Probability of a face of a die to come up
+pub type FaceProbability = f64;
 /// Dice rolled (5 for regular Yahtzee), assumed to be sorted
 pub type Hand = Vec<Pip>;
 /// Hand but it makes Clippy happy
@@ -17,20 +21,76 @@ pub type Score = u16;
 pub type Chips = u8;
 /// Rerolls in a turn (can be negative for chips usage)
 pub type Rerolls = i8;
-/// Score card for player, only indicating whether used or not
+/// Times a field was used, at most once for most games
+pub type Uses = u8;
+/// Column of score card, only indicating how many times each field was used
 /// * Array: Upper section and lower section
 /// * Inner vector: Individual scores per hand
-pub type ScoreCard = [Vec<bool>; 2];
+pub type ScoreColumn = [Vec<Uses>; 2];
+/// Score card for player, with one column for most games
+pub type ScoreCard = Vec<ScoreColumn>;
+/// Column index in scorecard
+pub type Column = usize;
 /// Section index in scorecard
 pub type Section = usize;
 /// Field index in section
 pub type Field = usize;
 
-/// Combination of dice
-/// * Minimum and maximum pip, e.g. (1, 6) for d6
-/// * Frequency, e.g. 5 for key 6 in regular Yahtzee (5 d6)
+/// Faces of a die, as distinct pips in ascending order with the probability of each
 #[derive(Clone, Debug, PartialEq)]
-pub struct Dice(pub Vec<(Die, Frequency)>);
+pub struct Faces(pub Vec<(Pip, FaceProbability)>);
+
+impl Faces {
+    /// Fair die with all pips from `lowest` to `highest`, e.g. 1 to 6 for d6
+    pub fn fair(lowest: Pip, highest: Pip) -> Self {
+        Self::weighted((lowest..=highest).map(|pip| (pip, 1.0)))
+    }
+
+    /// Die with faces of relative weights, normalized to probabilities
+    /// # Arguments
+    /// * `faces` - pips with their weights, e.g. measured probabilities of a calibrated die, or
+    ///   1 per face for `[1, 1, 2, 3, 5, 8]`, where repeated pips add up
+    pub fn weighted(faces: impl IntoIterator<Item = (Pip, FaceProbability)>) -> Self {
+        let mut faces = faces.into_iter().collect::<Vec<_>>();
+        faces.sort_by_key(|&(pip, _)| pip);
+        let total = faces
+            .iter()
+            .map(|&(_, weight)| weight)
+            .sum::<FaceProbability>();
+        let mut merged: Vec<(Pip, FaceProbability)> = Vec::new();
+        for (pip, weight) in faces {
+            match merged.last_mut() {
+                Some((last, probability)) if *last == pip => *probability += weight / total,
+                _ => merged.push((pip, weight / total)),
+            }
+        }
+        Faces(merged)
+    }
+}
+
+// Hash bitwise, so that dice can be identified for caching, see `rules::fingerprint`
+impl Hash for Faces {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for &(pip, probability) in &self.0 {
+            pip.hash(state);
+            probability.to_bits().hash(state);
+        }
+    }
+}
+
+/// Combination of dice
+/// * Kind of die, e.g. `D6`
+/// * Faces of the die, e.g. 1 to 6 for a fair d6
+/// * Frequency, e.g. 5 for d6 in regular Yahtzee (5 d6)
+#[derive(Clone, Debug, PartialEq, Hash)]
+pub struct Dice(pub Vec<(Die, Faces, Frequency)>);
+
+impl Dice {
+    /// Dice of a single kind, e.g. 5 fair `D6`
+    pub fn single(die: Die, faces: Faces, frequency: Frequency) -> Self {
+        Dice(vec![(die, faces, frequency)])
+    }
+}
 
 /// Partial hand, specifying dice and pips
 pub type PartialHandVec = Vec<(Die, Pip)>;
@@ -40,50 +100,58 @@ pub type PartialHandVec = Vec<(Die, Pip)>;
 pub struct PartialHand(pub PartialHandVec);
 
 impl PartialHand {
-    /// Compact format for cache keys
-    pub fn compact_fmt(&self) -> String {
-        self.0
-            .iter()
-            .map(|((min, max), pip)| format!("{},{},{}", min, max, pip))
-            .reduce(|a, b| format!("{},{}", a, b))
-            .unwrap_or_else(|| String::from(""))
+    /// Sort into the canonical order of hands, by pip and then by kind of die, so that equal hands
+    /// are equal as keys, e.g. in `strategy::probability_to_roll`
+    pub fn sort(&mut self) {
+        self.0.sort_unstable_by_key(|&(die, pip)| (pip, die));
     }
 
-    /// Decide whether this is a full hand according to rules `dice`
+    /// Decide whether this is a full hand according to rules `dice`, with pips on their faces
     pub fn is_full_hand(&self, dice: &Dice) -> bool {
-        dice.0.iter().all(|&(rules_die, freq)| {
-            let dice = self.0.iter().filter(|(hand_die, _)| hand_die == &rules_die);
-            dice.count() as Frequency == freq
-        })
+        let on_faces = self.0.iter().all(|&(hand_die, pip)| {
+            dice.0.iter().any(|(rules_die, faces, _)| {
+                *rules_die == hand_die && faces.0.iter().any(|&(face, _)| face == pip)
+            })
+        });
+        on_faces
+            && dice.0.iter().all(|&(rules_die, _, freq)| {
+                let dice = self.0.iter().filter(|(hand_die, _)| hand_die == &rules_die);
+                dice.count() as Frequency == freq
+            })
     }
 
     /// Decide whether this has the length of a full hand according to rules `dice`
     pub fn has_full_hand_length(&self, dice: &Dice) -> bool {
-        self.0.len() == dice.0.iter().map(|(_, freq)| freq).sum::<Frequency>() as usize
+        self.0.len() == dice.0.iter().map(|(_, _, freq)| freq).sum::<Frequency>() as usize
     }
 }
 
-/// Tuple for d6 pips
-pub const D6: (Pip, Pip) = (1, 6);
-/// Tuple for d10 pips
-pub const D10: (Pip, Pip) = (0, 9);
+/// Kind of the d6, and of the only dice in most games
+pub const D6: Die = 0;
+/// Kind of the d10 in Yahtzee Extreme, with pips 0 to 9
+pub const D10: Die = 1;
+/// Kind of the coins in tests, the first and only kind of dice as `D6` in most games
+#[cfg(test)]
+pub const COIN: Die = 0;
 
-/// Permitted rerolls per round
-pub const REROLLS: i8 = 2;
+/// Permitted rerolls per round in most games, see `rules::Rules::rerolls`
+pub const REROLLS: Rerolls = 2;
 /// Index of upper section
 pub const US: usize = 0;
 /// Index of lower section
 pub const LS: usize = 1;
-/// Size of a Yahtzee
-pub const YAHTZEE_SIZE: Frequency = 5;
-/// Index of Yahtzee in lower section (zero-indexed)
-pub const YAHTZEE_INDEX: usize = 5;
 
 /// Scores of various hands
 pub const FULL_HOUSE_SCORE: Score = 25;
 pub const SMALL_STRAIGHT_SCORE: Score = 30;
 pub const LARGE_STRAIGHT_SCORE: Score = 40;
 pub const YAHTZEE_SCORE: Score = 50;
+/// Scores of hands only in Yahtzee Extreme
+pub const THREE_PAIRS_SCORE: Score = 35;
+pub const TWO_TRIPLES_SCORE: Score = 45;
+pub const GRAND_FULL_HOUSE_SCORE: Score = 45;
+pub const HIGHWAY_SCORE: Score = 50;
+pub const YAHTZEE_EXTREME_SCORE: Score = 75;
 
 /// Field count in upper section
 #[cfg(test)]
@@ -96,26 +164,44 @@ pub const LS_LENGTH: usize = 7;
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_compact_fmt_partial_hand() {
-        assert_eq!(PartialHand(Vec::new()).compact_fmt(), "");
-        assert_eq!(
-            PartialHand(vec![(D6, 1), (D10, 2)]).compact_fmt(),
-            "1,6,1,0,9,2"
-        );
-    }
-
     #[test]
     fn test_is_full_hand() {
         let hand = PartialHand(vec![(D6, 1), (D10, 2)]);
-        assert!(hand.is_full_hand(&Dice(vec![(D6, 1), (D10, 1)])));
-        assert!(!hand.is_full_hand(&Dice(vec![(D6, 2)])));
+        let dice = Dice(vec![
+            (D6, Faces::fair(1, 6), 1),
+            (D10, Faces::fair(0, 9), 1),
+        ]);
+        assert!(hand.is_full_hand(&dice));
+        assert!(!hand.is_full_hand(&Dice::single(D6, Faces::fair(1, 6), 2)));
+        let off_faces = PartialHand(vec![(D6, 7), (D10, 2)]);
+        assert!(!off_faces.is_full_hand(&dice));
     }
 
     #[test]
     fn test_has_full_hand_length() {
         let hand = PartialHand(vec![(D6, 1)]);
-        assert!(hand.has_full_hand_length(&Dice(vec![(D6, 1)])));
-        assert!(!hand.has_full_hand_length(&Dice(vec![(D6, 2)])));
+        assert!(hand.has_full_hand_length(&Dice::single(D6, Faces::fair(1, 6), 1)));
+        assert!(!hand.has_full_hand_length(&Dice::single(D6, Faces::fair(1, 6), 2)));
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut hand = PartialHand(vec![(D6, 4), (D10, 0), (D6, 1), (D10, 4)]);
+        hand.sort();
+        assert_eq!(hand.0, vec![(D10, 0), (D6, 1), (D6, 4), (D10, 4)]);
+    }
+
+    #[test]
+    fn test_faces() {
+        assert_eq!(Faces::fair(1, 2), Faces(vec![(1, 0.5), (2, 0.5)]));
+        // Repeated pips add up, in any order
+        let fibonacci = Faces::weighted([8, 1, 2, 1, 3, 5].map(|pip| (pip, 1.0)));
+        let pips = fibonacci.0.iter().map(|&(pip, _)| pip).collect::<Vec<_>>();
+        assert_eq!(pips, vec![1, 2, 3, 5, 8]);
+        assert!((fibonacci.0[0].1 - 1.0 / 3.0).abs() < 1e-9);
+        assert!((fibonacci.0[4].1 - 1.0 / 6.0).abs() < 1e-9);
+        // Weights need not add up to one
+        let loaded = Faces::weighted([(1, 2.0), (2, 6.0)]);
+        assert_eq!(loaded, Faces(vec![(1, 0.25), (2, 0.75)]));
     }
 }
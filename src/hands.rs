@@ -42,11 +42,11 @@ fn identical(hand: &[Pip]) -> Vec<Frequency> {
 /// # Arguments
 /// * `required` - sorted vector of required frequencies, e.g. `[2, 3]` for Full House
 /// * `score` - function to calculate score based on hand,
-///             e.g. `total` for Three of a Kind, `|_| 50` for Yahtzee
+///   e.g. `total` for Three of a Kind, `|_| 50` for Yahtzee
 /// * `hand` - sorted
 pub fn generic_identical(
     required: Vec<Frequency>,
-    score: fn(&HandSlice) -> Score,
+    score: impl Fn(&HandSlice) -> Score,
     hand: &HandSlice,
 ) -> Score {
     let groups = identical(hand);
@@ -69,6 +69,61 @@ pub fn generic_identical(
     score(hand)
 }
 
+/// Frequency analysis over hand, keeping pips
+/// # Arguments
+/// * `hand` - sorted
+/// # Returns
+/// Vector of pips with their frequencies, highest pip first
+fn frequencies(hand: &HandSlice) -> Vec<(Pip, Frequency)> {
+    let mut groups = Vec::new();
+    let mut iter = hand.iter().rev().peekable();
+    while let Some(&pip) = iter.next() {
+        let mut freq = 1;
+        while iter.next_if_eq(&&pip).is_some() {
+            freq += 1;
+        }
+        groups.push((pip, freq));
+    }
+    groups
+}
+
+/// Best assignment of required frequencies to different groups, see `highest_identical`
+/// # Arguments
+/// * `required` - required frequencies still to be assigned
+/// * `groups` - see `frequencies`
+/// * `used` - bit mask of groups already assigned
+/// # Returns
+/// Highest sum of the assigned pips, `None` if no assignment exists
+fn highest_groups(
+    required: &[Frequency],
+    groups: &[(Pip, Frequency)],
+    used: usize,
+) -> Option<Score> {
+    let (&size, rest) = match required.split_first() {
+        Some(split) => split,
+        None => return Some(0),
+    };
+    let candidates = groups.iter().enumerate();
+    candidates
+        .filter(|&(i, &(_, freq))| used & 1 << i == 0 && freq >= size)
+        .filter_map(|(i, &(pip, _))| {
+            let rest = highest_groups(rest, groups, used | 1 << i)?;
+            Some(size as Score * pip as Score + rest)
+        })
+        .max()
+}
+
+/// Lower section fields scoring only the dice in groups of identical pips
+/// e.g. One Pair, Two Pairs, Three of a Kind in Scandinavian Yatzy, Full House in Maxi Yatzy
+/// # Arguments
+/// * `required` - required frequencies, each for different pips, e.g. `[2, 2]` for Two Pairs
+/// * `hand` - sorted
+/// # Returns
+/// Sum of the pips in the highest scoring groups, or 0 if there are not enough groups
+pub fn highest_identical(required: &[Frequency], hand: &HandSlice) -> Score {
+    highest_groups(required, &frequencies(hand), 0).unwrap_or(0)
+}
+
 /// Lower section straights
 /// # Arguments
 /// * `length` - desired length, e.g. `5` for Large Straight
@@ -96,52 +151,81 @@ pub fn generic_straight(length: Frequency, score: Score, hand: &HandSlice) -> Sc
     0
 }
 
+/// Lower section straights of fixed pips
+/// e.g. Small Straight 1-2-3-4-5 in Scandinavian Yatzy
+/// # Arguments
+/// * `lowest` - lowest required pip
+/// * `highest` - highest required pip
+/// * `score` - score if the hand contains all pips from `lowest` to `highest`
+/// * `hand` - sorted
+pub fn exact_straight(lowest: Pip, highest: Pip, score: Score, hand: &HandSlice) -> Score {
+    match (lowest..=highest).all(|pip| hand.contains(&pip)) {
+        true => score,
+        _ => 0,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_generic_upper_section() {
-        assert_eq!(generic_upper_section(1, &vec![1, 1, 1, 3, 5]), 3);
-        assert_eq!(generic_upper_section(5, &vec![2, 5, 5, 5, 6]), 15);
-        assert_eq!(generic_upper_section(6, &vec![3, 4, 6, 6, 6]), 18);
+        assert_eq!(generic_upper_section(1, &[1, 1, 1, 3, 5]), 3);
+        assert_eq!(generic_upper_section(5, &[2, 5, 5, 5, 6]), 15);
+        assert_eq!(generic_upper_section(6, &[3, 4, 6, 6, 6]), 18);
     }
 
     #[test]
     fn test_generic_identical() {
-        assert_eq!(generic_identical(vec![3], total, &vec![1, 1, 2, 3, 5]), 0);
-        assert_eq!(generic_identical(vec![3], total, &vec![1, 1, 1, 3, 5]), 11);
-        assert_eq!(generic_identical(vec![3], total, &vec![2, 5, 5, 5, 6]), 23);
-        assert_eq!(generic_identical(vec![3], total, &vec![3, 4, 6, 6, 6]), 25);
-        assert_eq!(generic_identical(vec![3], total, &vec![3, 6, 6, 6, 6]), 27);
+        assert_eq!(generic_identical(vec![3], total, &[1, 1, 2, 3, 5]), 0);
+        assert_eq!(generic_identical(vec![3], total, &[1, 1, 1, 3, 5]), 11);
+        assert_eq!(generic_identical(vec![3], total, &[2, 5, 5, 5, 6]), 23);
+        assert_eq!(generic_identical(vec![3], total, &[3, 4, 6, 6, 6]), 25);
+        assert_eq!(generic_identical(vec![3], total, &[3, 6, 6, 6, 6]), 27);
 
+        assert_eq!(generic_identical(vec![2, 3], |_| 25, &[2, 2, 3, 3, 3]), 25);
+        assert_eq!(generic_identical(vec![2, 3], |_| 25, &[2, 2, 3, 3, 4]), 0);
+        assert_eq!(generic_identical(vec![2, 3], |_| 25, &[2, 2, 2, 2, 2]), 0);
+        assert_eq!(generic_identical(vec![5], |_| 50, &[2, 2, 2, 2, 2]), 50);
         assert_eq!(
-            generic_identical(vec![2, 3], |_| 25, &vec![2, 2, 3, 3, 3]),
-            25
-        );
-        assert_eq!(
-            generic_identical(vec![2, 3], |_| 25, &vec![2, 2, 3, 3, 4]),
-            0
-        );
-        assert_eq!(
-            generic_identical(vec![2, 3], |_| 25, &vec![2, 2, 2, 2, 2]),
-            0
-        );
-        assert_eq!(generic_identical(vec![5], |_| 50, &vec![2, 2, 2, 2, 2]), 50);
-        assert_eq!(
-            generic_identical(vec![2, 2, 2], |_| 45, &vec![2, 2, 4, 4, 6, 6]),
+            generic_identical(vec![2, 2, 2], |_| 45, &[2, 2, 4, 4, 6, 6]),
             45
         );
     }
 
     #[test]
     fn test_generic_straight() {
-        assert_eq!(generic_straight(4, 30, &vec![1, 2, 2, 3, 4, 6]), 30);
-        assert_eq!(generic_straight(4, 30, &vec![1, 2, 3, 4, 6, 7]), 30);
-        assert_eq!(generic_straight(4, 30, &vec![1, 3, 4, 5, 6, 7]), 30);
-        assert_eq!(generic_straight(4, 30, &vec![1, 2, 4, 5, 6, 7]), 30);
-        assert_eq!(generic_straight(4, 30, &vec![1, 1, 2, 3, 6, 7]), 0);
-        assert_eq!(generic_straight(4, 30, &vec![1, 3, 4, 5, 6, 7]), 30);
-        assert_eq!(generic_straight(5, 40, &vec![1, 3, 4, 5, 6, 7]), 40);
+        assert_eq!(generic_straight(4, 30, &[1, 2, 2, 3, 4, 6]), 30);
+        assert_eq!(generic_straight(4, 30, &[1, 2, 3, 4, 6, 7]), 30);
+        assert_eq!(generic_straight(4, 30, &[1, 3, 4, 5, 6, 7]), 30);
+        assert_eq!(generic_straight(4, 30, &[1, 2, 4, 5, 6, 7]), 30);
+        assert_eq!(generic_straight(4, 30, &[1, 1, 2, 3, 6, 7]), 0);
+        assert_eq!(generic_straight(4, 30, &[1, 3, 4, 5, 6, 7]), 30);
+        assert_eq!(generic_straight(5, 40, &[1, 3, 4, 5, 6, 7]), 40);
+    }
+
+    #[test]
+    fn test_highest_identical() {
+        assert_eq!(highest_identical(&[2], &[1, 1, 3, 3, 5]), 6);
+        assert_eq!(highest_identical(&[2], &[1, 2, 3, 4, 5]), 0);
+        assert_eq!(highest_identical(&[2, 2], &[1, 1, 3, 3, 5]), 8);
+        assert_eq!(highest_identical(&[2, 2], &[1, 1, 1, 3, 3]), 8);
+        assert_eq!(highest_identical(&[2, 2], &[3, 3, 3, 3, 5]), 0);
+        assert_eq!(highest_identical(&[3], &[2, 5, 5, 5, 5]), 15);
+        assert_eq!(highest_identical(&[4], &[2, 5, 5, 5, 6]), 0);
+        assert_eq!(highest_identical(&[4], &[5, 5, 5, 5, 5]), 20);
+        // Only five of the six dice count, with the higher pips in the triple
+        assert_eq!(highest_identical(&[3, 2], &[1, 1, 1, 6, 6, 6]), 20);
+        assert_eq!(highest_identical(&[3, 2], &[2, 2, 2, 3, 3, 6]), 12);
+        assert_eq!(highest_identical(&[3, 2], &[2, 2, 2, 2, 2, 2]), 0);
+    }
+
+    #[test]
+    fn test_exact_straight() {
+        assert_eq!(exact_straight(1, 5, 15, &[1, 2, 3, 4, 5]), 15);
+        assert_eq!(exact_straight(1, 5, 15, &[2, 3, 4, 5, 6]), 0);
+        assert_eq!(exact_straight(2, 6, 20, &[2, 3, 4, 5, 6]), 20);
+        assert_eq!(exact_straight(2, 6, 20, &[1, 2, 3, 4, 5]), 0);
     }
 }
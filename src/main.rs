@@ -1,14 +1,19 @@
 mod caching;
+mod distribution;
+mod fairness;
 mod global;
 mod hands;
 mod repl;
 mod rules;
+mod rules_spec;
+mod simulation;
+mod solver;
 mod strategy;
 mod view_model;
 mod yahtzee_bonus_rules;
 
 use anyhow::{anyhow, ensure, Result};
-use clap::{IntoApp, Parser};
+use clap::{IntoApp, Parser, Subcommand};
 
 #[derive(Parser)]
 #[clap(about, version)]
@@ -19,14 +24,59 @@ struct Args {
     /// Pre-cache and write to <FILE>
     #[clap(long, value_name = "FILE")]
     cache_write: Option<String>,
+    /// Play by rules from <FILE> instead of a game, in TOML (or JSON if ending in .json)
+    #[clap(long, value_name = "FILE", conflicts_with = "game")]
+    rules: Option<String>,
+    /// Maximize probability to reach <SCORE> in total instead of expected score
+    #[clap(long, value_name = "SCORE")]
+    target: Option<global::Score>,
+    /// Play Yahtzee Extreme with bonus rules <VARIANT>: forced, free, original or kniffel
+    #[clap(long, value_name = "VARIANT")]
+    yahtzee_bonus: Option<String>,
+    /// Fill fields in card order, top to bottom
+    #[clap(long)]
+    forced_order: bool,
+    /// Allow <REROLLS> rerolls per turn instead of those of the game
+    #[clap(long, value_name = "REROLLS")]
+    rerolls: Option<global::Rerolls>,
+    /// Bank rerolls left at the end of a turn, up to <CAP>
+    #[clap(long, value_name = "CAP")]
+    carry_over: Option<global::Rerolls>,
+    /// Use at most <MAX> rerolls in a turn, including banked ones [default: all]
+    #[clap(long, value_name = "MAX", requires = "carry-over")]
+    carry_over_max: Option<global::Rerolls>,
+    /// Log rolls entered, to test the dice for fairness
+    #[clap(long)]
+    log_rolls: bool,
     /// Game to play. Allowed options:{n}
-    /// extreme  - Yahtzee Extreme{n}
+    /// extreme  - Yahtzee Extreme, without Yahtzee bonus unless given{n}
     /// forced   - Forced choice joker, used in regular Yahtzee{n}
     /// free     - Free choice joker, a popular alternative{n}
     /// original - Original 1956 rules{n}
     /// kniffel  - Kniffel rules, as published in German-speaking countries{n}
-    /// none     - No Yahtzee bonus
+    /// none     - No Yahtzee bonus{n}
+    /// yacht    - Original 1938 Yacht, without bonuses{n}
+    /// yatzy    - Scandinavian Yatzy{n}
+    /// maxi     - Maxi Yatzy, with six dice{n}
+    /// generala - Generala, with bonuses for hands made on the first roll and Double Generala{n}
+    /// triple   - Triple Yahtzee, with three columns scored ×1, ×2 and ×3{n}
+    /// balut    - Balut, with each field used four times
     game: Option<String>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Play games with random rolls following the strategy, and report statistics
+    Simulate {
+        /// Number of games to play
+        #[clap(long, default_value = "1000")]
+        games: usize,
+        /// Seed of the random rolls, the same seed playing the same games
+        #[clap(long, default_value = "0")]
+        seed: u64,
+    },
 }
 
 fn main() -> Result<()> {
@@ -43,28 +93,96 @@ fn main() -> Result<()> {
         caching::restore_caches(&filename)?;
     }
 
-    if args.game.is_none() {
-        println!("{}", app.render_usage());
-        return Err(anyhow!("Must specify game to play unless pre-caching"));
-    }
-    let rules_result = build_rules(&args.game.unwrap());
+    let rules_result = match (args.rules, args.game) {
+        (Some(filename), _) => rules_spec::load_rules(&filename),
+        (_, Some(game)) => build_rules(&game, args.yahtzee_bonus.as_deref()),
+        _ => {
+            println!("{}", app.render_usage());
+            return Err(anyhow!(
+                "Must specify game or rules to play unless pre-caching"
+            ));
+        }
+    };
     if let Err(e) = rules_result {
         println!("{}", app.render_usage());
         return Err(e);
     }
-    let rules = rules_result.unwrap();
+    let mut rules = rules_result.unwrap();
+    rules.forced_order |= args.forced_order;
+    if let Some(target) = args.target {
+        // Games with several columns are only estimated for expected score
+        ensure!(
+            rules.columns.len() == 1,
+            "Playing for a target requires a single column"
+        );
+        rules.objective = rules::Objective::Target(target);
+    }
+    if let Some(rerolls) = args.rerolls {
+        ensure!(rerolls >= 0, "Rerolls must not be negative");
+        rules.rerolls = rerolls;
+    }
+    if let Some(cap) = args.carry_over {
+        // Games with several columns are estimated without banked rerolls
+        ensure!(
+            rules.columns.len() == 1,
+            "Carrying over rerolls requires a single column"
+        );
+        let max_per_turn = args.carry_over_max.unwrap_or(rules.rerolls + cap);
+        ensure!(
+            cap >= 0 && max_per_turn >= rules.rerolls,
+            "Rerolls banked must not be negative, and at least {} usable per turn",
+            rules.rerolls
+        );
+        rules.carry_over = rules::CarryOverRules { cap, max_per_turn };
+    }
     let state = strategy::State::new_from_rules(&rules);
-    Ok(repl::run(view_model::ViewModel {
+    let rerolls = state.turn_rerolls(&rules);
+    let view_model = view_model::ViewModel {
         rules,
         state,
-        rerolls: global::REROLLS,
-    })?)
+        score: [0, 0],
+        rerolls,
+    };
+    if let Some(Command::Simulate { games, seed }) = args.command {
+        ensure!(games > 0, "Must play at least one game");
+        let expectation = strategy::turn_start_expectation(&view_model.state, &view_model.rules);
+        let simulation = simulation::simulate(&view_model, games, seed, &mut simulation::Optimal)?;
+        println!("{}", simulation.report(&view_model.rules, expectation));
+        return Ok(());
+    }
+    Ok(repl::run(view_model, args.log_rolls)?)
 }
 
-fn build_rules(game: &str) -> Result<rules::Rules> {
+fn build_rules(game: &str, yahtzee_bonus: Option<&str>) -> Result<rules::Rules> {
+    if let Some(variant) = yahtzee_bonus {
+        ensure!(game == "extreme", "Only Yahtzee Extreme takes bonus rules");
+        if game == "balut" {
+            return Ok(rules::build_balut_rules());
+        }
+        let mut enumerated = yahtzee_bonus_rules::ALL_VARIANTS_NAMES.iter().enumerate();
+        return enumerated
+            .find(|(_, &name)| name == variant)
+            .map(|(i, _)| rules::build_rules(true, yahtzee_bonus_rules::ALL_VARIANTS[i].clone()))
+            .ok_or_else(|| anyhow!("Unknown Yahtzee bonus variant: {}", variant));
+    }
     if game == "extreme" {
         return Ok(rules::build_rules(true, yahtzee_bonus_rules::NONE));
     }
+    if game == "yatzy" || game == "maxi" {
+        return Ok(rules::build_yatzy_rules(game == "maxi"));
+    }
+    if game == "triple" {
+        return Ok(rules::build_triple_rules());
+    }
+    if game == "yacht" {
+        return Ok(rules::build_yacht_rules());
+    }
+    if game == "generala" {
+        return Ok(rules::build_generala_rules());
+    }
+    if game == "balut" {
+        return Ok(rules::build_balut_rules());
+    }
     let mut enumerated = yahtzee_bonus_rules::ALL_VARIANTS_NAMES.iter().enumerate();
     enumerated
         .find(|(_, &name)| name == game)
@@ -78,8 +196,36 @@ mod tests {
 
     #[test]
     fn test_build_rules() {
-        assert_eq!(build_rules("extreme").unwrap().short_name, 'f');
-        assert_eq!(build_rules("forced").unwrap().short_name, 'a');
-        assert!(build_rules("null").is_err());
+        assert_eq!(build_rules("extreme", None).unwrap().short_name, 'f');
+        assert_eq!(build_rules("forced", None).unwrap().short_name, 'a');
+        assert_eq!(build_rules("yatzy", None).unwrap().short_name, 'i');
+        assert_eq!(build_rules("maxi", None).unwrap().short_name, 'j');
+        assert_eq!(build_rules("generala", None).unwrap().short_name, 'k');
+        assert_eq!(build_rules("yacht", None).unwrap().short_name, 'l');
+        assert_eq!(build_rules("triple", None).unwrap().short_name, 'm');
+        assert_eq!(build_rules("balut", None).unwrap().short_name, 'n');
+        assert!(build_rules("null", None).is_err());
+
+        let extreme = build_rules("extreme", Some("kniffel")).unwrap();
+        assert_eq!(extreme.short_name, 'f');
+        assert_eq!(extreme.yahtzee_bonus.short_name, 'd');
+        assert!(build_rules("extreme", Some("null")).is_err());
+        assert!(build_rules("forced", Some("free")).is_err());
+    }
+
+    #[test]
+    fn test_args() {
+        let args = Args::try_parse_from(["", "--carry-over", "2", "forced"]).unwrap();
+        assert_eq!(args.carry_over, Some(2));
+        assert_eq!(args.carry_over_max, None);
+        let args = Args::try_parse_from(["", "--rerolls", "3", "forced"]).unwrap();
+        assert_eq!(args.rerolls, Some(3));
+        assert!(Args::try_parse_from(["", "--carry-over-max", "3", "forced"]).is_err());
+        let args = Args::try_parse_from(["", "forced", "simulate", "--games", "10"]).unwrap();
+        assert_eq!(args.game, Some(String::from("forced")));
+        assert!(matches!(
+            args.command,
+            Some(Command::Simulate { games: 10, seed: 0 })
+        ));
     }
 }
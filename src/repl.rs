@@ -1,5 +1,8 @@
+use crate::fairness::{self, RollLog};
 use crate::global::*;
-use crate::view_model::{Recommendation, ViewModel};
+use crate::rules;
+use crate::strategy::{self, Expectation};
+use crate::view_model::{Action, Recommendation, ViewModel};
 use crate::yahtzee_bonus_rules as bonus;
 
 use core::num::ParseIntError;
@@ -8,15 +11,28 @@ use std::io;
 use anyhow::{anyhow, Result};
 use linefeed::{Interface, ReadResult};
 
-pub fn run(mut view_model: ViewModel) -> io::Result<()> {
+/// Run the REPL
+/// # Arguments
+/// * `view_model` - game to play
+/// * `log_rolls` - log the rolls entered, see `fairness`
+pub fn run(mut view_model: ViewModel, log_rolls: bool) -> io::Result<()> {
+    let mut log = log_rolls.then(|| RollLog::new(&view_model.rules.dice.dice));
     let reader = Interface::new("")?;
     reader.set_prompt(">>> ")?;
     while let ReadResult::Input(input) = reader.read_line()? {
+        let (command, args) = input.split_once(' ').unwrap_or((&input, ""));
         println!(
             "{}",
-            match match input.as_str() {
+            match match command {
                 "state" => output_state(&view_model),
-                _ => recommend(&mut view_model, &input),
+                "distribution" => output_distribution(&view_model),
+                "fairness" => fairness(log.as_ref()),
+                "player" => set_player(&mut view_model, &mut log, args),
+                "opponent" => set_opponent(&mut view_model, args),
+                "alternatives" => alternatives(&view_model, args),
+                "keep" => keep(&mut view_model, &mut log, args),
+                "score" => score(&mut view_model, &mut log, args),
+                _ => recommend(&mut view_model, &mut log, &input),
             } {
                 Ok(out) => out,
                 Err(err) => format!("Error: {}", err),
@@ -29,91 +45,465 @@ pub fn run(mut view_model: ViewModel) -> io::Result<()> {
 fn output_state(view_model: &ViewModel) -> Result<String> {
     let rules = &view_model.rules;
     let state = &view_model.state;
+    let score = &view_model.score;
+    let capacity = rules.capacity;
     Ok(format!(
-        "{}{}{}{}",
-        format!(
-            "You have scored {} in the upper section and {} in the lower section.",
-            state.score[0], state.score[1],
-        ),
-        rules
-            .fields
+        "You have scored {} in the upper section and {} in the lower section.{}{}{}{}{}",
+        score[US],
+        score[LS],
+        state
+            .used
             .iter()
-            .zip(state.used.iter())
-            .map(
-                |(rules, useds)| rules.iter().zip(useds.iter()).map(|(rule, &used)| format!(
-                    "{}: {}",
-                    rule.name,
-                    if used { "used" } else { "unused" },
-                ))
-            )
-            .flatten()
-            .fold(String::new(), |acc, info| format!("{}\n{}", acc, info)),
-        match rules.yahtzee_bonus == bonus::NONE {
-            true => String::new(),
-            _ => format!(
-                "\nYou have {}scored a Yahtzee.",
+            .zip(&rules.columns)
+            .enumerate()
+            .map(|(column, (used, multiplier))| {
+                let header = match rules.columns.len() {
+                    1 => String::new(),
+                    _ => format!("\nColumn {} (×{}):", column + 1, multiplier),
+                };
+                rules
+                    .fields
+                    .iter()
+                    .zip(used.iter())
+                    .flat_map(|(rules, useds)| {
+                        rules.iter().zip(useds.iter()).map(|(rule, &used)| {
+                            let uses = match (capacity, used) {
+                                (1, 0) => String::from("unused"),
+                                (1, _) => String::from("used"),
+                                _ => format!("used {} of {} times", used, capacity),
+                            };
+                            format!("{}: {}", rule.name, uses)
+                        })
+                    })
+                    .fold(header, |acc, info| format!("{}\n{}", acc, info))
+            })
+            .collect::<String>(),
+        match (rules.yahtzee_bonus == bonus::NONE, rules.yahtzee_field()) {
+            (false, Some(yahtzee)) => format!(
+                "\nYou have {}scored a {}.",
                 if !state.scored_yahtzee { "not " } else { "" },
+                rules.fields[LS][yahtzee].name,
             ),
+            _ => String::new(),
         },
         match rules.chips == 0 {
             true => String::new(),
             _ => format!("\nYou have {} chip(s) left.", state.chips),
+        },
+        match rules.carry_over.cap == 0 {
+            true => String::new(),
+            _ => format!("\nYou have {} reroll(s) banked.", state.pool),
+        },
+        match rules.objective {
+            rules::Objective::Expectation => String::new(),
+            rules::Objective::Target(target) => format!("\nYou are playing to reach {}.", target),
+            rules::Objective::Win(_) => String::from("\nYou are playing against an opponent."),
         }
     ))
 }
 
-fn recommend(view_model: &mut ViewModel, input: &str) -> Result<String> {
-    let mut split = input.split(' ');
-    let d6_chars = split.next().unwrap().chars();
-    let d6s = d6_chars
-        .map(|c| c.to_string().parse().map_err(|e: ParseIntError| anyhow!(e)))
-        .collect::<Result<Vec<_>>>()?;
-    let mut partial_hand: PartialHand =
-        PartialHand(d6s.iter().map(|&p| (D6, p)).collect::<Vec<_>>());
-    if let Some(d10) = split.next() {
-        partial_hand.0.push((D10, d10.parse()?));
-    }
-    Ok(match view_model.recommend(partial_hand)? {
-        Recommendation::Reroll(partial_hand) => {
-            let mut iter = partial_hand.0.iter().peekable();
-            let mut out = String::new();
-            if let Some(peek) = iter.peek() {
-                if peek.0 == D10 {
-                    out = String::from("You should keep the d10");
-                    iter.next();
-                }
+/// Parse state at the beginning of a turn
+/// # Arguments
+/// * `rules` - rules of the game
+/// * `args` - upper section score (without bonus and multiplier, separated by commas for each
+///   column), lower section score (in total), used fields as one digit per field in the order of
+///   `state` output, counting the times used (0 or 1 for most games), optionally chips left
+///   (otherwise all), `yahtzee` if a Yahtzee was scored and `pool=N` for N rerolls banked,
+///   separated by spaces
+/// # Returns
+/// Strategy state and points banked in upper and lower section
+fn parse_state(rules: &rules::Rules, args: &str) -> Result<(strategy::State, [Score; 2])> {
+    let usage = || anyhow!("Expected upper and lower section score and used fields");
+    let mut split = args.split(' ');
+    let upper = split.next().ok_or_else(usage)?.split(',');
+    let upper = upper
+        .map(|score| score.parse())
+        .collect::<Result<Vec<Score>, _>>()?;
+    if upper.len() != rules.columns.len() {
+        return Err(anyhow!(
+            "Expected upper section score for each of {} column(s)",
+            rules.columns.len()
+        ));
+    }
+    let lower: Score = split.next().ok_or_else(usage)?.parse()?;
+    let used = split.next().ok_or_else(usage)?;
+
+    let mut state = strategy::State::new_from_rules(rules);
+    let mut used = used.chars();
+    let capacity = rules.capacity;
+    let usage = || anyhow!("Expected 0 to {} for every field", capacity);
+    for field in state.used.iter_mut().flatten().flatten() {
+        let uses = used.next().and_then(|c| c.to_digit(10));
+        *field = match uses {
+            Some(uses) if uses <= capacity as u32 => uses as Uses,
+            _ => return Err(usage()),
+        };
+    }
+    if used.next().is_some() {
+        return Err(usage());
+    }
+    if rules.forced_order && !strategy::is_in_order(&state.used, rules) {
+        return Err(anyhow!("Expected fields to be used in card order"));
+    }
+    for arg in split {
+        match arg {
+            "yahtzee" if rules.yahtzee_bonus != bonus::NONE && rules.yahtzee_field().is_some() => {
+                state.scored_yahtzee = true
             }
-            let d6s = iter.map(|(_, pip)| pip.to_string()).collect::<Vec<_>>();
-            if d6s.is_empty() {
-                if out.is_empty() {
-                    out = String::from("You should reroll altogether");
-                }
+            _ if arg.starts_with("pool=") => state.pool = arg["pool=".len()..].parse()?,
+            _ => state.chips = arg.parse()?,
+        }
+    }
+    if state.chips > rules.chips {
+        return Err(anyhow!("At most {} chip(s) allowed", rules.chips));
+    }
+    if !(0..=rules.carry_over.cap).contains(&state.pool) {
+        return Err(anyhow!(
+            "At most {} reroll(s) banked allowed",
+            rules.carry_over.cap
+        ));
+    }
+
+    let threshold = rules.us_bonus.threshold;
+    state.upper_scores = upper.iter().map(|&score| score.min(threshold)).collect();
+    let upper = upper
+        .iter()
+        .zip(&rules.columns)
+        .map(|(&score, multiplier)| {
+            let bonus = if score >= threshold {
+                rules.us_bonus.bonus
             } else {
-                let d6 = &d6s[..].join(", ");
-                let recomm = match out.as_str() {
-                    "" => format!("You should keep d6 {}", d6),
-                    _ => format!(" and d6 {}", d6),
-                };
-                out += &recomm;
+                0
+            };
+            (score + bonus) * multiplier
+        });
+    Ok((state, [upper.sum(), lower]))
+}
+
+fn set_player(view_model: &mut ViewModel, log: &mut Option<RollLog>, args: &str) -> Result<String> {
+    let (state, score) = parse_state(&view_model.rules, args)?;
+    view_model.set_state(state, score);
+    if let Some(log) = log {
+        log.new_turn();
+    }
+    output_state(view_model)
+}
+
+fn set_opponent(view_model: &mut ViewModel, args: &str) -> Result<String> {
+    let (state, score) = parse_state(&view_model.rules, args)?;
+    let opponent = view_model.set_opponent(&state, score)?;
+    Ok(format!(
+        "Your opponent can expect to score {:.2} in total, you now play to win.",
+        opponent.mean()
+    ))
+}
+
+fn output_distribution(view_model: &ViewModel) -> Result<String> {
+    let distribution = view_model.distribution()?;
+    let mut output = format!(
+        "You can expect to score {:.2} in total, with a standard deviation of {:.2}.
+Percentiles: {}",
+        distribution.mean(),
+        distribution.variance().sqrt(),
+        [5, 25, 50, 75, 95]
+            .iter()
+            .map(|&percent| format!(
+                "{}%: {}",
+                percent,
+                distribution.percentile(percent as f64 / 100.0)
+            ))
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    if view_model.rules.us_bonus != rules::USBonusRules::NONE {
+        output += &format!(
+            "\nYou receive the upper section bonus with a probability of {:.2}%.",
+            distribution.upper_bonus * 100.0
+        );
+    }
+    Ok(output)
+}
+
+/// Name of a die by the pips on its faces, e.g. `d6` for 1 to 6 and `d10` for 0 to 9, or by their
+/// range or pips otherwise, e.g. `{1,2,3,5,8} die`
+fn die_name(pips: impl IntoIterator<Item = Pip>) -> String {
+    let pips: Vec<_> = pips.into_iter().collect();
+    let consecutive = pips.windows(2).all(|pair| pair[1] == pair[0] + 1);
+    match (consecutive, &pips[..]) {
+        (true, [0 | 1, ..]) => format!("d{}", pips.len()),
+        (true, [lowest, .., highest]) => format!("{}-{} die", lowest, highest),
+        _ => {
+            let pips = pips.iter().map(|pip| pip.to_string()).collect::<Vec<_>>();
+            format!("{{{}}} die", pips.join(","))
+        }
+    }
+}
+
+/// Parse hand as entered by the user: pips of each kind of dice in the order of the rules (e.g.
+/// the d6 and then the d10 in Yahtzee Extreme) separated by spaces, with `-` for none. Pips of a
+/// kind are digits, or separated by commas if any of its faces has several digits.
+/// # Arguments
+/// * `input` - as entered
+/// * `dice` - dice rules
+fn parse_hand(input: &str, dice: &Dice) -> Result<PartialHand> {
+    let groups: Vec<_> = input.split(' ').collect();
+    if groups.len() > dice.0.len() {
+        return Err(anyhow!(
+            "Expected pips of at most {} kind(s) of dice",
+            dice.0.len()
+        ));
+    }
+    let mut partial_hand = PartialHand(Vec::new());
+    for (group, (die, faces, _)) in groups.iter().zip(&dice.0) {
+        let group = group.trim_start_matches('-');
+        if group.is_empty() {
+            continue;
+        }
+        let pips = match faces.0.iter().any(|&(pip, _)| pip > 9) {
+            true => group
+                .split(',')
+                .map(|pip| pip.parse().map_err(|e: ParseIntError| anyhow!(e)))
+                .collect::<Result<Vec<Pip>>>()?,
+            _ => group
+                .chars()
+                .map(|c| c.to_string().parse().map_err(|e: ParseIntError| anyhow!(e)))
+                .collect::<Result<Vec<Pip>>>()?,
+        };
+        partial_hand
+            .0
+            .extend(pips.into_iter().map(|pip| (*die, pip)));
+    }
+    Ok(partial_hand)
+}
+
+/// Describe recommended action, e.g. "keep d6 6, 6"
+fn describe(view_model: &ViewModel, recommendation: &Recommendation) -> String {
+    match recommendation {
+        Recommendation::Reroll(partial_hand, _) => {
+            let dice = view_model.rules.dice.dice.0.iter();
+            let kept = dice.filter_map(|(die, faces, frequency)| {
+                let pips = partial_hand.0.iter().filter(|(kept, _)| kept == die);
+                let pips = pips.map(|(_, pip)| pip.to_string()).collect::<Vec<_>>();
+                let name = || die_name(faces.0.iter().map(|&(pip, _)| pip));
+                match (pips.len(), frequency) {
+                    (0, _) => None,
+                    (_, 1) => Some(format!("the {}", name())),
+                    _ => Some(format!("{} {}", name(), pips.join(", "))),
+                }
+            });
+            let kept = kept.collect::<Vec<_>>();
+            match kept.is_empty() {
+                true => String::from("reroll altogether"),
+                _ => format!("keep {}", kept.join(" and ")),
             }
-            out.push('.');
-            out
         }
-        Recommendation::Field(section, field) => {
-            format!(
-                "You should score as {}.",
-                view_model.rules.fields[section][field].name
-            )
+        Recommendation::Field(column, section, field, _) => {
+            let rules = &view_model.rules;
+            let out = format!("score as {}", rules.fields[*section][*field].name);
+            match rules.columns.len() {
+                1 => out,
+                _ => format!("{} in column {}", out, column + 1),
+            }
         }
+    }
+}
+
+/// Format expectation value, as points or percent depending on the objective
+fn format_expectation(view_model: &ViewModel, expectation: Expectation) -> String {
+    match view_model.rules.objective {
+        rules::Objective::Expectation => format!("{:.2}", expectation),
+        _ => format!("{:.2}%", expectation * 100.0),
+    }
+}
+
+/// Describe expectation value in a sentence, depending on the objective
+fn describe_expectation(view_model: &ViewModel, expectation: Expectation) -> String {
+    match view_model.rules.objective {
+        rules::Objective::Expectation => {
+            format!("You can expect to score {:.2} in total.", expectation)
+        }
+        rules::Objective::Target(target) => format!(
+            "You reach {} in total with a probability of {:.2}%.",
+            target,
+            expectation * 100.0
+        ),
+        rules::Objective::Win(_) => format!(
+            "You win with a probability of {:.2}%, counting ties as half.",
+            expectation * 100.0
+        ),
+    }
+}
+
+fn recommend(view_model: &mut ViewModel, log: &mut Option<RollLog>, input: &str) -> Result<String> {
+    // In forced order, there is no choice of field
+    let forced =
+        view_model.rules.forced_order && view_model.state.open_fields(&view_model.rules).len() == 1;
+    let hand = parse_hand(input, &view_model.rules.dice.dice)?;
+    let recommendation = view_model.recommend(hand.clone())?;
+    if let Some(log) = log {
+        log.log(&hand, kept_for(&recommendation));
+    }
+    let verb = match (&recommendation, forced) {
+        (Recommendation::Field(..), true) => "must",
+        _ => "should",
+    };
+    Ok(format!(
+        "You {} {}.\n{}",
+        verb,
+        describe(view_model, &recommendation),
+        describe_expectation(view_model, recommendation.expectation())
+    ))
+}
+
+/// Dice kept for the next roll after a recommendation, none when scoring, unknown when rerolling
+/// as the recommendation need not be followed, see `keep` to log the dice kept
+fn kept_for(recommendation: &Recommendation) -> Option<PartialHand> {
+    match recommendation {
+        Recommendation::Reroll(..) => None,
+        Recommendation::Field(..) => Some(PartialHand(Vec::new())),
+    }
+}
+
+/// Apply action actually taken
+/// # Arguments
+/// * `view_model` - to apply to
+/// * `log` - rolls logged, if any
+/// * `args` - hand and action, separated by `/`
+/// * `parse_action` - parse action from the part after `/`
+fn apply(
+    view_model: &mut ViewModel,
+    log: &mut Option<RollLog>,
+    args: &str,
+    parse_action: fn(&ViewModel, &str) -> Result<Action>,
+) -> Result<String> {
+    let usage = || anyhow!("Expected hand and action, separated by /");
+    let (hand, action) = args.split_once('/').ok_or_else(usage)?;
+    let action = parse_action(view_model, action.trim())?;
+    let kept = match &action {
+        Action::Reroll(keep) => keep.clone(),
+        Action::Field(..) => PartialHand(Vec::new()),
+    };
+    let hand = parse_hand(hand.trim(), &view_model.rules.dice.dice)?;
+    let (recommendation, loss) = view_model.apply(hand.clone(), action)?;
+    if let Some(log) = log {
+        log.log(&hand, Some(kept));
+    }
+    let expectation = recommendation.expectation() - loss;
+    // Leave room for rounding errors
+    let out = match loss > 1e-9 {
+        true => format!(
+            "You lost {} against the best move, which was to {}.",
+            format_expectation(view_model, loss),
+            describe(view_model, &recommendation)
+        ),
+        _ => String::from("That was the best move."),
+    };
+    Ok(format!(
+        "{}\n{}",
+        out,
+        describe_expectation(view_model, expectation)
+    ))
+}
+
+fn keep(view_model: &mut ViewModel, log: &mut Option<RollLog>, args: &str) -> Result<String> {
+    apply(view_model, log, args, |view_model, kept| {
+        let dice = &view_model.rules.dice.dice;
+        Ok(Action::Reroll(parse_hand(kept, dice)?))
+    })
+}
+
+fn score(view_model: &mut ViewModel, log: &mut Option<RollLog>, args: &str) -> Result<String> {
+    apply(view_model, log, args, |view_model, field| {
+        // Number of field in the order of `state` output, starting at 1
+        let number: usize = field.parse()?;
+        let rules = &view_model.rules;
+        let mut enumerated = (0..rules.columns.len()).flat_map(|column| {
+            let sections = rules.fields.iter().enumerate();
+            sections.flat_map(move |(section, rules)| {
+                (0..rules.len()).map(move |field| Action::Field(column, section, field))
+            })
+        });
+        let field = number.checked_sub(1).and_then(|i| enumerated.nth(i));
+        field.ok_or_else(|| anyhow!("No field number {}", number))
     })
 }
 
+fn alternatives(view_model: &ViewModel, args: &str) -> Result<String> {
+    let usage = || anyhow!("Expected number of alternatives and hand");
+    let (count, input) = args.split_once(' ').ok_or_else(usage)?;
+    let count: usize = count.parse()?;
+    let hand = parse_hand(input, &view_model.rules.dice.dice)?;
+    let alternatives = view_model.alternatives(hand)?;
+    Ok(alternatives
+        .iter()
+        .take(count)
+        .enumerate()
+        .map(|(i, (recommendation, loss))| {
+            let mut description = describe(view_model, recommendation);
+            // Capitalize, descriptions are ASCII
+            description[..1].make_ascii_uppercase();
+            let expectation = recommendation.expectation();
+            let mut out = format!(
+                "{}. {}: {}",
+                i + 1,
+                description,
+                format_expectation(view_model, expectation)
+            );
+            if i > 0 {
+                out += &format!(" ({} less)", format_expectation(view_model, *loss));
+            }
+            out
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Test the rolls logged for fairness, for each kind of dice
+fn fairness(log: Option<&RollLog>) -> Result<String> {
+    let log = log.ok_or_else(|| anyhow!("Rolls are not logged, start with --log-rolls"))?;
+    let tests = log.fit_tests();
+    let mut out = match tests.is_empty() {
+        true => String::from("No rolls logged yet."),
+        _ => tests
+            .iter()
+            .map(|test| {
+                let faces = test.faces.iter().map(|(pip, count, expected)| {
+                    format!("{}: {} (expected {:.2})", pip, count, expected)
+                });
+                let p_value = match test.p_value {
+                    Some(p_value) => format!("p-value {:.4}", p_value),
+                    None => format!(
+                        "no p-value, as expected counts below {} make it unreliable",
+                        fairness::MIN_EXPECTED_COUNT
+                    ),
+                };
+                format!(
+                    "{}, rolled {} time(s):\n{}\nChi-square {:.2} with {} degree(s) of freedom, {}.",
+                    die_name(test.faces.iter().map(|&(pip, _, _)| pip)),
+                    test.rolled(),
+                    faces.collect::<Vec<_>>().join("\n"),
+                    test.chi_square,
+                    test.degrees,
+                    p_value
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    if log.skipped() > 0 {
+        out += &format!(
+            "\n{} roll(s) after recommendations to reroll were not logged, as the dice kept are unknown. Enter them with keep instead.",
+            log.skipped()
+        );
+    }
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use crate::rules;
-    use crate::strategy;
+    use std::sync::Arc;
 
     #[test]
     fn test_output_state() {
@@ -123,27 +513,35 @@ mod tests {
         rules.fields[0] = vec![
             rules::SectionRule {
                 name: String::from("Unwinnable 1"),
-                function: |_| 0,
+                kind: rules::FieldKind::UpperFace(1),
+                function: Arc::new(|_| 0),
+                by_rerolls: None,
             },
             rules::SectionRule {
                 name: String::from("Unwinnable 2"),
-                function: |_| 0,
+                kind: rules::FieldKind::UpperFace(2),
+                function: Arc::new(|_| 0),
+                by_rerolls: None,
             },
         ];
         rules.yahtzee_bonus = bonus::FORCED_JOKER;
+        // Yahtzee is looked up by kind
+        rules.fields[LS][0].kind = rules::FieldKind::Yahtzee;
+        let rules = rules.fingerprinted();
 
-        state.used[0] = vec![true, false];
+        state.used[0][0] = vec![1, 0];
 
         let expected_fmt = "You have scored 0 in the upper section and 0 in the lower section.
 Unwinnable 1: used
 Unwinnable 2: unused
 Throw 2: unused
-You have {}scored a Yahtzee.
+You have {}scored a Throw 2.
 You have 2 chip(s) left.";
 
         let mut view_model = ViewModel {
             rules,
             state,
+            score: [0, 0],
             rerolls: 0,
         };
         assert_eq!(
@@ -155,43 +553,313 @@ You have 2 chip(s) left.";
             output_state(&view_model).unwrap(),
             expected_fmt.replace("{}", ""),
         );
+        view_model.rules.carry_over = rules::CarryOverRules {
+            cap: 3,
+            max_per_turn: 4,
+        };
+        view_model.state.pool = 1;
+        assert!(output_state(&view_model)
+            .unwrap()
+            .ends_with("\nYou have 1 reroll(s) banked."));
+    }
+
+    #[test]
+    fn test_parse_state() {
+        let rules = rules::build_rules(false, bonus::FORCED_JOKER);
+        let (state, score) = parse_state(&rules, "65 100 1111110010000 yahtzee").unwrap();
+        assert_eq!(state.upper_scores, vec![63]);
+        assert_eq!(state.used[0][US], vec![1; 6]);
+        assert_eq!(state.used[0][LS], vec![0, 0, 1, 0, 0, 0, 0]);
+        assert!(state.scored_yahtzee);
+        assert_eq!(score, [100, 100]);
+
+        let rules = rules::build_rules(true, bonus::NONE);
+        let used = "0".repeat(rules.fields[US].len() + rules.fields[LS].len());
+        let (state, score) = parse_state(&rules, &format!("3 0 {} 1", used)).unwrap();
+        assert_eq!(state.upper_scores, vec![3]);
+        assert_eq!(state.chips, 1);
+        assert_eq!(score, [3, 0]);
+
+        assert!(parse_state(&rules, &format!("3 0 {} 4", used)).is_err());
+        assert!(parse_state(&rules, &format!("3 0 {} yahtzee", used)).is_err());
+        assert!(parse_state(&rules, "3 0 0000").is_err());
+        assert!(parse_state(&rules, "3 0").is_err());
+
+        let rules = rules::build_triple_rules();
+        let used = "1".repeat(3 * (US_LENGTH + LS_LENGTH));
+        let (state, score) = parse_state(&rules, &format!("70,10,5 300 {}", used)).unwrap();
+        assert_eq!(state.upper_scores, vec![63, 10, 5]);
+        assert!(state.is_game_over(&rules));
+        assert_eq!(score, [(70 + 35) + 2 * 10 + 3 * 5, 300]);
+        assert!(parse_state(&rules, &format!("70 300 {}", used)).is_err());
+
+        let rules = rules::build_balut_rules();
+        let (state, _) = parse_state(&rules, "0 100 4321000").unwrap();
+        assert_eq!(state.used[0][US], vec![4, 3, 2]);
+        assert_eq!(state.used[0][LS], vec![1, 0, 0, 0]);
+        assert_eq!(state.open_fields(&rules).len(), 6);
+        assert_eq!(state.turns_left(&rules), 28 - 10);
+        assert!(parse_state(&rules, "0 100 5000000").is_err());
+
+        let mut rules = rules::build_rules(false, bonus::NONE);
+        rules.forced_order = true;
+        assert!(parse_state(&rules, "0 0 1100000000000").is_ok());
+        assert!(parse_state(&rules, "0 0 1010000000000").is_err());
+
+        let mut rules = rules::build_rules(false, bonus::NONE);
+        assert!(parse_state(&rules, "0 0 1100000000000 pool=1").is_err());
+        rules.carry_over = rules::CarryOverRules {
+            cap: 3,
+            max_per_turn: 4,
+        };
+        let (state, _) = parse_state(&rules, "0 0 1100000000000 pool=3").unwrap();
+        assert_eq!(state.pool, 3);
+        assert!(parse_state(&rules, "0 0 1100000000000 pool=4").is_err());
+        assert!(parse_state(&rules, "0 0 1100000000000 pool=x").is_err());
+    }
+
+    #[test]
+    fn test_output_distribution() {
+        let mut view_model = ViewModel {
+            rules: strategy::tests::very_simple_rules(),
+            state: strategy::tests::very_simple_state(),
+            score: [0, 2],
+            rerolls: REROLLS,
+        };
+        assert_eq!(
+            output_distribution(&view_model).unwrap(),
+            "You can expect to score 2.94 in total, with a standard deviation of 0.24.
+Percentiles: 5%: 2, 25%: 3, 50%: 3, 75%: 3, 95%: 3
+You receive the upper section bonus with a probability of 0.00%."
+        );
+        view_model.rerolls -= 1;
+        assert!(output_distribution(&view_model).is_err());
     }
 
     #[test]
     fn test_recommend() {
         let rules = rules::build_rules(true, bonus::NONE);
         let mut state = strategy::State::new_from_rules(&rules);
-        state.used = [
-            vec![true; rules.fields[US].len()],
-            [[true].repeat(rules.fields[LS].len() - 1), vec![false]].concat(),
-        ];
+        state.used = vec![[
+            vec![1; rules.fields[US].len()],
+            [vec![1; rules.fields[LS].len() - 1], vec![0]].concat(),
+        ]];
         state.chips = 0;
         let view_model = ViewModel {
             rules,
             state,
+            score: [0, 5],
             rerolls: 1,
         };
 
+        // Only compare recommendation, not expectation
+        let recommend_first_line = |input| {
+            let out = recommend(&mut view_model.clone(), &mut None, input).unwrap();
+            String::from(out.lines().next().unwrap())
+        };
         assert_eq!(
-            recommend(&mut view_model.clone(), "11111 0").unwrap(),
+            recommend_first_line("11111 0"),
             String::from("You should reroll altogether.")
         );
         assert_eq!(
-            recommend(&mut view_model.clone(), "11611 0").unwrap(),
+            recommend_first_line("11611 0"),
             String::from("You should keep d6 6.")
         );
         assert_eq!(
-            recommend(&mut view_model.clone(), "11111 9").unwrap(),
+            recommend_first_line("11111 9"),
             String::from("You should keep the d10.")
         );
         assert_eq!(
-            recommend(&mut view_model.clone(), "61116 9").unwrap(),
-            String::from("You should keep the d10 and d6 6, 6.")
+            recommend_first_line("61116 9"),
+            String::from("You should keep d6 6, 6 and the d10.")
+        );
+
+        // Without rerolls, Super Chance must be used, adding to the banked score
+        let mut last_roll = view_model.clone();
+        last_roll.rerolls = 0;
+        assert_eq!(
+            recommend(&mut last_roll, &mut None, "11111 0").unwrap(),
+            String::from(
+                "You should score as Super Chance.\nYou can expect to score 15.00 in total."
+            )
+        );
+
+        // Listing alternatives, but there is only one without rerolls
+        let mut last_roll = view_model.clone();
+        last_roll.rerolls = 0;
+        assert_eq!(
+            alternatives(&last_roll, "3 11111 0").unwrap(),
+            String::from("1. Score as Super Chance: 15.00")
+        );
+        let listed = alternatives(&view_model, "2 11111 0").unwrap();
+        let mut lines = listed.lines();
+        assert!(lines.next().unwrap().starts_with("1. Reroll altogether: "));
+        assert!(lines.next().unwrap().ends_with(" less)"));
+        assert!(lines.next().is_none());
+        assert!(alternatives(&view_model, "11111 0").is_err());
+
+        // Actual actions, Super Chance being the last field
+        let super_chance = rules::build_rules(true, bonus::NONE)
+            .fields
+            .iter()
+            .map(Vec::len)
+            .sum::<usize>();
+        let mut last_roll = view_model.clone();
+        last_roll.rerolls = 0;
+        assert_eq!(
+            score(
+                &mut last_roll,
+                &mut None,
+                &format!("11111 0 / {}", super_chance)
+            )
+            .unwrap(),
+            String::from("That was the best move.\nYou can expect to score 15.00 in total.")
         );
+        let out = score(
+            &mut view_model.clone(),
+            &mut None,
+            &format!("11111 0 / {}", super_chance),
+        )
+        .unwrap();
+        assert!(out.starts_with("You lost "));
+        assert!(out.contains("which was to reroll altogether."));
+        let out = keep(&mut view_model.clone(), &mut None, "11111 0 / -").unwrap();
+        assert!(out.starts_with("That was the best move.\n"));
+        let out = keep(&mut view_model.clone(), &mut None, "61116 9 / - 9").unwrap();
+        assert!(out.starts_with("You lost "));
+        assert!(keep(&mut view_model.clone(), &mut None, "11111 0 / 2").is_err());
+        assert!(keep(&mut view_model.clone(), &mut None, "11111 0").is_err());
+        assert!(score(&mut view_model.clone(), &mut None, "11111 0 / 0").is_err());
 
         // XXX It would be cleaner to test this and the sorting of pips from ViewModel, but with
         // the current design, this is quite some fewer LOC. Might be refactored.
-        assert!(recommend(&mut view_model.clone(), "11111").is_err());
-        assert!(recommend(&mut view_model.clone(), "not numbers").is_err());
+        assert!(recommend(&mut view_model.clone(), &mut None, "11111").is_err());
+        assert!(recommend(&mut view_model.clone(), &mut None, "not numbers").is_err());
+
+        // In forced order, there is no choice of field, but still of stopping
+        let mut forced = view_model.clone();
+        forced.rules.forced_order = true;
+        assert!(recommend(&mut forced.clone(), &mut None, "11111 0")
+            .unwrap()
+            .starts_with("You should reroll altogether.\n"));
+        assert!(recommend(&mut forced, &mut None, "66666 9")
+            .unwrap()
+            .starts_with("You must score as Super Chance.\n"));
+
+        // Playing for a target, the probability is output
+        let mut target_roll = view_model.clone();
+        target_roll.rules.objective = rules::Objective::Target(15);
+        target_roll.state.banked = 5;
+        target_roll.rerolls = 0;
+        assert_eq!(
+            recommend(&mut target_roll, &mut None, "11111 0").unwrap(),
+            String::from(
+                "You should score as Super Chance.\nYou reach 15 in total with a probability of 100.00%."
+            )
+        );
+    }
+    #[test]
+    fn test_parse_hand() {
+        // Repeated pips add up on the faces
+        let fibonacci = Faces::weighted([1, 1, 2, 3, 5, 8].map(|pip| (pip, 1.0)));
+        assert_eq!(die_name([1, 2, 3, 5, 8]), "{1,2,3,5,8} die");
+        let dice = Dice::single(D6, fibonacci.clone(), 5);
+        assert_eq!(
+            parse_hand("11358", &dice).unwrap(),
+            PartialHand([1, 1, 3, 5, 8].map(|pip| (D6, pip)).to_vec())
+        );
+        assert!(parse_hand("1135 8", &dice).is_err());
+
+        // Pips with several digits, separated by commas for their kind only
+        let mut dice = Dice::single(D6, Faces::fair(1, 6), 2);
+        dice.0.push((D10, Faces::fair(5, 12), 2));
+        assert_eq!(die_name(5..=12), "5-12 die");
+        assert_eq!(
+            parse_hand("63 12,5", &dice).unwrap(),
+            PartialHand(vec![(D6, 6), (D6, 3), (D10, 12), (D10, 5)])
+        );
+        assert_eq!(
+            parse_hand("- 10", &dice).unwrap(),
+            PartialHand(vec![(D10, 10)])
+        );
+        assert!(parse_hand("6x", &dice).is_err());
+        assert!(parse_hand("6 12.5", &dice).is_err());
+
+        // Last turn, with only Chance left, keeping the pips above average
+        let mut rules = rules::build_rules(false, bonus::NONE);
+        rules.dice.dice = Dice::single(D6, fibonacci, 5);
+        let rules = rules.fingerprinted();
+        let mut state = strategy::State::new_from_rules(&rules);
+        state.used = vec![[
+            vec![1; US_LENGTH],
+            [vec![1; LS_LENGTH - 1], vec![0]].concat(),
+        ]];
+        let mut view_model = ViewModel {
+            rules,
+            state,
+            score: [0, 0],
+            rerolls: 1,
+        };
+        let out = recommend(&mut view_model, &mut None, "11358").unwrap();
+        assert!(out.starts_with("You should keep {1,2,3,5,8} die 5, 8.\n"));
+    }
+
+    #[test]
+    fn test_fairness() {
+        assert!(fairness(None).is_err());
+        let rules = rules::build_rules(true, bonus::NONE);
+        let mut log = Some(RollLog::new(&rules.dice.dice));
+        assert_eq!(fairness(log.as_ref()).unwrap(), "No rolls logged yet.");
+
+        // Last turn, with only Super Chance left
+        let mut state = strategy::State::new_from_rules(&rules);
+        state.used = vec![[
+            vec![1; rules.fields[US].len()],
+            [vec![1; rules.fields[LS].len() - 1], vec![0]].concat(),
+        ]];
+        state.chips = 0;
+        let mut view_model = ViewModel {
+            rules,
+            state,
+            score: [0, 5],
+            rerolls: 1,
+        };
+        // The d6 kept are not counted again, the others were rerolled
+        keep(&mut view_model, &mut log, "61116 9 / 66 9").unwrap();
+        recommend(&mut view_model, &mut log, "66234 9").unwrap();
+        let out = fairness(log.as_ref()).unwrap();
+        assert!(out.starts_with("d6, rolled 8 time(s):\n1: 3 (expected 1.33)\n"));
+        assert!(out.contains("\n5: 0 (expected 1.33)\n6: 2 (expected 1.33)\n"));
+        assert!(out.contains(
+            "Chi-square 4.00 with 5 degree(s) of freedom, no p-value, as expected counts below 5 make it unreliable."
+        ));
+        assert!(out.contains("\nd10, rolled 1 time(s):\n0: 0 (expected 0.10)\n"));
+        assert!(!out.contains("not logged"));
+    }
+
+    #[test]
+    fn test_fairness_recommend() {
+        let rules = rules::build_rules(false, bonus::NONE);
+        let mut log = Some(RollLog::new(&rules.dice.dice));
+        // Last turn, with only Chance left
+        let mut state = strategy::State::new_from_rules(&rules);
+        state.used = vec![[
+            vec![1; US_LENGTH],
+            [vec![1; LS_LENGTH - 1], vec![0]].concat(),
+        ]];
+        let mut view_model = ViewModel {
+            rules,
+            state,
+            score: [0, 0],
+            rerolls: REROLLS,
+        };
+        // The first roll of the turn is logged, but the dice kept after it are unknown
+        recommend(&mut view_model, &mut log, "12346").unwrap();
+        recommend(&mut view_model, &mut log, "12345").unwrap();
+        let out = fairness(log.as_ref()).unwrap();
+        assert!(out.starts_with("d6, rolled 5 time(s):\n"));
+        assert!(out.ends_with(
+            "\n1 roll(s) after recommendations to reroll were not logged, as the dice kept are unknown. Enter them with keep instead."
+        ));
     }
 }
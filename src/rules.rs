@@ -1,35 +1,87 @@
 use crate::global::*;
 use crate::hands;
+use crate::strategy::{self, ArchFloat};
 use crate::yahtzee_bonus_rules as bonus;
 
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 
 /// Rules for dice used
 #[derive(Clone, Debug, PartialEq)]
 pub struct DiceRules {
-    /// Short name for caching
-    pub short_name: char,
     /// Actual rules
     pub dice: Dice,
 }
 /// Rules for reroll chips used, specify amount per player
 type ChipsRules = Chips;
 /// Function that calculates a score from a hand
-type ScoreFunction = fn(&HandSlice) -> Score;
+pub type ScoreFunction = Arc<dyn Fn(&HandSlice) -> Score + Send + Sync>;
+/// Function that calculates a score from a hand and the rerolls used in the turn
+/// (`0` if scored on the first roll, one more than `REROLLS` if a chip was used)
+pub type RerollsScoreFunction = Arc<dyn Fn(&HandSlice, Rerolls) -> Score + Send + Sync>;
+/// Kind of field, so that rules can refer to fields by what they score rather than by position
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FieldKind {
+    /// Dice showing a pip, e.g. Count and Add Only Fives
+    UpperFace(Pip),
+    /// Identical pips, e.g. 3 for Three of a Kind
+    NOfAKind(Frequency),
+    /// Three and two identical pips
+    FullHouse,
+    /// Other groups of identical pips, sorted, e.g. `[2, 2]` for Two Pairs
+    Groups(Vec<Frequency>),
+    /// Consecutive pips, e.g. 4 for a Small Straight
+    Straight(Frequency),
+    /// Consecutive pips from a lowest to a highest one, e.g. 1 to 5 for Small Straight in Yatzy
+    ExactStraight { lowest: Pip, highest: Pip },
+    /// Five identical pips, making the Yahtzee bonus available once scored
+    Yahtzee,
+    /// Sum of all dice, possibly multiplied, e.g. Super Chance
+    Chance,
+    /// Sum of all dice within bounds, e.g. 10 or less
+    SumThreshold {
+        min: Option<Score>,
+        max: Option<Score>,
+    },
+}
+
 /// Rule for field on score card
 #[derive(Clone)]
 pub struct SectionRule {
     /// Name of field for user interaction
     pub name: String,
+    /// Kind of field, see `FieldKind`
+    pub kind: FieldKind,
     /// Function from hand to score
     pub function: ScoreFunction,
+    /// Function from hand and rerolls used to score, replacing `function` if present,
+    /// e.g. for bonuses when scoring on the first roll
+    pub by_rerolls: Option<RerollsScoreFunction>,
+}
+
+impl SectionRule {
+    /// Calculate score of a hand
+    /// # Arguments
+    /// * `hand` - sorted
+    /// * `rerolls_used` - rerolls used in the turn, see `RerollsScoreFunction`
+    pub fn score(&self, hand: &HandSlice, rerolls_used: Rerolls) -> Score {
+        match &self.by_rerolls {
+            Some(function) => function(hand, rerolls_used),
+            None => (self.function)(hand),
+        }
+    }
 }
 /// Rules in a section
 type SectionRules = Vec<SectionRule>;
 /// Rules for allowed fields (upper and lower section)
 type FieldsRules = [SectionRules; 2];
 /// Rule for upper section bonus
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct USBonusRules {
     /// Score required to receive upper section bonus (63 in regular Yahtzee)
     pub threshold: Score,
@@ -37,6 +89,116 @@ pub struct USBonusRules {
     pub bonus: Score,
 }
 
+impl USBonusRules {
+    /// No upper section bonus, so that the upper section score need not be tracked
+    pub const NONE: Self = USBonusRules {
+        threshold: 0,
+        bonus: 0,
+    };
+}
+
+impl Default for USBonusRules {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Rules for carrying over rerolls not used in a turn to later turns
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CarryOverRules {
+    /// Rerolls that can be banked at most
+    pub cap: Rerolls,
+    /// Rerolls that can be used in a turn at most, including banked ones, at least the rerolls of
+    /// the game unless there is no cap
+    pub max_per_turn: Rerolls,
+}
+
+impl CarryOverRules {
+    /// No rerolls carried over, so that they need not be tracked
+    pub const NONE: Self = CarryOverRules {
+        cap: 0,
+        max_per_turn: REROLLS,
+    };
+}
+
+impl Default for CarryOverRules {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Utility of final scores, indexed by score, the last one also applying to all higher scores
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "Vec<ArchFloat>", into = "Vec<ArchFloat>")]
+pub struct Utility(pub Arc<[ArchFloat]>);
+
+impl From<Vec<ArchFloat>> for Utility {
+    fn from(utility: Vec<ArchFloat>) -> Self {
+        Utility(utility.into())
+    }
+}
+
+impl From<Utility> for Vec<ArchFloat> {
+    fn from(utility: Utility) -> Self {
+        utility.0.to_vec()
+    }
+}
+
+// Compare bitwise, so that utilities can be used in cache keys
+impl PartialEq for Utility {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Utility {}
+
+impl PartialOrd for Utility {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Utility {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let bits = |utility: &Self| utility.0.iter().map(|u| u.to_bits()).collect::<Vec<_>>();
+        bits(self).cmp(&bits(other))
+    }
+}
+
+impl Hash for Utility {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for u in self.0.iter() {
+            u.to_bits().hash(state);
+        }
+    }
+}
+
+/// Objective to play for - not part of the game itself, but what the strategy optimizes
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Objective {
+    /// Maximize expectation value of final score
+    Expectation,
+    /// Maximize probability of final score reaching at least the target
+    Target(Score),
+    /// Maximize probability to win against an opponent, counting ties as half a win, or more
+    /// generally, maximize expectation value of the utility of final score
+    Win(Utility),
+}
+
+impl Objective {
+    /// Cap on points banked, as far as they matter for the objective
+    /// # Returns
+    /// Cap, or `None` if points banked do not matter at all
+    pub fn banked_cap(&self) -> Option<Score> {
+        match self {
+            Objective::Expectation => None,
+            Objective::Target(target) => Some(*target),
+            Objective::Win(utility) => Some((utility.0.len() - 1) as Score),
+        }
+    }
+}
+
 /// Rules for a game
 #[derive(Clone)]
 pub struct Rules {
@@ -46,54 +208,184 @@ pub struct Rules {
     pub fields: FieldsRules,
     pub us_bonus: USBonusRules,
     pub yahtzee_bonus: bonus::Rules,
+    pub objective: Objective,
+    /// Multipliers of score card columns, `[1]` for a single column
+    pub columns: Vec<Score>,
+    /// Rerolls per turn, `global::REROLLS` for most games
+    pub rerolls: Rerolls,
+    /// Times each field is used in a column, 1 for most games
+    pub capacity: Uses,
+    /// Fields must be used in card order, top to bottom, see `strategy::State::open_fields`
+    pub forced_order: bool,
+    /// Rerolls left at the end of a turn are banked, see `strategy::State::turn_rerolls`
+    pub carry_over: CarryOverRules,
+    /// Fingerprint of the dice and the fields, see `Rules::fingerprinted`
+    pub fingerprint: Fingerprint,
+}
+
+/// Identification of rules for caching
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub short_name: char,
+    /// Short name of the Yahtzee bonus rules, which the short name of Extreme does not include
+    pub yahtzee_bonus: char,
+    pub objective: Objective,
+    pub rerolls: Rerolls,
+    pub forced_order: bool,
+    /// Rerolls left at the end of a turn are banked, see `strategy::State::turn_rerolls`
+    pub carry_over: CarryOverRules,
+    pub chips: ChipsRules,
+    pub us_bonus: USBonusRules,
+    pub columns: Vec<Score>,
+    pub capacity: Uses,
+    /// Fingerprint of the rest of the game, the dice and the fields with their scores, see
+    /// `Rules::fingerprinted`
+    pub game: Fingerprint,
+}
+
+/// Hash of rules that is stable across runs, so that it can be stored, see `crate::caching`
+pub type Fingerprint = u64;
+
+/// Fingerprint of a value, e.g. dice
+pub fn fingerprint(value: &impl Hash) -> Fingerprint {
+    // Created with fixed keys, unlike the hasher of a `HashMap`
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Rules {
+    /// Key to identify these rules in caches - the short name only identifies the game, but not
+    /// the objective, the rerolls or the mode of play, and the game may be configured differently
+    pub fn cache_key(&self) -> CacheKey {
+        CacheKey {
+            short_name: self.short_name,
+            yahtzee_bonus: self.yahtzee_bonus.short_name,
+            objective: self.objective.clone(),
+            rerolls: self.rerolls,
+            forced_order: self.forced_order,
+            carry_over: self.carry_over.clone(),
+            chips: self.chips,
+            us_bonus: self.us_bonus.clone(),
+            columns: self.columns.clone(),
+            capacity: self.capacity,
+            game: self.fingerprint,
+        }
+    }
+
+    /// Compute the fingerprint of the dice and the fields with their scores, see `CacheKey::game`
+    /// Score functions cannot be compared, so they are evaluated for all hands and, if it matters,
+    /// all rerolls used. This is done once the rules are built, and must be done again whenever
+    /// their dice or fields change.
+    pub fn fingerprinted(mut self) -> Self {
+        let hands = self.full_hands();
+        // Including those drawn from the pool, see `carry_over`
+        let max_rerolls = self.rerolls.max(self.carry_over.max_per_turn);
+        let mut hasher = DefaultHasher::new();
+        self.dice.dice.hash(&mut hasher);
+        for section in &self.fields {
+            section.len().hash(&mut hasher);
+            for field in section {
+                field.name.hash(&mut hasher);
+                field.kind.hash(&mut hasher);
+                for hand in &hands {
+                    match &field.by_rerolls {
+                        // Rerolls used go up to one more than the rerolls, using a chip
+                        Some(function) => (0..=max_rerolls + 1).for_each(|rerolls_used| {
+                            function(hand, rerolls_used).hash(&mut hasher)
+                        }),
+                        None => (field.function)(hand).hash(&mut hasher),
+                    }
+                }
+            }
+        }
+        self.fingerprint = hasher.finish();
+        self
+    }
+
+    /// Pips of all full hands of the dice, sorted, without distinguishing kinds of dice
+    pub fn full_hands(&self) -> Vec<Hand> {
+        let empty = PartialHand(Vec::new());
+        let full_hands = strategy::probability_to_roll(empty, &self.dice).table;
+        let mut hands: Vec<Hand> = full_hands
+            .into_keys()
+            .map(|hand| {
+                let mut pips: Hand = hand.0.iter().map(|&(_, pip)| pip).collect();
+                pips.sort_unstable();
+                pips
+            })
+            .collect();
+        hands.sort_unstable();
+        hands.dedup();
+        hands
+    }
+
+    /// Position of the Yahtzee field in the lower section, which makes the Yahtzee bonus available
+    /// once scored
+    pub fn yahtzee_field(&self) -> Option<Field> {
+        let mut fields = self.fields[LS].iter();
+        fields.position(|field| field.kind == FieldKind::Yahtzee)
+    }
+
+    /// Position of the upper section field for a pip, e.g. Count and Add Only Fives
+    pub fn upper_field(&self, pip: Pip) -> Option<Field> {
+        let mut fields = self.fields[US].iter();
+        fields.position(|field| field.kind == FieldKind::UpperFace(pip))
+    }
+
+    /// Decide whether stopping depends on the rerolls left in the turn, i.e. whether any score
+    /// depends on the rerolls used, or rerolls left are carried over
+    pub fn depends_on_rerolls(&self) -> bool {
+        let mut fields = self.fields.iter().flatten();
+        self.carry_over.cap > 0 || fields.any(|field| field.by_rerolls.is_some())
+    }
+
+    /// Rules for playing a single column without multiplier, e.g. to estimate games with
+    /// several columns
+    /// Chips and rerolls carried over are shared by all columns, so there are none. The short name
+    /// is the upper-case one of these rules, which is reserved for this purpose.
+    pub fn column_rules(&self) -> Rules {
+        Rules {
+            short_name: self.short_name.to_ascii_uppercase(),
+            chips: 0,
+            carry_over: CarryOverRules::NONE,
+            objective: Objective::Expectation,
+            columns: vec![1],
+            ..self.clone()
+        }
+    }
 }
 
 impl fmt::Debug for Rules {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Rules")
             .field("short_name", &self.short_name)
+            .field("objective", &self.objective)
+            .field("rerolls", &self.rerolls)
+            .field("forced_order", &self.forced_order)
+            .field("carry_over", &self.carry_over)
             .finish()
     }
 }
 
 impl PartialEq for Rules {
     fn eq(&self, other: &Self) -> bool {
-        self.short_name == other.short_name
+        self.cache_key() == other.cache_key()
     }
 }
 
 /// Build upper section fields rules
 fn build_upper_section_rules() -> SectionRules {
-    // Manual unroll because a closure would capture the running variable and could thus not be
-    // coerced into a function pointer (and that is with `move`).
-    // If you are reading this and know of a better way, please tell me.
-
-    vec![
-        SectionRule {
-            name: String::from("Count and Add Only Aces"),
-            function: |hand| hands::generic_upper_section(1, hand),
-        },
-        SectionRule {
-            name: String::from("Count and Add Only Twos"),
-            function: |hand| hands::generic_upper_section(2, hand),
-        },
-        SectionRule {
-            name: String::from("Count and Add Only Threes"),
-            function: |hand| hands::generic_upper_section(3, hand),
-        },
-        SectionRule {
-            name: String::from("Count and Add Only Fours"),
-            function: |hand| hands::generic_upper_section(4, hand),
-        },
-        SectionRule {
-            name: String::from("Count and Add Only Fives"),
-            function: |hand| hands::generic_upper_section(5, hand),
-        },
-        SectionRule {
-            name: String::from("Count and Add Only Sixes"),
-            function: |hand| hands::generic_upper_section(6, hand),
-        },
-    ]
+    let names = ["Aces", "Twos", "Threes", "Fours", "Fives", "Sixes"];
+    (1..)
+        .zip(names)
+        .map(|(pip, name)| SectionRule {
+            name: format!("Count and Add Only {}", name),
+            kind: FieldKind::UpperFace(pip),
+            function: Arc::new(move |hand| hands::generic_upper_section(pip, hand)),
+            by_rerolls: None,
+        })
+        .collect()
 }
 
 /// Build lower section fields rules
@@ -103,81 +395,127 @@ fn build_lower_section_rules(extreme: bool) -> SectionRules {
     let mut ls_fields_rules: SectionRules = vec![
         SectionRule {
             name: String::from("Three of a Kind"),
-            function: |hand| hands::generic_identical(vec![3], hands::total, hand),
+            kind: FieldKind::NOfAKind(3),
+            function: Arc::new(|hand| hands::generic_identical(vec![3], hands::total, hand)),
+            by_rerolls: None,
         },
         SectionRule {
             name: String::from("Four of a Kind"),
-            function: |hand| hands::generic_identical(vec![4], hands::total, hand),
+            kind: FieldKind::NOfAKind(4),
+            function: Arc::new(|hand| hands::generic_identical(vec![4], hands::total, hand)),
+            by_rerolls: None,
         },
     ];
     if extreme {
         ls_fields_rules.push(SectionRule {
             name: String::from("Two Pairs"),
-            function: |hand| hands::generic_identical(vec![2, 2], hands::total, hand),
+            kind: FieldKind::Groups(vec![2, 2]),
+            function: Arc::new(|hand| hands::generic_identical(vec![2, 2], hands::total, hand)),
+            by_rerolls: None,
         });
         ls_fields_rules.push(SectionRule {
             name: String::from("Three Pairs"),
-            function: |hand| hands::generic_identical(vec![2, 2, 2], |_| 35, hand),
+            kind: FieldKind::Groups(vec![2, 2, 2]),
+            function: Arc::new(|hand| {
+                hands::generic_identical(vec![2, 2, 2], |_| THREE_PAIRS_SCORE, hand)
+            }),
+            by_rerolls: None,
         });
         ls_fields_rules.push(SectionRule {
             name: String::from("Two Triples"),
-            function: |hand| hands::generic_identical(vec![3, 3], |_| 45, hand),
+            kind: FieldKind::Groups(vec![3, 3]),
+            function: Arc::new(|hand| {
+                hands::generic_identical(vec![3, 3], |_| TWO_TRIPLES_SCORE, hand)
+            }),
+            by_rerolls: None,
         });
     }
 
     ls_fields_rules.push(SectionRule {
         name: String::from("Full House"),
-        function: |hand| hands::generic_identical(vec![2, 3], |_| FULL_HOUSE_SCORE, hand),
+        kind: FieldKind::FullHouse,
+        function: Arc::new(|hand| hands::generic_identical(vec![2, 3], |_| FULL_HOUSE_SCORE, hand)),
+        by_rerolls: None,
     });
     if extreme {
         ls_fields_rules.push(SectionRule {
             name: String::from("Grand Full House"),
-            function: |hand| hands::generic_identical(vec![2, 4], |_| 45, hand),
+            kind: FieldKind::Groups(vec![2, 4]),
+            function: Arc::new(|hand| {
+                hands::generic_identical(vec![2, 4], |_| GRAND_FULL_HOUSE_SCORE, hand)
+            }),
+            by_rerolls: None,
         });
     }
 
     ls_fields_rules.push(SectionRule {
         name: String::from("Small Straight"),
-        function: |hand| hands::generic_straight(4, SMALL_STRAIGHT_SCORE, hand),
+        kind: FieldKind::Straight(4),
+        function: Arc::new(|hand| hands::generic_straight(4, SMALL_STRAIGHT_SCORE, hand)),
+        by_rerolls: None,
     });
     ls_fields_rules.push(SectionRule {
         name: String::from("Large Straight"),
-        function: |hand| hands::generic_straight(5, LARGE_STRAIGHT_SCORE, hand),
+        kind: FieldKind::Straight(5),
+        function: Arc::new(|hand| hands::generic_straight(5, LARGE_STRAIGHT_SCORE, hand)),
+        by_rerolls: None,
     });
     if extreme {
         ls_fields_rules.push(SectionRule {
             name: String::from("Highway"),
-            function: |hand| hands::generic_straight(6, 50, hand),
+            kind: FieldKind::Straight(6),
+            function: Arc::new(|hand| hands::generic_straight(6, HIGHWAY_SCORE, hand)),
+            by_rerolls: None,
         });
     }
 
     ls_fields_rules.push(SectionRule {
         name: String::from("Yahtzee"),
-        function: |hand| hands::generic_identical(vec![5], |_| YAHTZEE_SCORE, hand),
+        kind: FieldKind::Yahtzee,
+        function: Arc::new(|hand| hands::generic_identical(vec![5], |_| YAHTZEE_SCORE, hand)),
+        by_rerolls: None,
     });
     if extreme {
         ls_fields_rules.push(SectionRule {
             name: String::from("Yahtzee Extreme"),
-            function: |hand| hands::generic_identical(vec![6], |_| 75, hand),
+            kind: FieldKind::NOfAKind(6),
+            function: Arc::new(|hand| {
+                hands::generic_identical(vec![6], |_| YAHTZEE_EXTREME_SCORE, hand)
+            }),
+            by_rerolls: None,
         });
         ls_fields_rules.push(SectionRule {
             name: String::from("10 or less"),
-            function: |hand| if hands::total(hand) <= 10 { 40 } else { 0 },
+            kind: FieldKind::SumThreshold {
+                min: None,
+                max: Some(10),
+            },
+            function: Arc::new(|hand| if hands::total(hand) <= 10 { 40 } else { 0 }),
+            by_rerolls: None,
         });
         ls_fields_rules.push(SectionRule {
             name: String::from("33 or more"),
-            function: |hand| if hands::total(hand) >= 33 { 40 } else { 0 },
+            kind: FieldKind::SumThreshold {
+                min: Some(33),
+                max: None,
+            },
+            function: Arc::new(|hand| if hands::total(hand) >= 33 { 40 } else { 0 }),
+            by_rerolls: None,
         });
     }
 
     ls_fields_rules.push(SectionRule {
         name: String::from("Chance"),
-        function: hands::total,
+        kind: FieldKind::Chance,
+        function: Arc::new(hands::total),
+        by_rerolls: None,
     });
     if extreme {
         ls_fields_rules.push(SectionRule {
             name: String::from("Super Chance"),
-            function: |hand| 2 * hands::total(hand),
+            kind: FieldKind::Chance,
+            function: Arc::new(|hand| 2 * hands::total(hand)),
+            by_rerolls: None,
         });
     }
 
@@ -186,12 +524,8 @@ fn build_lower_section_rules(extreme: bool) -> SectionRules {
 
 /// Build rules for Yahtzee
 /// # Arguments
-/// * `extreme` - build for Extreme variant
+/// * `extreme` - build for Extreme variant, whose short name is the same for all bonus rules
 pub fn build_rules(extreme: bool, yahtzee_bonus: bonus::Rules) -> Rules {
-    if extreme && yahtzee_bonus != bonus::NONE {
-        panic!("Yahtzee Extreme with non-null bonus rules is undefined");
-    }
-
     let short_name = match extreme {
         false => yahtzee_bonus.short_name,
         true => 'f',
@@ -199,13 +533,11 @@ pub fn build_rules(extreme: bool, yahtzee_bonus: bonus::Rules) -> Rules {
 
     // Five d6
     let mut dice = DiceRules {
-        short_name: 'a',
-        dice: Dice(vec![(D6, 5)]),
+        dice: Dice::single(D6, Faces::fair(1, 6), 5),
     };
     if extreme {
-        dice.short_name = 'b';
         // One d10, starting at 0
-        dice.dice.0.push((D10, 1));
+        dice.dice.0.push((D10, Faces::fair(0, 9), 1));
     }
     let chips = if extreme { 3 } else { 0 };
 
@@ -230,7 +562,452 @@ pub fn build_rules(extreme: bool, yahtzee_bonus: bonus::Rules) -> Rules {
         fields: [us_fields_rules, ls_fields_rules],
         us_bonus,
         yahtzee_bonus,
+        objective: Objective::Expectation,
+        columns: vec![1],
+        rerolls: REROLLS,
+        capacity: 1,
+        forced_order: false,
+        carry_over: CarryOverRules::NONE,
+        fingerprint: 0,
+    }
+    .fingerprinted()
+}
+
+/// Build lower section fields rules for Scandinavian Yatzy
+/// Only the dice in groups of identical pips count for Pairs and Kinds, straights are fixed
+/// # Arguments
+/// * `maxi` - build for Maxi Yatzy
+fn build_yatzy_lower_section_rules(maxi: bool) -> SectionRules {
+    let mut ls_fields_rules: SectionRules = vec![
+        SectionRule {
+            name: String::from("One Pair"),
+            kind: FieldKind::NOfAKind(2),
+            function: Arc::new(|hand| hands::highest_identical(&[2], hand)),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Two Pairs"),
+            kind: FieldKind::Groups(vec![2, 2]),
+            function: Arc::new(|hand| hands::highest_identical(&[2, 2], hand)),
+            by_rerolls: None,
+        },
+    ];
+    if maxi {
+        ls_fields_rules.push(SectionRule {
+            name: String::from("Three Pairs"),
+            kind: FieldKind::Groups(vec![2, 2, 2]),
+            function: Arc::new(|hand| hands::generic_identical(vec![2, 2, 2], hands::total, hand)),
+            by_rerolls: None,
+        });
+    }
+
+    ls_fields_rules.push(SectionRule {
+        name: String::from("Three of a Kind"),
+        kind: FieldKind::NOfAKind(3),
+        function: Arc::new(|hand| hands::highest_identical(&[3], hand)),
+        by_rerolls: None,
+    });
+    ls_fields_rules.push(SectionRule {
+        name: String::from("Four of a Kind"),
+        kind: FieldKind::NOfAKind(4),
+        function: Arc::new(|hand| hands::highest_identical(&[4], hand)),
+        by_rerolls: None,
+    });
+    if maxi {
+        ls_fields_rules.push(SectionRule {
+            name: String::from("Five of a Kind"),
+            kind: FieldKind::NOfAKind(5),
+            function: Arc::new(|hand| hands::highest_identical(&[5], hand)),
+            by_rerolls: None,
+        });
+    }
+
+    ls_fields_rules.push(SectionRule {
+        name: String::from("Small Straight"),
+        kind: FieldKind::ExactStraight {
+            lowest: 1,
+            highest: 5,
+        },
+        function: Arc::new(|hand| hands::exact_straight(1, 5, 15, hand)),
+        by_rerolls: None,
+    });
+    ls_fields_rules.push(SectionRule {
+        name: String::from("Large Straight"),
+        kind: FieldKind::ExactStraight {
+            lowest: 2,
+            highest: 6,
+        },
+        function: Arc::new(|hand| hands::exact_straight(2, 6, 20, hand)),
+        by_rerolls: None,
+    });
+    if maxi {
+        ls_fields_rules.push(SectionRule {
+            name: String::from("Full Straight"),
+            kind: FieldKind::Straight(6),
+            function: Arc::new(|hand| hands::generic_straight(6, 21, hand)),
+            by_rerolls: None,
+        });
+    }
+
+    ls_fields_rules.push(SectionRule {
+        name: String::from("Full House"),
+        kind: FieldKind::FullHouse,
+        function: Arc::new(|hand| hands::highest_identical(&[3, 2], hand)),
+        by_rerolls: None,
+    });
+    if maxi {
+        ls_fields_rules.push(SectionRule {
+            name: String::from("Castle"),
+            kind: FieldKind::Groups(vec![3, 3]),
+            function: Arc::new(|hand| hands::generic_identical(vec![3, 3], hands::total, hand)),
+            by_rerolls: None,
+        });
+        ls_fields_rules.push(SectionRule {
+            name: String::from("Tower"),
+            kind: FieldKind::Groups(vec![2, 4]),
+            function: Arc::new(|hand| hands::generic_identical(vec![2, 4], hands::total, hand)),
+            by_rerolls: None,
+        });
+    }
+
+    ls_fields_rules.push(SectionRule {
+        name: String::from("Chance"),
+        kind: FieldKind::Chance,
+        function: Arc::new(hands::total),
+        by_rerolls: None,
+    });
+    ls_fields_rules.push(match maxi {
+        true => SectionRule {
+            name: String::from("Maxi Yatzy"),
+            kind: FieldKind::NOfAKind(6),
+            function: Arc::new(|hand| hands::generic_identical(vec![6], |_| 100, hand)),
+            by_rerolls: None,
+        },
+        _ => SectionRule {
+            name: String::from("Yatzy"),
+            kind: FieldKind::Yahtzee,
+            function: Arc::new(|hand| hands::generic_identical(vec![5], |_| YAHTZEE_SCORE, hand)),
+            by_rerolls: None,
+        },
+    });
+
+    ls_fields_rules
+}
+
+/// Build rules for Scandinavian Yatzy, without Yahtzee bonus
+/// # Arguments
+/// * `maxi` - build for Maxi Yatzy
+pub fn build_yatzy_rules(maxi: bool) -> Rules {
+    // Five d6, or six for Maxi Yatzy
+    let dice = match maxi {
+        true => DiceRules {
+            dice: Dice::single(D6, Faces::fair(1, 6), 6),
+        },
+        _ => DiceRules {
+            dice: Dice::single(D6, Faces::fair(1, 6), 5),
+        },
+    };
+
+    let us_bonus = match maxi {
+        true => USBonusRules {
+            threshold: 84,
+            bonus: 100,
+        },
+        _ => USBonusRules {
+            threshold: 63,
+            bonus: 50,
+        },
+    };
+
+    Rules {
+        short_name: if maxi { 'j' } else { 'i' },
+        dice,
+        chips: 0,
+        fields: [
+            build_upper_section_rules(),
+            build_yatzy_lower_section_rules(maxi),
+        ],
+        us_bonus,
+        yahtzee_bonus: bonus::NONE,
+        objective: Objective::Expectation,
+        columns: vec![1],
+        rerolls: REROLLS,
+        capacity: 1,
+        forced_order: false,
+        carry_over: CarryOverRules::NONE,
+        fingerprint: 0,
     }
+    .fingerprinted()
+}
+
+/// Build Generala field, scoring more if served, i.e. made on the first roll
+/// # Arguments
+/// * `name` - name of field
+/// * `kind` - kind of field
+/// * `function` - function from hand to score if not served
+/// * `served` - score if served
+fn build_generala_field(
+    name: &str,
+    kind: FieldKind,
+    function: ScoreFunction,
+    served: Score,
+) -> SectionRule {
+    let not_served = function.clone();
+    SectionRule {
+        name: String::from(name),
+        kind,
+        function,
+        by_rerolls: Some(Arc::new(move |hand, rerolls_used| {
+            match (not_served(hand), rerolls_used) {
+                (0, _) => 0,
+                (_, 0) => served,
+                (score, _) => score,
+            }
+        })),
+    }
+}
+
+/// Build rules for Generala
+/// A served Generala, which traditionally wins the game outright, scores 100 instead. Every
+/// further Generala scores a Double Generala bonus, see `bonus::DOUBLE_GENERALA`, and there is no
+/// upper section bonus.
+pub fn build_generala_rules() -> Rules {
+    let ls_fields_rules = vec![
+        // 3-4-5-6-1 also counts, with the ace following the six
+        build_generala_field(
+            "Escalera",
+            FieldKind::Straight(5),
+            Arc::new(|hand| match hand {
+                [1, 3, 4, 5, 6] => 20,
+                _ => hands::generic_straight(5, 20, hand),
+            }),
+            25,
+        ),
+        build_generala_field(
+            "Full",
+            FieldKind::FullHouse,
+            Arc::new(|hand| hands::generic_identical(vec![2, 3], |_| 30, hand)),
+            35,
+        ),
+        build_generala_field(
+            "Poker",
+            FieldKind::NOfAKind(4),
+            Arc::new(|hand| hands::generic_identical(vec![4], |_| 40, hand)),
+            45,
+        ),
+        build_generala_field(
+            "Generala",
+            FieldKind::Yahtzee,
+            Arc::new(|hand| hands::generic_identical(vec![5], |_| 50, hand)),
+            100,
+        ),
+    ];
+
+    Rules {
+        short_name: 'k',
+        // Five d6
+        dice: DiceRules {
+            dice: Dice::single(D6, Faces::fair(1, 6), 5),
+        },
+        chips: 0,
+        fields: [build_upper_section_rules(), ls_fields_rules],
+        us_bonus: USBonusRules::NONE,
+        yahtzee_bonus: bonus::DOUBLE_GENERALA,
+        objective: Objective::Expectation,
+        columns: vec![1],
+        rerolls: REROLLS,
+        capacity: 1,
+        forced_order: false,
+        carry_over: CarryOverRules::NONE,
+        fingerprint: 0,
+    }
+    .fingerprinted()
+}
+
+/// Build rules for the original 1938 Yacht
+/// There are no bonuses, and Four of a Kind scores only the four dice
+pub fn build_yacht_rules() -> Rules {
+    let ls_fields_rules = vec![
+        SectionRule {
+            name: String::from("Full House"),
+            kind: FieldKind::FullHouse,
+            function: Arc::new(|hand| hands::generic_identical(vec![2, 3], hands::total, hand)),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Four of a Kind"),
+            kind: FieldKind::NOfAKind(4),
+            function: Arc::new(|hand| hands::highest_identical(&[4], hand)),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Little Straight"),
+            kind: FieldKind::ExactStraight {
+                lowest: 1,
+                highest: 5,
+            },
+            function: Arc::new(|hand| hands::exact_straight(1, 5, 30, hand)),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Big Straight"),
+            kind: FieldKind::ExactStraight {
+                lowest: 2,
+                highest: 6,
+            },
+            function: Arc::new(|hand| hands::exact_straight(2, 6, 30, hand)),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Choice"),
+            kind: FieldKind::Chance,
+            function: Arc::new(hands::total),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Yacht"),
+            kind: FieldKind::Yahtzee,
+            function: Arc::new(|hand| hands::generic_identical(vec![5], |_| YAHTZEE_SCORE, hand)),
+            by_rerolls: None,
+        },
+    ];
+
+    Rules {
+        short_name: 'l',
+        // Five d6
+        dice: DiceRules {
+            dice: Dice::single(D6, Faces::fair(1, 6), 5),
+        },
+        chips: 0,
+        fields: [build_upper_section_rules(), ls_fields_rules],
+        us_bonus: USBonusRules::NONE,
+        yahtzee_bonus: bonus::NONE,
+        objective: Objective::Expectation,
+        columns: vec![1],
+        rerolls: REROLLS,
+        capacity: 1,
+        forced_order: false,
+        carry_over: CarryOverRules::NONE,
+        fingerprint: 0,
+    }
+    .fingerprinted()
+}
+
+/// Build rules for Triple Yahtzee
+/// Three columns of regular Yahtzee are scored ×1, ×2 and ×3, each with its own upper section
+/// bonus, and there is no Yahtzee bonus
+pub fn build_triple_rules() -> Rules {
+    Rules {
+        short_name: 'm',
+        columns: vec![1, 2, 3],
+        ..build_rules(false, bonus::NONE)
+    }
+}
+
+/// Score a point of Balut is worth, the step between conversions of the grand total into points
+const BALUT_POINT: Score = 50;
+
+/// Build rules for Balut
+/// Each of the seven fields is used four times. Balut converts the totals of fields into points,
+/// e.g. 2 for 65 or more in Fives or 4 for four straights, and 2 for each Balut, and the grand
+/// total into further points, one for every 50. The state tracks how often fields were used but
+/// not their totals, so the strategy maximizes the score with each point worth `BALUT_POINT`,
+/// approximating the conversions it cannot track:
+/// * Fours, Fives and Sixes award their 2 points each as an upper section bonus for reaching
+///   52 + 65 + 78 = 195 in total
+/// * Straight, Full House and Choice award their points spread over their four uses, the latter
+///   for each Choice of at least 25, a quarter of the 100 required
+/// * Balut awards its points exactly
+pub fn build_balut_rules() -> Rules {
+    let spread = |points: Score| points * BALUT_POINT / 4;
+    let (straight, full_house, choice) = (spread(4), spread(3), spread(2));
+    let balut = 2 * BALUT_POINT;
+
+    let us_fields_rules = [(4, "Fours"), (5, "Fives"), (6, "Sixes")]
+        .into_iter()
+        .map(|(pip, name)| SectionRule {
+            name: String::from(name),
+            kind: FieldKind::UpperFace(pip),
+            function: Arc::new(move |hand| hands::generic_upper_section(pip, hand)),
+            by_rerolls: None,
+        })
+        .collect();
+    let ls_fields_rules = vec![
+        // 1-2-3-4-5 or 2-3-4-5-6, scoring the sum of all dice
+        SectionRule {
+            name: String::from("Straight"),
+            kind: FieldKind::Straight(5),
+            function: Arc::new(move |hand| match hands::generic_straight(5, 1, hand) {
+                0 => 0,
+                _ => hands::total(hand) + straight,
+            }),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Full House"),
+            kind: FieldKind::FullHouse,
+            function: Arc::new(move |hand| {
+                let score = |hand: &HandSlice| hands::total(hand) + full_house;
+                hands::generic_identical(vec![2, 3], score, hand)
+            }),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Choice"),
+            kind: FieldKind::Chance,
+            function: Arc::new(move |hand| match hands::total(hand) {
+                total if total >= 100 / 4 => total + choice,
+                total => total,
+            }),
+            by_rerolls: None,
+        },
+        SectionRule {
+            name: String::from("Balut"),
+            kind: FieldKind::Yahtzee,
+            function: Arc::new(move |hand| {
+                let score = |hand: &HandSlice| 20 + hands::total(hand) + balut;
+                hands::generic_identical(vec![5], score, hand)
+            }),
+            by_rerolls: None,
+        },
+    ];
+
+    Rules {
+        short_name: 'n',
+        // Five d6
+        dice: DiceRules {
+            dice: Dice::single(D6, Faces::fair(1, 6), 5),
+        },
+        chips: 0,
+        fields: [us_fields_rules, ls_fields_rules],
+        us_bonus: USBonusRules {
+            threshold: 52 + 65 + 78,
+            bonus: 3 * 2 * BALUT_POINT,
+        },
+        yahtzee_bonus: bonus::NONE,
+        objective: Objective::Expectation,
+        columns: vec![1],
+        rerolls: REROLLS,
+        capacity: 4,
+        forced_order: false,
+        carry_over: CarryOverRules::NONE,
+        fingerprint: 0,
+    }
+    .fingerprinted()
+}
+
+/// Build rules for all built-in games, e.g. for pre-caching
+pub fn build_all_rules() -> Vec<Rules> {
+    let regular = bonus::ALL_VARIANTS.iter().cloned();
+    let mut all: Vec<_> = regular.map(|variant| build_rules(false, variant)).collect();
+    all.push(build_rules(true, bonus::NONE));
+    all.push(build_yatzy_rules(false));
+    all.push(build_yatzy_rules(true));
+    all.push(build_generala_rules());
+    all.push(build_yacht_rules());
+    all.push(build_triple_rules());
+    all.push(build_balut_rules());
+    all
 }
 
 #[cfg(test)]
@@ -247,8 +1024,7 @@ mod tests {
             assert_eq!(
                 rules.dice,
                 DiceRules {
-                    short_name: 'a',
-                    dice: Dice(vec![((1, 6), 5)]),
+                    dice: Dice::single(D6, Faces::fair(1, 6), 5),
                 }
             );
             assert_eq!(rules.chips, 0);
@@ -301,8 +1077,10 @@ mod tests {
         assert_eq!(
             rules.dice,
             DiceRules {
-                short_name: 'b',
-                dice: Dice(vec![((1, 6), 5), ((0, 9), 1)]),
+                dice: Dice(vec![
+                    (D6, Faces::fair(1, 6), 5),
+                    (D10, Faces::fair(0, 9), 1)
+                ]),
             },
         );
         assert_eq!(rules.chips, 3);
@@ -353,5 +1131,267 @@ mod tests {
             }
         );
         assert_eq!(rules.yahtzee_bonus.short_name, bonus::NONE.short_name);
+        assert_eq!(rules.yahtzee_field(), Some(10));
+
+        // Bonus rules share the short name, but not caches
+        let forced = build_rules(true, bonus::FORCED_JOKER);
+        assert_eq!(forced.short_name, 'f');
+        assert_ne!(forced.cache_key(), rules.cache_key());
+    }
+
+    #[test]
+    fn test_yatzy_rules() {
+        let rules = build_yatzy_rules(false);
+
+        assert_eq!(rules.short_name, 'i');
+        assert_eq!(rules.dice, build_rules(false, bonus::NONE).dice);
+        assert_eq!(rules.chips, 0);
+
+        assert_eq!(rules.fields[US].len(), US_LENGTH);
+        assert_eq!(rules.fields[LS].len(), 9);
+        for (i, field) in [
+            vec![
+                (vec![1, 1, 1, 1, 2], 4),
+                (vec![1, 2, 2, 2, 2], 8),
+                (vec![1, 3, 3, 3, 3], 12),
+                (vec![1, 4, 4, 4, 4], 16),
+                (vec![1, 5, 5, 5, 5], 20),
+                (vec![1, 6, 6, 6, 6], 24),
+            ],
+            vec![
+                (vec![2, 2, 5, 5, 6], 10),
+                (vec![2, 2, 5, 5, 6], 14),
+                (vec![1, 4, 4, 4, 6], 12),
+                (vec![1, 4, 4, 4, 4], 16),
+                (vec![1, 2, 3, 4, 5], 15),
+                (vec![2, 3, 4, 5, 6], 20),
+                (vec![2, 2, 6, 6, 6], 22),
+                (vec![1, 1, 1, 1, 2], 6),
+                (vec![1, 1, 1, 1, 1], 50),
+            ],
+        ]
+        .iter()
+        .enumerate()
+        {
+            for (j, (hand, score)) in field.iter().enumerate() {
+                assert_eq!((rules.fields[i][j].function)(hand), *score);
+            }
+        }
+
+        // No Full House from a Yatzy, no Large Straight from a Small Straight
+        assert_eq!((rules.fields[LS][6].function)(&[3, 3, 3, 3, 3]), 0);
+        assert_eq!((rules.fields[LS][5].function)(&[1, 2, 3, 4, 5]), 0);
+
+        assert_eq!(
+            rules.us_bonus,
+            USBonusRules {
+                threshold: 63,
+                bonus: 50,
+            }
+        );
+        assert_eq!(rules.yahtzee_bonus.short_name, bonus::NONE.short_name);
+    }
+
+    #[test]
+    fn test_maxi_yatzy_rules() {
+        let rules = build_yatzy_rules(true);
+
+        assert_eq!(rules.short_name, 'j');
+        assert_eq!(
+            rules.dice,
+            DiceRules {
+                dice: Dice::single(D6, Faces::fair(1, 6), 6),
+            }
+        );
+        assert_eq!(rules.chips, 0);
+
+        assert_eq!(rules.fields[US].len(), US_LENGTH);
+        assert_eq!(rules.fields[LS].len(), 14);
+        for (j, (hand, score)) in [
+            (vec![2, 2, 3, 5, 5, 6], 10),
+            (vec![2, 2, 3, 5, 5, 6], 14),
+            (vec![2, 2, 3, 3, 5, 5], 20),
+            (vec![1, 4, 4, 4, 6, 6], 12),
+            (vec![1, 4, 4, 4, 4, 6], 16),
+            (vec![4, 4, 4, 4, 4, 6], 20),
+            (vec![1, 2, 3, 4, 5, 5], 15),
+            (vec![2, 3, 4, 5, 6, 6], 20),
+            (vec![1, 2, 3, 4, 5, 6], 21),
+            (vec![1, 2, 2, 6, 6, 6], 22),
+            (vec![1, 1, 1, 6, 6, 6], 21),
+            (vec![1, 1, 6, 6, 6, 6], 26),
+            (vec![1, 1, 1, 1, 2, 3], 9),
+            (vec![1, 1, 1, 1, 1, 1], 100),
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert_eq!((rules.fields[LS][j].function)(hand), *score);
+        }
+
+        assert_eq!(
+            rules.us_bonus,
+            USBonusRules {
+                threshold: 84,
+                bonus: 100,
+            }
+        );
+        assert_eq!(rules.yahtzee_bonus.short_name, bonus::NONE.short_name);
+    }
+
+    #[test]
+    fn test_generala_rules() {
+        let rules = build_generala_rules();
+
+        assert_eq!(rules.short_name, 'k');
+        assert_eq!(rules.dice, build_rules(false, bonus::NONE).dice);
+        assert!(rules.depends_on_rerolls());
+        assert!(!build_rules(false, bonus::NONE).depends_on_rerolls());
+
+        assert_eq!(rules.fields[US].len(), US_LENGTH);
+        assert_eq!(rules.fields[LS].len(), 4);
+        for (j, (hand, score, served)) in [
+            (vec![1, 3, 4, 5, 6], 20, 25),
+            (vec![2, 2, 3, 3, 3], 30, 35),
+            (vec![2, 3, 3, 3, 3], 40, 45),
+            (vec![3, 3, 3, 3, 3], 50, 100),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let field = &rules.fields[LS][j];
+            assert_eq!(field.score(hand, 0), *served);
+            assert_eq!(field.score(hand, 1), *score);
+            assert_eq!(field.score(hand, REROLLS), *score);
+            assert_eq!(field.score(&[1, 2, 3, 4, 6], 0), 0);
+        }
+        assert_eq!(rules.fields[US][0].score(&[1, 1, 3, 4, 6], 0), 2);
+
+        assert_eq!(rules.us_bonus, USBonusRules::NONE);
+        assert!(rules.yahtzee_bonus == bonus::DOUBLE_GENERALA);
+    }
+
+    #[test]
+    fn test_yacht_rules() {
+        let rules = build_yacht_rules();
+
+        assert_eq!(rules.short_name, 'l');
+        assert_eq!(rules.dice, build_rules(false, bonus::NONE).dice);
+
+        assert_eq!(rules.fields[US].len(), US_LENGTH);
+        assert_eq!(rules.fields[LS].len(), 6);
+        for (j, (hand, score)) in [
+            (vec![2, 2, 6, 6, 6], 22),
+            (vec![1, 4, 4, 4, 4], 16),
+            (vec![1, 2, 3, 4, 5], 30),
+            (vec![2, 3, 4, 5, 6], 30),
+            (vec![1, 1, 1, 1, 2], 6),
+            (vec![1, 1, 1, 1, 1], 50),
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert_eq!((rules.fields[LS][j].function)(hand), *score);
+        }
+        // A Yacht counts as Four of a Kind, but not as Full House
+        assert_eq!((rules.fields[LS][0].function)(&[3, 3, 3, 3, 3]), 0);
+        assert_eq!((rules.fields[LS][1].function)(&[3, 3, 3, 3, 3]), 12);
+        // Straights of five dice are told apart by their pips
+        assert_ne!(rules.fields[LS][2].kind, rules.fields[LS][3].kind);
+
+        assert_eq!(rules.us_bonus, USBonusRules::NONE);
+        assert_eq!(rules.yahtzee_bonus.short_name, bonus::NONE.short_name);
+    }
+
+    #[test]
+    fn test_triple_rules() {
+        let rules = build_triple_rules();
+        let column_rules = rules.column_rules();
+
+        assert_eq!(rules.short_name, 'm');
+        assert_eq!(rules.columns, vec![1, 2, 3]);
+        assert_eq!(column_rules.short_name, 'M');
+        assert_eq!(column_rules.columns, vec![1]);
+        assert_eq!(column_rules.dice, build_rules(false, bonus::NONE).dice);
+        assert_eq!(column_rules.fields[LS].len(), LS_LENGTH);
+        assert_eq!(column_rules.us_bonus, rules.us_bonus);
+    }
+
+    #[test]
+    fn test_balut_rules() {
+        let rules = build_balut_rules();
+
+        assert_eq!(rules.short_name, 'n');
+        assert_eq!(rules.dice, build_rules(false, bonus::NONE).dice);
+        assert_eq!(rules.capacity, 4);
+
+        assert_eq!(rules.fields[US].len(), 3);
+        assert_eq!(rules.fields[LS].len(), 4);
+        for (i, field) in [
+            vec![
+                (vec![1, 4, 4, 4, 6], 12),
+                (vec![1, 5, 5, 5, 6], 15),
+                (vec![1, 4, 5, 6, 6], 12),
+            ],
+            vec![
+                (vec![2, 3, 4, 5, 6], 20 + 50),
+                (vec![2, 2, 6, 6, 6], 22 + 37),
+                (vec![2, 6, 6, 6, 6], 26 + 25),
+                (vec![6, 6, 6, 6, 6], 50 + 100),
+            ],
+        ]
+        .iter()
+        .enumerate()
+        {
+            for (j, (hand, score)) in field.iter().enumerate() {
+                assert_eq!((rules.fields[i][j].function)(hand), *score);
+            }
+        }
+        assert_eq!((rules.fields[LS][0].function)(&[1, 2, 3, 4, 6]), 0);
+        // Choice below a quarter of the 100 required earns no points
+        assert_eq!((rules.fields[LS][2].function)(&[1, 1, 1, 1, 2]), 6);
+
+        assert_eq!(rules.us_bonus.threshold, 195);
+        assert_eq!(rules.us_bonus.bonus, 300);
+    }
+
+    #[test]
+    fn test_cache_key() {
+        // Built separately, the same game has the same key
+        let rules = build_rules(false, bonus::FORCED_JOKER);
+        assert_eq!(
+            rules.cache_key(),
+            build_rules(false, bonus::FORCED_JOKER).cache_key()
+        );
+
+        // Everything that affects tables is part of the key, not only the short name
+        let mut capacity = rules.clone();
+        capacity.capacity = 2;
+        let mut columns = rules.clone();
+        columns.columns = vec![1, 2];
+        let mut dice = rules.clone();
+        dice.dice.dice = Dice::single(D6, Faces::weighted([(1, 2.0), (6, 1.0)]), 5);
+        let mut us_bonus = rules.clone();
+        us_bonus.us_bonus.bonus = 50;
+        let mut chips = rules.clone();
+        chips.chips = 1;
+        // Same name and kind, but another score
+        let mut score = rules.clone();
+        score.fields[LS][2].function =
+            Arc::new(|hand| hands::generic_identical(vec![2, 3], |_| 20, hand));
+        let mut by_rerolls = rules.clone();
+        by_rerolls.fields[LS][2].by_rerolls = Some(Arc::new(|hand, rerolls_used| {
+            hands::generic_identical(vec![2, 3], |_| 30 - 5 * rerolls_used as Score, hand)
+        }));
+        // Fingerprinted again, as their dice or fields changed
+        let [dice, score, by_rerolls] = [dice, score, by_rerolls].map(Rules::fingerprinted);
+        let variants = [
+            rules, capacity, columns, dice, us_bonus, chips, score, by_rerolls,
+        ];
+        for (i, a) in variants.iter().enumerate() {
+            for b in &variants[i + 1..] {
+                assert_ne!(a.cache_key(), b.cache_key());
+            }
+        }
     }
 }
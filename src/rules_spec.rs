@@ -0,0 +1,618 @@
+/// Serializable specification of rules, e.g. for house rules loaded from a file
+use crate::global::*;
+use crate::hands;
+use crate::rules;
+use crate::strategy::StateIndex;
+use crate::yahtzee_bonus_rules as bonus;
+
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, ensure, Result};
+use serde::{Deserialize, Serialize};
+
+/// Kind of dice, either fair with a range of pips or with explicit faces
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiceSpec {
+    /// Lowest pip, e.g. 0 for a d10
+    #[serde(default)]
+    pub lowest: Option<Pip>,
+    /// Highest pip, e.g. 6 for a d6
+    #[serde(default)]
+    pub highest: Option<Pip>,
+    /// Pips on the faces instead of a range, e.g. `[1, 1, 2, 3, 5, 8]`
+    #[serde(default)]
+    pub faces: Vec<Pip>,
+    /// Probability of each of the `faces`, e.g. for loaded dice, all equal if empty
+    #[serde(default)]
+    pub probabilities: Vec<FaceProbability>,
+    /// Number of dice of this kind
+    pub count: Frequency,
+}
+
+impl DiceSpec {
+    /// Faces of the dice, checking the range or probabilities given
+    fn faces(&self) -> Result<Faces> {
+        ensure!(self.count > 0, "No dice of a kind");
+        match (self.lowest, self.highest) {
+            (Some(lowest), Some(highest)) => {
+                ensure!(
+                    self.faces.is_empty(),
+                    "Both range of pips and faces specified"
+                );
+                ensure!(lowest <= highest, "Lowest pip above highest");
+                return Ok(Faces::fair(lowest, highest));
+            }
+            (None, None) => (),
+            _ => return Err(anyhow!("Lowest or highest pip missing")),
+        }
+        ensure!(!self.faces.is_empty(), "No faces specified");
+        if self.probabilities.is_empty() {
+            return Ok(Faces::weighted(self.faces.iter().map(|&pip| (pip, 1.0))));
+        }
+        ensure!(
+            self.probabilities.len() == self.faces.len(),
+            "Probabilities do not match faces"
+        );
+        ensure!(
+            self.probabilities
+                .iter()
+                .all(|&probability| probability > 0.0),
+            "Probabilities must be positive"
+        );
+        // Allow for measured probabilities being rounded
+        let total = self.probabilities.iter().sum::<FaceProbability>();
+        ensure!(
+            (total - 1.0).abs() < 1e-3,
+            "Probabilities add up to {}",
+            total
+        );
+        Ok(Faces::weighted(
+            self.faces
+                .iter()
+                .copied()
+                .zip(self.probabilities.iter().copied()),
+        ))
+    }
+}
+
+/// Scoring of a field, in terms of `crate::hands`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScoringSpec {
+    /// Sum of dice showing a pip, see `hands::generic_upper_section`
+    UpperSection { pip: Pip },
+    /// Sum of all dice
+    Total,
+    /// Identical pips, see `hands::generic_identical`, scoring the sum of all dice unless a
+    /// fixed score is given
+    Identical {
+        required: Vec<Frequency>,
+        score: Option<Score>,
+    },
+    /// Straight, see `hands::generic_straight`
+    Straight { length: Frequency, score: Score },
+    /// Sum of all dice within bounds, e.g. 10 or less
+    SumThreshold {
+        min: Option<Score>,
+        max: Option<Score>,
+        score: Score,
+    },
+}
+
+/// Field on score card
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldSpec {
+    /// Name of field for user interaction
+    pub name: String,
+    #[serde(flatten)]
+    pub scoring: ScoringSpec,
+    /// Factor applied to the score, e.g. 2 for Super Chance
+    #[serde(default = "default_multiplier")]
+    pub multiplier: Score,
+}
+
+fn default_multiplier() -> Score {
+    1
+}
+
+/// Rules for a game
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RulesSpec {
+    /// Short name for caching, must differ from built-in games and other specifications
+    pub short_name: char,
+    pub dice: Vec<DiceSpec>,
+    #[serde(default)]
+    pub chips: Chips,
+    /// Upper section bonus, none if omitted
+    #[serde(default)]
+    pub us_bonus: rules::USBonusRules,
+    /// Yahtzee bonus variant, one of `bonus::ALL_VARIANTS_NAMES`
+    #[serde(default = "default_yahtzee_bonus")]
+    pub yahtzee_bonus: String,
+    pub upper_section: Vec<FieldSpec>,
+    pub lower_section: Vec<FieldSpec>,
+    /// Multipliers of score card columns, one column if omitted
+    #[serde(default = "default_columns")]
+    pub columns: Vec<Score>,
+    /// Rerolls per turn, `REROLLS` if omitted
+    #[serde(default = "default_rerolls")]
+    pub rerolls: Rerolls,
+    /// Times each field is used in a column, once if omitted
+    #[serde(default = "default_capacity")]
+    pub capacity: Uses,
+    /// Fields must be used in card order, top to bottom
+    #[serde(default)]
+    pub forced_order: bool,
+    /// Rerolls carried over to later turns, none if omitted
+    #[serde(default)]
+    pub carry_over: rules::CarryOverRules,
+}
+
+fn default_yahtzee_bonus() -> String {
+    String::from("none")
+}
+
+fn default_columns() -> Vec<Score> {
+    vec![1]
+}
+
+fn default_rerolls() -> Rerolls {
+    REROLLS
+}
+
+fn default_capacity() -> Uses {
+    1
+}
+
+impl ScoringSpec {
+    /// Kind of field, five identical pips making a Yahtzee
+    fn kind(&self) -> rules::FieldKind {
+        match self {
+            ScoringSpec::UpperSection { pip } => rules::FieldKind::UpperFace(*pip),
+            ScoringSpec::Total => rules::FieldKind::Chance,
+            ScoringSpec::Identical { required, .. } => match required[..] {
+                [5] => rules::FieldKind::Yahtzee,
+                [n] => rules::FieldKind::NOfAKind(n),
+                [2, 3] => rules::FieldKind::FullHouse,
+                _ => rules::FieldKind::Groups(required.clone()),
+            },
+            ScoringSpec::Straight { length, .. } => rules::FieldKind::Straight(*length),
+            ScoringSpec::SumThreshold { min, max, .. } => rules::FieldKind::SumThreshold {
+                min: *min,
+                max: *max,
+            },
+        }
+    }
+
+    /// Build score function
+    fn function(&self) -> rules::ScoreFunction {
+        match self.clone() {
+            ScoringSpec::UpperSection { pip } => {
+                Arc::new(move |hand| hands::generic_upper_section(pip, hand))
+            }
+            ScoringSpec::Total => Arc::new(hands::total),
+            ScoringSpec::Identical { required, score } => Arc::new(move |hand| {
+                let score = |hand: &HandSlice| score.unwrap_or_else(|| hands::total(hand));
+                hands::generic_identical(required.clone(), score, hand)
+            }),
+            ScoringSpec::Straight { length, score } => {
+                Arc::new(move |hand| hands::generic_straight(length, score, hand))
+            }
+            ScoringSpec::SumThreshold { min, max, score } => Arc::new(move |hand| {
+                let total = hands::total(hand);
+                let above = min.is_none_or(|min| total >= min);
+                let below = max.is_none_or(|max| total <= max);
+                if above && below {
+                    score
+                } else {
+                    0
+                }
+            }),
+        }
+    }
+}
+
+impl FieldSpec {
+    /// Build field rule
+    fn section_rule(&self) -> rules::SectionRule {
+        let function = self.scoring.function();
+        let multiplier = self.multiplier;
+        rules::SectionRule {
+            name: self.name.clone(),
+            kind: self.scoring.kind(),
+            function: match multiplier {
+                1 => function,
+                _ => Arc::new(move |hand| multiplier * function(hand)),
+            },
+            by_rerolls: None,
+        }
+    }
+}
+
+impl RulesSpec {
+    /// Build rules from specification
+    /// # Returns
+    /// Result - the specification is checked for consistency
+    pub fn to_rules(&self) -> Result<rules::Rules> {
+        let built_in = rules::build_all_rules();
+        ensure!(
+            !built_in
+                .iter()
+                .any(|rules| rules.short_name == self.short_name),
+            "Short name {} is used by a built-in game",
+            self.short_name
+        );
+        // Reserved for playing a single column, see `rules::Rules::column_rules`
+        ensure!(
+            !self.short_name.is_ascii_uppercase(),
+            "Short name {} is upper-case",
+            self.short_name
+        );
+
+        ensure!(!self.dice.is_empty(), "No dice specified");
+        ensure!(
+            self.dice.len() <= Die::MAX as usize,
+            "Too many kinds of dice"
+        );
+        // Kinds of dice in the order given, so that `D6` and `D10` are as in Yahtzee Extreme
+        let dice = self
+            .dice
+            .iter()
+            .enumerate()
+            .map(|(die, dice)| Ok((die as Die, dice.faces()?, dice.count)))
+            .collect::<Result<_>>()?;
+
+        let fields = [&self.upper_section, &self.lower_section].map(|section| {
+            section
+                .iter()
+                .map(FieldSpec::section_rule)
+                .collect::<Vec<_>>()
+        });
+        let fields_count = fields.iter().map(Vec::len).sum::<usize>();
+        ensure!(fields_count > 0, "No fields specified");
+        ensure!(!self.columns.is_empty(), "No columns specified");
+        // Uses are entered as one digit per field, see `crate::repl`
+        ensure!(
+            (1..=9).contains(&self.capacity),
+            "Capacity must be between 1 and 9"
+        );
+        // Uses of fields are packed into an index, see `strategy::State::index`
+        let uses_radix = self.capacity as StateIndex + 1;
+        ensure!(
+            uses_radix
+                .checked_pow((fields_count * self.columns.len()) as u32)
+                .is_some(),
+            "Too many fields"
+        );
+
+        ensure!(self.rerolls >= 0, "Negative rerolls");
+        ensure!(self.carry_over.cap >= 0, "Negative cap of banked rerolls");
+        ensure!(
+            self.carry_over.cap == 0 || self.carry_over.max_per_turn >= self.rerolls,
+            "Rerolls per turn must be at least {}",
+            self.rerolls
+        );
+
+        let yahtzee_bonus = bonus::ALL_VARIANTS_NAMES
+            .iter()
+            .position(|&name| name == self.yahtzee_bonus)
+            .map(|i| bonus::ALL_VARIANTS[i].clone())
+            .ok_or_else(|| anyhow!("Unknown Yahtzee bonus variant: {}", self.yahtzee_bonus))?;
+        // Yahtzee bonus rules refer to fields by kind, see `rules::FieldKind`
+        let has_yahtzee = fields[LS]
+            .iter()
+            .any(|field| field.kind == rules::FieldKind::Yahtzee);
+        ensure!(
+            yahtzee_bonus == bonus::NONE || has_yahtzee && self.capacity == 1,
+            "Yahtzee bonus rules require a Yahtzee field, used once"
+        );
+
+        Ok(rules::Rules {
+            short_name: self.short_name,
+            dice: rules::DiceRules { dice: Dice(dice) },
+            chips: self.chips,
+            fields,
+            us_bonus: self.us_bonus.clone(),
+            yahtzee_bonus,
+            objective: rules::Objective::Expectation,
+            columns: self.columns.clone(),
+            rerolls: self.rerolls,
+            capacity: self.capacity,
+            forced_order: self.forced_order,
+            carry_over: self.carry_over.clone(),
+            fingerprint: 0,
+        }
+        .fingerprinted())
+    }
+}
+
+/// Load rules from file
+/// # Arguments
+/// * `filename` - JSON if it ends in `.json`, TOML otherwise
+/// # Returns
+/// Result - I/O, deserialization and consistency checks can fail
+pub fn load_rules(filename: &str) -> Result<rules::Rules> {
+    let contents = read_to_string(filename)?;
+    let spec: RulesSpec = match Path::new(filename).extension() {
+        Some(extension) if extension == "json" => serde_json::from_str(&contents)?,
+        _ => toml::from_str(&contents)?,
+    };
+    spec.to_rules()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::strategy;
+
+    use std::env::temp_dir;
+    use std::fs::{remove_file, write};
+
+    /// Compare rules field by field on all hands
+    fn assert_same_rules(rules: &rules::Rules, expected: &rules::Rules) {
+        assert_eq!(rules.dice.dice, expected.dice.dice);
+        assert_eq!(rules.chips, expected.chips);
+        assert_eq!(rules.us_bonus, expected.us_bonus);
+        assert!(rules.yahtzee_bonus == expected.yahtzee_bonus);
+
+        let empty = PartialHand(Vec::new());
+        let hands = strategy::probability_to_roll(empty, &expected.dice).table;
+        for (section, expected_section) in rules.fields.iter().zip(&expected.fields) {
+            assert_eq!(section.len(), expected_section.len());
+            for (field, expected_field) in section.iter().zip(expected_section) {
+                assert_eq!(field.name, expected_field.name);
+                assert_eq!(field.kind, expected_field.kind);
+                for hand in hands.keys() {
+                    let mut pips: Hand = hand.0.iter().map(|&(_, pip)| pip).collect();
+                    pips.sort_unstable();
+                    assert_eq!(
+                        (field.function)(&pips),
+                        (expected_field.function)(&pips),
+                        "{}: {:?}",
+                        field.name,
+                        pips
+                    );
+                }
+            }
+        }
+    }
+
+    const REGULAR: &str = r#"
+short_name = "g"
+yahtzee_bonus = "forced"
+dice = [{ lowest = 1, highest = 6, count = 5 }]
+
+[us_bonus]
+threshold = 63
+bonus = 35
+
+[[upper_section]]
+name = "Count and Add Only Aces"
+kind = "upper_section"
+pip = 1
+
+[[upper_section]]
+name = "Count and Add Only Twos"
+kind = "upper_section"
+pip = 2
+
+[[upper_section]]
+name = "Count and Add Only Threes"
+kind = "upper_section"
+pip = 3
+
+[[upper_section]]
+name = "Count and Add Only Fours"
+kind = "upper_section"
+pip = 4
+
+[[upper_section]]
+name = "Count and Add Only Fives"
+kind = "upper_section"
+pip = 5
+
+[[upper_section]]
+name = "Count and Add Only Sixes"
+kind = "upper_section"
+pip = 6
+
+[[lower_section]]
+name = "Three of a Kind"
+kind = "identical"
+required = [3]
+
+[[lower_section]]
+name = "Four of a Kind"
+kind = "identical"
+required = [4]
+
+[[lower_section]]
+name = "Full House"
+kind = "identical"
+required = [2, 3]
+score = 25
+
+[[lower_section]]
+name = "Small Straight"
+kind = "straight"
+length = 4
+score = 30
+
+[[lower_section]]
+name = "Large Straight"
+kind = "straight"
+length = 5
+score = 40
+
+[[lower_section]]
+name = "Yahtzee"
+kind = "identical"
+required = [5]
+score = 50
+
+[[lower_section]]
+name = "Chance"
+kind = "total"
+"#;
+
+    #[test]
+    fn test_regular_spec() {
+        let spec: RulesSpec = toml::from_str(REGULAR).unwrap();
+        let rules = spec.to_rules().unwrap();
+        assert_eq!(rules.short_name, 'g');
+        assert_same_rules(&rules, &rules::build_rules(false, bonus::FORCED_JOKER));
+
+        // Inconsistent specifications
+        let mut reserved = spec.clone();
+        reserved.short_name = 'a';
+        assert!(reserved.to_rules().is_err());
+        let mut unknown = spec.clone();
+        unknown.yahtzee_bonus = String::from("unknown");
+        assert!(unknown.to_rules().is_err());
+        let mut no_yahtzee = spec.clone();
+        no_yahtzee.lower_section.remove(5);
+        assert!(no_yahtzee.to_rules().is_err());
+        no_yahtzee.yahtzee_bonus = String::from("none");
+        assert!(no_yahtzee.to_rules().is_ok());
+        let mut no_chance = spec.clone();
+        no_chance.lower_section.pop();
+        assert!(no_chance.to_rules().is_ok());
+        let mut refilled = spec.clone();
+        refilled.capacity = 2;
+        assert!(refilled.to_rules().is_err());
+        refilled.yahtzee_bonus = String::from("none");
+        assert_eq!(refilled.to_rules().unwrap().capacity, 2);
+        refilled.capacity = 0;
+        assert!(refilled.to_rules().is_err());
+        let mut carried = spec.clone();
+        carried.carry_over.cap = 3;
+        carried.carry_over.max_per_turn = 4;
+        assert_eq!(carried.to_rules().unwrap().carry_over.cap, 3);
+        carried.carry_over.max_per_turn = 1;
+        assert!(carried.to_rules().is_err());
+        carried.rerolls = 1;
+        assert_eq!(carried.to_rules().unwrap().rerolls, 1);
+        carried.rerolls = -1;
+        assert!(carried.to_rules().is_err());
+        let mut no_dice = spec;
+        no_dice.dice[0].count = 0;
+        assert!(no_dice.to_rules().is_err());
+    }
+
+    /// Fair dice with a range of pips
+    fn fair(lowest: Pip, highest: Pip, count: Frequency) -> DiceSpec {
+        DiceSpec {
+            lowest: Some(lowest),
+            highest: Some(highest),
+            faces: Vec::new(),
+            probabilities: Vec::new(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_dice_spec() {
+        let mut spec: RulesSpec = toml::from_str(REGULAR).unwrap();
+        spec.dice = vec![toml::from_str("faces = [1, 1, 2, 3, 5, 8]\ncount = 5").unwrap()];
+        let fibonacci = Faces::weighted([1, 1, 2, 3, 5, 8].map(|pip| (pip, 1.0)));
+        let rules = spec.to_rules().unwrap();
+        assert_eq!(rules.dice.dice, Dice::single(D6, fibonacci, 5));
+
+        // Loaded die, with probabilities rounded as measured
+        let loaded = toml::from_str::<DiceSpec>(
+            "faces = [1, 2, 3, 4, 5, 6]
+probabilities = [0.1667, 0.1667, 0.1667, 0.1667, 0.1667, 0.1666]
+count = 5",
+        )
+        .unwrap();
+        let faces = loaded.faces().unwrap();
+        assert_eq!(faces.0.len(), 6);
+        assert!((faces.0[5].1 - 0.1666).abs() < 1e-4);
+
+        // Inconsistent specifications
+        let mut wrong = loaded.clone();
+        wrong.probabilities.pop();
+        assert!(wrong.faces().is_err());
+        let mut wrong = loaded.clone();
+        wrong.probabilities[0] = 0.5;
+        assert!(wrong.faces().is_err());
+        let mut wrong = loaded.clone();
+        wrong.probabilities = vec![1.5, -0.1, -0.1, -0.1, -0.1, -0.1];
+        assert!(wrong.faces().is_err());
+        let mut wrong = loaded;
+        wrong.lowest = Some(1);
+        assert!(wrong.faces().is_err());
+        let mut wrong = fair(1, 6, 5);
+        wrong.highest = None;
+        assert!(wrong.faces().is_err());
+        assert!(fair(6, 1, 5).faces().is_err());
+    }
+
+    #[test]
+    fn test_load_rules() {
+        // Yahtzee Extreme, with upper section taken from regular rules
+        let regular: RulesSpec = toml::from_str(REGULAR).unwrap();
+        let field = |name: &str, scoring| FieldSpec {
+            name: String::from(name),
+            scoring,
+            multiplier: 1,
+        };
+        let identical = |required: &[Frequency], score| ScoringSpec::Identical {
+            required: required.to_vec(),
+            score,
+        };
+        let straight = |length, score| ScoringSpec::Straight { length, score };
+        let threshold = |min, max| ScoringSpec::SumThreshold {
+            min,
+            max,
+            score: 40,
+        };
+        let spec = RulesSpec {
+            short_name: 'h',
+            dice: vec![fair(1, 6, 5), fair(0, 9, 1)],
+            chips: 3,
+            us_bonus: rules::USBonusRules {
+                threshold: 73,
+                bonus: 45,
+            },
+            yahtzee_bonus: default_yahtzee_bonus(),
+            upper_section: regular.upper_section,
+            lower_section: vec![
+                field("Three of a Kind", identical(&[3], None)),
+                field("Four of a Kind", identical(&[4], None)),
+                field("Two Pairs", identical(&[2, 2], None)),
+                field("Three Pairs", identical(&[2, 2, 2], Some(35))),
+                field("Two Triples", identical(&[3, 3], Some(45))),
+                field("Full House", identical(&[2, 3], Some(25))),
+                field("Grand Full House", identical(&[2, 4], Some(45))),
+                field("Small Straight", straight(4, 30)),
+                field("Large Straight", straight(5, 40)),
+                field("Highway", straight(6, 50)),
+                field("Yahtzee", identical(&[5], Some(50))),
+                field("Yahtzee Extreme", identical(&[6], Some(75))),
+                field("10 or less", threshold(None, Some(10))),
+                field("33 or more", threshold(Some(33), None)),
+                field("Chance", ScoringSpec::Total),
+                FieldSpec {
+                    multiplier: 2,
+                    ..field("Super Chance", ScoringSpec::Total)
+                },
+            ],
+            columns: default_columns(),
+            rerolls: default_rerolls(),
+            capacity: default_capacity(),
+            forced_order: false,
+            carry_over: rules::CarryOverRules::NONE,
+        };
+
+        let test_file = temp_dir().join("optimal-yahtzee-extreme-test_load_rules.json");
+        let test_filename = test_file.to_str().unwrap();
+        write(test_filename, serde_json::to_string(&spec).unwrap()).unwrap();
+        let rules = load_rules(test_filename).unwrap();
+        assert_same_rules(&rules, &rules::build_rules(true, bonus::NONE));
+        remove_file(test_filename).unwrap();
+    }
+}
@@ -0,0 +1,414 @@
+/// Simulation of whole games with random rolls, to compare the strategy with actual play
+use crate::global::*;
+use crate::rules;
+use crate::strategy::{self, ArchFloat};
+use crate::view_model::{Action, Recommendation, ViewModel};
+use crate::yahtzee_bonus_rules as bonus;
+
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Player deciding on actions in simulated games
+pub trait Policy {
+    /// Act on a full hand rolled, advancing `view_model` as if the action was taken
+    /// # Returns
+    /// Action taken
+    fn act(&mut self, view_model: &mut ViewModel, hand: PartialHand) -> Result<Action>;
+}
+
+/// Follow the recommendations of the strategy, see `ViewModel::recommend`
+pub struct Optimal;
+
+impl Policy for Optimal {
+    fn act(&mut self, view_model: &mut ViewModel, hand: PartialHand) -> Result<Action> {
+        Ok(match view_model.recommend(hand)? {
+            Recommendation::Reroll(keep, _) => Action::Reroll(keep),
+            Recommendation::Field(column, section, field, _) => {
+                Action::Field(column, section, field)
+            }
+        })
+    }
+}
+
+/// Any function choosing an action, taken with `ViewModel::apply`
+impl<F: FnMut(&ViewModel, &PartialHand) -> Action> Policy for F {
+    fn act(&mut self, view_model: &mut ViewModel, hand: PartialHand) -> Result<Action> {
+        let action = self(view_model, &hand);
+        view_model.apply(hand, action.clone())?;
+        Ok(action)
+    }
+}
+
+/// Result of a simulated game
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameResult {
+    /// Final score, including bonuses
+    pub score: Score,
+    /// Yahtzees scored, in the Yahtzee field or with a Yahtzee bonus, see `scores_yahtzee`
+    pub yahtzees: usize,
+    /// Upper section bonus received in every column
+    pub upper_bonus: bool,
+    /// Reroll chips used
+    pub chips_used: Chips,
+}
+
+/// Results of simulated games
+#[derive(Clone, Debug, PartialEq)]
+pub struct Simulation {
+    pub games: Vec<GameResult>,
+}
+
+impl Simulation {
+    /// Mean of final score
+    pub fn mean(&self) -> ArchFloat {
+        self.mean_of(|game| game.score as ArchFloat)
+    }
+
+    /// Standard deviation of final score, as estimated from the sample
+    pub fn standard_deviation(&self) -> ArchFloat {
+        let mean = self.mean();
+        let squares = self
+            .games
+            .iter()
+            .map(|game| (game.score as ArchFloat - mean).powi(2));
+        (squares.sum::<ArchFloat>() / (self.games.len().max(2) - 1) as ArchFloat).sqrt()
+    }
+
+    /// Mean of any statistic per game
+    pub fn mean_of(&self, statistic: impl Fn(&GameResult) -> ArchFloat) -> ArchFloat {
+        self.games.iter().map(statistic).sum::<ArchFloat>() / self.games.len() as ArchFloat
+    }
+
+    /// Histogram of final score
+    /// # Arguments
+    /// * `width` - points per bin
+    /// # Returns
+    /// Lowest score of each bin with the games in it, from the lowest to the highest score
+    pub fn histogram(&self, width: Score) -> Vec<(Score, usize)> {
+        let scores = self.games.iter().map(|game| game.score / width);
+        let (min, max) = match (scores.clone().min(), scores.clone().max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Vec::new(),
+        };
+        let mut bins = vec![0; (max - min + 1) as usize];
+        for bin in scores {
+            bins[(bin - min) as usize] += 1;
+        }
+        let enumerated = bins.into_iter().enumerate();
+        enumerated
+            .map(|(i, count)| ((min + i as Score) * width, count))
+            .collect()
+    }
+
+    /// Report statistics, compared with the expectation of the strategy
+    /// # Arguments
+    /// * `rules` - rules the games were played by
+    /// * `expectation` - expectation value at the start of the games, see `rules::Objective`
+    pub fn report(&self, rules: &rules::Rules, expectation: strategy::Expectation) -> String {
+        let games = self.games.len() as ArchFloat;
+        let standard_deviation = self.standard_deviation();
+        let mut out = format!(
+            "Played {} game(s), scoring {:.2} on average with a standard deviation of {:.2}.",
+            self.games.len(),
+            self.mean(),
+            standard_deviation
+        );
+        out += &match rules.objective {
+            // Estimated for several columns, see `strategy::column_estimate`
+            rules::Objective::Expectation if rules.columns.len() > 1 => format!(
+                "\nThe strategy estimates {:.2} from playing each column on its own, the standard error of the mean is {:.2}.",
+                expectation,
+                standard_deviation / games.sqrt()
+            ),
+            rules::Objective::Expectation => format!(
+                "\nThe strategy expects {:.2}, the standard error of the mean is {:.2}.",
+                expectation,
+                standard_deviation / games.sqrt()
+            ),
+            rules::Objective::Target(target) => format!(
+                "\nReached {} in {:.2}% of games, the strategy expects {:.2}%.",
+                target,
+                self.mean_of(|game| (game.score >= target) as u8 as ArchFloat) * 100.0,
+                expectation * 100.0
+            ),
+            rules::Objective::Win(_) => String::new(),
+        };
+        if let Some(yahtzee) = rules.yahtzee_field() {
+            out += &format!(
+                "\n{} scored {:.3} times per game, in {:.2}% of games at least once.",
+                rules.fields[LS][yahtzee].name,
+                self.mean_of(|game| game.yahtzees as ArchFloat),
+                self.mean_of(|game| (game.yahtzees > 0) as u8 as ArchFloat) * 100.0
+            );
+        }
+        if rules.us_bonus != rules::USBonusRules::NONE {
+            out += &format!(
+                "\nReceived the upper section bonus in {:.2}% of games.",
+                self.mean_of(|game| game.upper_bonus as u8 as ArchFloat) * 100.0
+            );
+        }
+        if rules.chips > 0 {
+            out += &format!(
+                "\nUsed {:.2} of {} chip(s) per game.",
+                self.mean_of(|game| game.chips_used as ArchFloat),
+                rules.chips
+            );
+        }
+        // Bars relative to the fullest bin
+        let histogram = self.histogram(HISTOGRAM_WIDTH);
+        let fullest = histogram.iter().map(|&(_, count)| count).max().unwrap_or(0);
+        for (score, count) in histogram {
+            out += &format!(
+                "\n{:>4}-{:<4} {:>6.2}% {}",
+                score,
+                score + HISTOGRAM_WIDTH - 1,
+                count as ArchFloat / games * 100.0,
+                "#".repeat(count * HISTOGRAM_BAR / fullest)
+            );
+        }
+        out
+    }
+}
+
+/// Points per bin of the histogram in the report
+const HISTOGRAM_WIDTH: Score = 10;
+/// Length of the bar of the fullest bin in the report
+const HISTOGRAM_BAR: usize = 50;
+
+/// Roll the dice not kept
+/// # Arguments
+/// * `rng` - random number generator
+/// * `dice` - dice of the rules
+/// * `keep` - dice kept, empty to roll all
+fn roll(rng: &mut StdRng, dice: &Dice, keep: PartialHand) -> PartialHand {
+    let mut hand = keep;
+    for (die, faces, frequency) in &dice.0 {
+        let kept = hand.0.iter().filter(|(other, _)| other == die).count();
+        for _ in kept..*frequency as usize {
+            let mut random = rng.gen::<FaceProbability>();
+            // Fall back to the last face for rounding errors in the probabilities
+            let (mut pip, _) = faces.0[faces.0.len() - 1];
+            for &(face, probability) in &faces.0 {
+                if random < probability {
+                    pip = face;
+                    break;
+                }
+                random -= probability;
+            }
+            hand.0.push((*die, pip));
+        }
+    }
+    hand
+}
+
+/// Decide whether scoring a hand counts as a Yahtzee, i.e. it is scored in the Yahtzee field, or
+/// it is a further Yahtzee awarded a Yahtzee bonus, scored by the rerolls used as in
+/// `strategy::score_field`
+/// # Arguments
+/// * `view_model` - game before scoring
+/// * `hand` - full hand scored
+/// * `column`, `section`, `field` - field scored in
+fn scores_yahtzee(
+    view_model: &ViewModel,
+    hand: &PartialHand,
+    column: Column,
+    section: Section,
+    field: Field,
+) -> bool {
+    let rules = &view_model.rules;
+    let Some(yahtzee) = rules.yahtzee_field() else {
+        return false;
+    };
+    let mut pips: Hand = hand.0.iter().map(|&(_, pip)| pip).collect();
+    pips.sort_unstable();
+    let rerolls_used = strategy::rerolls_used(&view_model.state, view_model.rerolls, rules);
+    if rules.fields[LS][yahtzee].score(&pips, rerolls_used) == 0 {
+        return false;
+    }
+    if section == LS && field == yahtzee {
+        return true;
+    }
+    if !view_model.state.scored_yahtzee || rules.yahtzee_bonus == bonus::NONE {
+        return false;
+    }
+    let natural = rules.fields[section][field].score(&pips, rerolls_used);
+    let score_card = &view_model.state.used[column];
+    let joker = bonus::joker(rules, score_card, &pips, section, field, natural);
+    let (_, yahtzee_bonus) = (rules.yahtzee_bonus.rules)(&joker, section);
+    yahtzee_bonus > 0
+}
+
+/// Play games with random rolls
+/// # Arguments
+/// * `view_model` - game to start each from, usually a new one
+/// * `games` - number of games to play
+/// * `seed` - seed of the random rolls, the same seed playing the same games
+/// * `policy` - deciding on actions, e.g. `Optimal`
+pub fn simulate(
+    view_model: &ViewModel,
+    games: usize,
+    seed: u64,
+    policy: &mut impl Policy,
+) -> Result<Simulation> {
+    let rules = &view_model.rules;
+    let dice = &rules.dice.dice;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut results = Vec::with_capacity(games);
+    for _ in 0..games {
+        let mut game = view_model.clone();
+        let mut yahtzees = 0;
+        while !game.state.is_game_over(rules) {
+            let mut hand = roll(&mut rng, dice, PartialHand(Vec::new()));
+            loop {
+                let before = game.clone();
+                match policy.act(&mut game, hand.clone())? {
+                    Action::Reroll(keep) => hand = roll(&mut rng, dice, keep),
+                    Action::Field(column, section, field) => {
+                        if scores_yahtzee(&before, &hand, column, section, field) {
+                            yahtzees += 1;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        let threshold = rules.us_bonus.threshold;
+        results.push(GameResult {
+            score: game.score.iter().sum(),
+            yahtzees,
+            upper_bonus: game.state.upper_scores.iter().all(|&s| s >= threshold),
+            chips_used: view_model.state.chips - game.state.chips,
+        });
+    }
+    Ok(Simulation { games: results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::strategy::tests::{simple_rules, very_simple_rules};
+
+    use std::sync::Arc;
+
+    /// New game by `rules`
+    fn new_game(rules: rules::Rules) -> ViewModel {
+        let state = strategy::State::new_from_rules(&rules);
+        let rerolls = state.turn_rerolls(&rules);
+        ViewModel {
+            rules,
+            state,
+            score: [0, 0],
+            rerolls,
+        }
+    }
+
+    #[test]
+    fn test_roll() {
+        let dice = Dice(vec![
+            (D6, Faces::fair(1, 6), 5),
+            (D10, Faces::fair(0, 9), 1),
+        ]);
+        let mut rng = StdRng::seed_from_u64(0);
+        let hand = roll(&mut rng, &dice, PartialHand(vec![(D6, 6), (D6, 6)]));
+        assert!(hand.is_full_hand(&dice));
+        assert_eq!(hand.0[..2], [(D6, 6), (D6, 6)]);
+
+        // A loaded coin
+        let loaded = Dice::single(COIN, Faces::weighted([(1, 1.0), (2, 3.0)]), 1);
+        let twos = (0..4000)
+            .filter(|_| roll(&mut rng, &loaded, PartialHand(Vec::new())).0[0] == (COIN, 2))
+            .count();
+        assert!((2850..3150).contains(&twos));
+    }
+
+    #[test]
+    fn test_simulate() {
+        // Scoring the coin right away
+        let game = new_game(very_simple_rules());
+        let mut first_roll = |_: &ViewModel, _: &PartialHand| Action::Field(0, LS, 0);
+        let simulation = simulate(&game, 1000, 1, &mut first_roll).unwrap();
+        assert_eq!(simulation.games.len(), 1000);
+        assert!((simulation.mean() - 0.5).abs() < 0.05);
+        assert!((simulation.standard_deviation() - 0.5).abs() < 0.01);
+        assert_eq!(simulation.mean_of(|game| game.chips_used as ArchFloat), 0.0);
+        let histogram = simulation.histogram(1);
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(histogram[0].1 + histogram[1].1, 1000);
+
+        // The strategy throws until 2, with two rerolls and two chips, using chips if needed
+        let simulation = simulate(&game, 1000, 1, &mut Optimal).unwrap();
+        let expectation = strategy::turn_start_expectation(&game.state, &game.rules);
+        assert!((simulation.mean() - expectation).abs() < 0.02);
+        assert!(simulation.games.iter().all(|game| game.chips_used <= 2));
+        // Deterministic with the seed
+        assert_eq!(simulate(&game, 1000, 1, &mut Optimal).unwrap(), simulation);
+        assert_eq!(simulation.histogram(10), vec![(0, 1000)]);
+    }
+
+    #[test]
+    fn test_report() {
+        let rules = simple_rules();
+        let game = new_game(rules.clone());
+        let simulation = simulate(&game, 200, 2, &mut Optimal).unwrap();
+        let expectation = strategy::turn_start_expectation(&game.state, &rules);
+        // Within four standard errors
+        let standard_error = simulation.standard_deviation() / (200.0 as ArchFloat).sqrt();
+        assert!((simulation.mean() - expectation).abs() < 4.0 * standard_error);
+        assert!(simulation.games.iter().any(|game| game.yahtzees > 0));
+        assert!(simulation.games.iter().any(|game| game.upper_bonus));
+
+        let report = simulation.report(&rules, expectation);
+        let mut lines = report.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("Played 200 game(s), scoring "));
+        assert!(lines.next().unwrap().starts_with("The strategy expects "));
+        assert!(lines.next().unwrap().starts_with("All Twos scored "));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("Received the upper section bonus in "));
+        assert!(lines.all(|line| line.contains('%')));
+
+        // Only estimated with several columns
+        let rules = rules::build_triple_rules();
+        let report = simulation.report(&rules, 700.0);
+        let line = report.lines().nth(1).unwrap();
+        assert!(line.starts_with("The strategy estimates 700.00 from playing each column"));
+    }
+
+    #[test]
+    fn test_scores_yahtzee() {
+        let mut game = new_game(simple_rules());
+        let (chance, all_twos) = (4, 5);
+        let twos = PartialHand(vec![(COIN, 2), (COIN, 2)]);
+        assert!(scores_yahtzee(&game, &twos, 0, LS, all_twos));
+        // Scoring in another field before the Yahtzee field
+        assert!(!scores_yahtzee(&game, &twos, 0, LS, chance));
+        assert!(!scores_yahtzee(&game, &twos, 0, US, 1));
+        let mixed = PartialHand(vec![(COIN, 1), (COIN, 2)]);
+        assert!(!scores_yahtzee(&game, &mixed, 0, LS, all_twos));
+
+        // With a Yahtzee bonus after the Yahtzee field was scored
+        game.state.used[0][LS][all_twos] = 1;
+        game.state.scored_yahtzee = true;
+        assert!(scores_yahtzee(&game, &twos, 0, LS, chance));
+        assert!(!scores_yahtzee(&game, &mixed, 0, LS, chance));
+
+        // Scoring by the rerolls used, only counting when served
+        let mut rules = simple_rules();
+        let function = rules.fields[LS][all_twos].function.clone();
+        rules.fields[LS][all_twos].by_rerolls =
+            Some(Arc::new(move |hand, rerolls_used| match rerolls_used {
+                0 => function(hand),
+                _ => 0,
+            }));
+        let mut game = new_game(rules.fingerprinted());
+        assert!(scores_yahtzee(&game, &twos, 0, LS, all_twos));
+        game.rerolls = 0;
+        assert!(!scores_yahtzee(&game, &twos, 0, LS, all_twos));
+    }
+}
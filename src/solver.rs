@@ -0,0 +1,960 @@
+/// Bottom-up solver over turn-start states, see Glenn 2006
+use crate::global::*;
+use crate::rules;
+use crate::strategy::{self, Expectation, State, StateIndex};
+use crate::yahtzee_bonus_rules as bonus;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{ensure, Result};
+use cached::proc_macro::cached;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+
+/// Expectation values at the beginning of a turn, see `rules::Objective`, indexed by
+/// `State::index`, with NaN for states not solved
+#[derive(Debug)]
+pub enum Table {
+    /// Held in memory
+    Memory(Vec<Expectation>),
+    /// Read from a file on each look-up, for tables too large to hold in memory
+    File {
+        file: Mutex<File>,
+        /// Position of the expectation value of the first state, in little-endian
+        offset: u64,
+        /// Number of states
+        len: StateIndex,
+    },
+}
+
+impl Table {
+    /// Expectation value of a state, if it was solved
+    /// # Arguments
+    /// * `index` - packed index of the state, see `State::index`
+    pub fn get(&self, index: StateIndex) -> Option<Expectation> {
+        let expectation = match self {
+            Table::Memory(expectations) => *expectations.get(index)?,
+            Table::File { file, offset, len } => {
+                if index >= *len {
+                    return None;
+                }
+                let position = offset + (index * size_of::<Expectation>()) as u64;
+                let mut bytes = [0; size_of::<Expectation>()];
+                let mut file = file.lock().unwrap();
+                file.seek(SeekFrom::Start(position))
+                    .and_then(|_| file.read_exact(&mut bytes))
+                    .expect("Tables stay readable from their file");
+                Expectation::from_le_bytes(bytes)
+            }
+        };
+        (!expectation.is_nan()).then_some(expectation)
+    }
+
+    /// States solved, by packed index, with their expectation values
+    #[cfg(test)]
+    pub fn iter(&self) -> impl Iterator<Item = (StateIndex, Expectation)> + '_ {
+        let len = match self {
+            Table::Memory(expectations) => expectations.len(),
+            Table::File { len, .. } => *len,
+        };
+        (0..len).filter_map(|index| Some((index, self.get(index)?)))
+    }
+}
+
+/// Expectation values at the beginning of a turn of the states in a layer, see `solve_layers`
+/// States are grouped by score card into blocks of all packed indices with its uses of fields,
+/// which are consecutive, see `State::card_index_count`, with NaN for states not reachable.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    /// Packed uses of fields of the score cards, see `State::used_index`, sorted
+    cards: Vec<StateIndex>,
+    /// Number of packed indices per score card
+    block: StateIndex,
+    /// Blocks of expectation values, in the order of `cards`
+    expectations: Vec<Expectation>,
+}
+
+impl Layer {
+    /// Expectation value of a state, if it is in the layer
+    /// # Arguments
+    /// * `index` - packed index of the state, see `State::index`
+    fn get(&self, index: StateIndex) -> Option<Expectation> {
+        let position = self.cards.binary_search(&(index / self.block)).ok()?;
+        let expectation = self.expectations[position * self.block + index % self.block];
+        (!expectation.is_nan()).then_some(expectation)
+    }
+
+    /// Blocks of expectation values, see `Layer`, by packed index of their first state, e.g. to
+    /// store them in a table
+    pub fn blocks(&self) -> impl Iterator<Item = (StateIndex, &[Expectation])> + '_ {
+        let blocks = self.cards.iter().zip(self.expectations.chunks(self.block));
+        blocks.map(move |(&card, expectations)| (card * self.block, expectations))
+    }
+
+    /// States in the layer, by packed index, with their expectation values
+    #[cfg(test)]
+    pub fn iter(&self) -> impl Iterator<Item = (StateIndex, Expectation)> + '_ {
+        self.blocks().flat_map(|(first, expectations)| {
+            let expectations = expectations.iter().copied().enumerate();
+            let solved = expectations.filter(|(_, expectation)| !expectation.is_nan());
+            solved.map(move |(index, expectation)| (first + index, expectation))
+        })
+    }
+}
+
+/// Combinatorics of dice, shared by all turns
+pub struct Rolls {
+    /// Pips of all full hands, sorted
+    hands: Vec<Hand>,
+    /// Probabilities to roll full hands from scratch, as indices into `hands`
+    initial: Vec<(usize, Expectation)>,
+    /// Probabilities to roll full hands when keeping a partial hand, as indices into `hands`
+    keeps: Vec<Vec<(usize, Expectation)>>,
+    /// Partial hands that can be kept from each full hand, as indices into `keeps`
+    hand_keeps: Vec<Vec<usize>>,
+}
+
+impl Rolls {
+    /// Enumerate all hands and partial hands
+    /// # Arguments
+    /// * `rules` - dice rules
+    fn new(rules: &rules::DiceRules) -> Self {
+        let empty = PartialHand(Vec::new());
+        let mut full_hands: Vec<_> = strategy::probability_to_roll(empty, rules)
+            .table
+            .into_iter()
+            .collect();
+        // Deterministic order
+        full_hands.sort_unstable_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        let index: HashMap<_, _> = full_hands
+            .iter()
+            .enumerate()
+            .map(|(i, (hand, _))| (hand.clone(), i))
+            .collect();
+
+        let mut keep_index = HashMap::new();
+        let mut keeps = Vec::new();
+        let mut hand_keeps = Vec::new();
+        for (hand, _) in &full_hands {
+            let mut indices = Vec::new();
+            for keep in strategy::possible_keeps(hand) {
+                if keep.has_full_hand_length(&rules.dice) {
+                    continue;
+                }
+                let i = *keep_index.entry(keep.clone()).or_insert_with(|| {
+                    let table = strategy::probability_to_roll(keep, rules).table;
+                    let outcomes = table.into_iter().map(|(hand, p)| (index[&hand], p.0));
+                    keeps.push(outcomes.collect());
+                    keeps.len() - 1
+                });
+                indices.push(i);
+            }
+            hand_keeps.push(indices);
+        }
+
+        Rolls {
+            hands: full_hands
+                .iter()
+                .map(|(hand, _)| hand.0.iter().map(|&(_, pip)| pip).collect())
+                .collect(),
+            initial: full_hands
+                .iter()
+                .enumerate()
+                .map(|(i, (_, p))| (i, p.0))
+                .collect(),
+            keeps,
+            hand_keeps,
+        }
+    }
+
+    /// Expectation values of all partial hands to keep
+    /// # Arguments
+    /// * `values` - expectation values of full hands after rolling
+    fn keep_values(&self, values: &[Expectation]) -> Vec<Expectation> {
+        let expect = |outcomes: &Vec<(usize, Expectation)>| {
+            outcomes.iter().map(|&(i, p)| p * values[i]).sum()
+        };
+        self.keeps.iter().map(expect).collect()
+    }
+
+    /// Best choices of full hands between stopping and keeping for a reroll
+    /// # Arguments
+    /// * `stop` - expectation values of full hands when stopping
+    /// * `keep_values` - expectation values of partial hands, see `keep_values`
+    /// # Returns
+    /// Expectation values of full hands, with the partial hand to keep as an index into `keeps`,
+    /// or `None` to stop, preferred on ties
+    fn choose(
+        &self,
+        stop: &[Expectation],
+        keep_values: &[Expectation],
+    ) -> Vec<(Expectation, Option<usize>)> {
+        let best_keep = |(&stop, keeps): (&Expectation, &Vec<usize>)| {
+            let keeps = keeps.iter().map(|&k| (keep_values[k], Some(k)));
+            keeps.fold((stop, None), |best, keep| match keep.0 > best.0 {
+                true => keep,
+                _ => best,
+            })
+        };
+        stop.iter()
+            .zip(self.hand_keeps.iter())
+            .map(best_keep)
+            .collect()
+    }
+
+    /// Expectation values of full hands when choosing between stopping and keeping for a reroll,
+    /// see `choose`
+    fn best(&self, stop: &[Expectation], keep_values: &[Expectation]) -> Vec<Expectation> {
+        let choices = self.choose(stop, keep_values).into_iter();
+        choices.map(|(expectation, _)| expectation).collect()
+    }
+}
+
+/// Combinatorics of dice, see `Rolls`
+/// # Arguments
+/// * `rules` - dice rules
+#[cached(
+    key = "rules::Fingerprint",
+    convert = r#"{ rules::fingerprint(&rules.dice) }"#
+)]
+pub fn rolls(rules: &rules::DiceRules) -> Arc<Rolls> {
+    Arc::new(Rolls::new(rules))
+}
+
+/// Evaluate a turn, starting with an empty hand
+/// # Arguments
+/// * `state` - state at the beginning of the turn, with at least one field unused
+/// * `rules` - rules to be used
+/// * `rolls` - see `Rolls`
+/// * `next` - expectation value of a state reachable after this turn, e.g. from the next layer
+/// # Returns
+/// Expectation value, see `rules::Objective`
+pub fn solve_turn(
+    state: &State,
+    rules: &rules::Rules,
+    rolls: &Rolls,
+    next: impl Fn(&State) -> Expectation,
+) -> Expectation {
+    let open = state.open_fields(rules);
+    let stop_values = |chips, rerolls| {
+        let mut state = state.clone();
+        state.chips = chips;
+        let best_field = |hand: &Hand| {
+            let fields = open.iter().map(|&(column, section, field)| {
+                let (new_state, score) =
+                    strategy::score_field(&state, hand, column, section, field, rerolls, rules);
+                strategy::gained_value(score, rules) + next(&new_state)
+            });
+            fields.fold(Expectation::NEG_INFINITY, Expectation::max)
+        };
+        rolls.hands.iter().map(best_field).collect::<Vec<_>>()
+    };
+
+    let stop = stop_values(state.chips, 0);
+    let mut values = stop.clone();
+    if state.chips > 0 {
+        // One more reroll using a chip, after which we must stop
+        let chip_stop = stop_values(state.chips - 1, -2);
+        let chip = rolls.best(&chip_stop, &rolls.keep_values(&chip_stop));
+        values = values.iter().zip(chip).map(|(&a, b)| a.max(b)).collect();
+    }
+    let depends_on_rerolls = rules.depends_on_rerolls();
+    for rerolls in 1..=state.turn_rerolls(rules) {
+        // Stopping only differs if fields depend on the rerolls used, or they are carried over
+        let level_stop;
+        let stop = match depends_on_rerolls {
+            true => {
+                level_stop = stop_values(state.chips, rerolls);
+                &level_stop
+            }
+            _ => &stop,
+        };
+        values = rolls.best(stop, &rolls.keep_values(&values));
+    }
+    rolls.initial.iter().map(|&(i, p)| p * values[i]).sum()
+}
+
+/// Calculate probabilities of the outcomes of a turn when choosing best, see `solve_turn`
+/// # Arguments
+/// * `state` - state at the beginning of the turn, with at least one field unused
+/// * `rules` - rules to be used
+/// * `rolls` - see `Rolls`
+/// * `next` - expectation value of a state reachable after this turn
+/// # Returns
+/// States after the turn by packed index, see `State::index`, with points gained and probabilities
+pub fn turn_outcomes(
+    state: &State,
+    rules: &rules::Rules,
+    rolls: &Rolls,
+    next: impl Fn(&State) -> Expectation,
+) -> Vec<(StateIndex, Score, Expectation)> {
+    let open = state.open_fields(rules);
+    // Best field of each full hand, preferring later fields on ties as `strategy::choose_field`
+    let stop_choices = |chips, rerolls| {
+        let mut state = state.clone();
+        state.chips = chips;
+        let best_field = |hand: &Hand| {
+            let fields = open.iter().map(|&(column, section, field)| {
+                let (new_state, score) =
+                    strategy::score_field(&state, hand, column, section, field, rerolls, rules);
+                let expectation = strategy::gained_value(score, rules) + next(&new_state);
+                (
+                    expectation,
+                    new_state.index(rules),
+                    score.iter().sum::<Score>(),
+                )
+            });
+            let best = fields.reduce(|best, field| match field.0 >= best.0 {
+                true => field,
+                _ => best,
+            });
+            best.expect("At least one field is unused")
+        };
+        rolls.hands.iter().map(best_field).collect::<Vec<_>>()
+    };
+    let values = |stop: &[(Expectation, StateIndex, Score)]| {
+        stop.iter()
+            .map(|&(expectation, _, _)| expectation)
+            .collect::<Vec<_>>()
+    };
+
+    // Stopping only differs if fields depend on the rerolls used, or they are carried over
+    let turn_rerolls = state.turn_rerolls(rules);
+    let stop = stop_choices(state.chips, 0);
+    let stops: Vec<_> = (0..=turn_rerolls)
+        .map(|rerolls| match rerolls > 0 && rules.depends_on_rerolls() {
+            true => stop_choices(state.chips, rerolls),
+            _ => stop.clone(),
+        })
+        .collect();
+    let chip_stop = (state.chips > 0).then(|| stop_choices(state.chips - 1, -2));
+
+    // Partial hands to keep by rerolls left, without any only using a chip
+    let mut level = values(&stops[0]);
+    let mut chip_choices = vec![None; rolls.hands.len()];
+    if let Some(chip_stop) = &chip_stop {
+        let chip_values = values(chip_stop);
+        let chip = rolls.choose(&chip_values, &rolls.keep_values(&chip_values));
+        let choices = level.iter_mut().zip(chip_choices.iter_mut());
+        for ((value, choice), (chip_value, keep)) in choices.zip(chip) {
+            // Using a chip only to keep all dice is never better
+            if keep.is_some() && chip_value > *value {
+                (*value, *choice) = (chip_value, keep);
+            }
+        }
+    }
+    let mut choices = vec![chip_choices];
+    for stop in &stops[1..] {
+        let keep_values = rolls.keep_values(&level);
+        let (level_values, level_choices) = rolls
+            .choose(&values(stop), &keep_values)
+            .into_iter()
+            .unzip();
+        level = level_values;
+        choices.push(level_choices);
+    }
+
+    // Propagate probabilities of full hands forward, the last ones rolled with a chip
+    let mut outcomes = HashMap::new();
+    let mut add_outcome =
+        |(_, index, gained), p| *outcomes.entry((index, gained)).or_insert(0.0) += p;
+    let mut hands = vec![0.0; rolls.hands.len()];
+    for &(i, p) in &rolls.initial {
+        hands[i] += p;
+    }
+    for (stop, choices) in stops.iter().zip(&choices).rev() {
+        let mut next = vec![0.0; rolls.hands.len()];
+        for ((&p, choice), &stop) in hands.iter().zip(choices).zip(stop) {
+            match choice {
+                _ if p == 0.0 => (),
+                Some(keep) => {
+                    for &(i, q) in &rolls.keeps[*keep] {
+                        next[i] += p * q;
+                    }
+                }
+                None => add_outcome(stop, p),
+            }
+        }
+        hands = next;
+    }
+    if let Some(chip_stop) = chip_stop {
+        for (&p, &stop) in hands.iter().zip(&chip_stop) {
+            if p > 0.0 {
+                add_outcome(stop, p);
+            }
+        }
+    }
+    let outcomes = outcomes.into_iter();
+    outcomes
+        .map(|((index, gained), p)| (index, gained, p))
+        .collect()
+}
+
+/// Calculate probability distribution of the score of a field, when playing a turn for it alone,
+/// maximizing its expected score, e.g. to estimate a game, see `distribution::estimate`
+/// # Arguments
+/// * `section`, `field` - field to score in
+/// * `rules` - rules to be used, without chips or rerolls carried over
+/// # Returns
+/// Probability mass function, indexed by score
+#[cached(
+    key = "(rules::Fingerprint, Rerolls, Section, Field)",
+    convert = r#"{ (rules.fingerprint, rules.rerolls, section, field) }"#
+)]
+pub fn field_distribution(
+    section: Section,
+    field: Field,
+    rules: &rules::Rules,
+) -> Vec<Expectation> {
+    let rolls = rolls(&rules.dice);
+    let rule = &rules.fields[section][field];
+    let scores = |rerolls: Rerolls| {
+        let hands = rolls.hands.iter();
+        hands
+            .map(|hand| rule.score(hand, rules.rerolls - rerolls))
+            .collect::<Vec<_>>()
+    };
+
+    // Choices by rerolls left, stopping without any
+    let stop = |scores: &Vec<Score>| scores.iter().map(|&score| score as Expectation).collect();
+    let mut values: Vec<_> = stop(&scores(0));
+    let mut choices = vec![vec![None; rolls.hands.len()]];
+    for rerolls in 1..=rules.rerolls {
+        let (level_values, level_choices) = rolls
+            .choose(&stop(&scores(rerolls)), &rolls.keep_values(&values))
+            .into_iter()
+            .unzip();
+        values = level_values;
+        choices.push(level_choices);
+    }
+
+    // Propagate probabilities of full hands forward
+    let mut pmf = Vec::new();
+    let mut hands = vec![0.0; rolls.hands.len()];
+    for &(i, p) in &rolls.initial {
+        hands[i] += p;
+    }
+    for (rerolls, choices) in choices.iter().enumerate().rev() {
+        let scores = scores(rerolls as Rerolls);
+        let mut next = vec![0.0; rolls.hands.len()];
+        for ((&p, choice), &score) in hands.iter().zip(choices).zip(&scores) {
+            if p == 0.0 {
+                continue;
+            }
+            match choice {
+                Some(keep) => {
+                    for &(i, q) in &rolls.keeps[*keep] {
+                        next[i] += p * q;
+                    }
+                }
+                None => {
+                    if pmf.len() <= score as usize {
+                        pmf.resize(score as usize + 1, 0.0);
+                    }
+                    pmf[score as usize] += p;
+                }
+            }
+        }
+        hands = next;
+    }
+    pmf
+}
+
+/// Packed indices of all states with a given score card that can be reached from a start state
+/// # Arguments
+/// * `start` - start state
+/// * `used` - score card, with at least the fields of `start` used
+/// * `rules` - rules to be used
+fn indices_with_used(start: &State, used: &ScoreCard, rules: &rules::Rules) -> Vec<StateIndex> {
+    // Upper section scores can only grow by using upper section fields of their column
+    let mut upper_scores = vec![Vec::new()];
+    for (column, &start_score) in start.upper_scores.iter().enumerate() {
+        let column_scores = match used[column][US] == start.used[column][US] {
+            true => start_score..=start_score,
+            _ => start_score..=rules.us_bonus.threshold,
+        };
+        upper_scores = upper_scores
+            .into_iter()
+            .flat_map(|scores| {
+                column_scores
+                    .clone()
+                    .map(move |score| [scores.clone(), vec![score]].concat())
+            })
+            .collect();
+    }
+    // Only mark Yahtzee if it is relevant for a bonus
+    let mut scored_yahtzees = vec![start.scored_yahtzee];
+    let mut columns = start.used.iter().zip(used);
+    if let (Some(yahtzee), false) = (rules.yahtzee_field(), rules.yahtzee_bonus == bonus::NONE) {
+        if columns.any(|(start, used)| start[LS][yahtzee] == 0 && used[LS][yahtzee] > 0) {
+            scored_yahtzees.push(true);
+        }
+    }
+
+    // Rerolls banked can only change at the end of a turn
+    let pools = match used == &start.used {
+        true => start.pool..=start.pool,
+        _ => 0..=rules.carry_over.cap,
+    };
+
+    // Points banked can only grow by using fields, and are only tracked when playing for a target
+    let banked_scores = match (rules.objective.banked_cap(), used == &start.used) {
+        (Some(cap), false) => start.banked..=cap,
+        _ => start.banked..=start.banked,
+    };
+
+    let used_index = State::used_index(used, rules);
+    let mut indices = Vec::new();
+    for upper_scores in &upper_scores {
+        for &scored_yahtzee in &scored_yahtzees {
+            for chips in 0..=start.chips {
+                for pool in pools.clone() {
+                    for banked in banked_scores.clone() {
+                        indices.push(State::pack(
+                            used_index,
+                            upper_scores,
+                            scored_yahtzee,
+                            chips,
+                            pool,
+                            banked,
+                            rules,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    indices
+}
+
+/// All fields of a score card
+fn fields(used: &ScoreCard) -> Vec<(Column, Section, Field)> {
+    let columns = used.iter().enumerate();
+    columns
+        .flat_map(|(column, sections)| {
+            let sections = sections.iter().enumerate();
+            sections.flat_map(move |(section, fields)| {
+                (0..fields.len()).map(move |field| (column, section, field))
+            })
+        })
+        .collect()
+}
+
+/// Visit all score cards with a number of uses added to some fields, one at a time
+/// # Arguments
+/// * `used` - score card to add uses to, restored when done
+/// * `fields` - fields to add uses to
+/// * `turns` - number of uses to add
+/// * `rules` - rules to be used
+/// * `visit` - called with each score card
+fn for_each_card(
+    used: &mut ScoreCard,
+    fields: &[(Column, Section, Field)],
+    turns: usize,
+    rules: &rules::Rules,
+    visit: &mut impl FnMut(&ScoreCard),
+) {
+    if turns == 0 {
+        if !rules.forced_order || strategy::is_in_order(used, rules) {
+            visit(used)
+        }
+        return;
+    }
+    // Without fields left, there is no room for the uses
+    if let Some((&(column, section, field), fields)) = fields.split_first() {
+        let left = (rules.capacity - used[column][section][field]) as usize;
+        for uses in 0..=left.min(turns) {
+            used[column][section][field] += uses as Uses;
+            for_each_card(used, fields, turns - uses, rules, visit);
+            used[column][section][field] -= uses as Uses;
+        }
+    }
+}
+
+/// Solve all states reachable from a start state by playing a number of turns
+/// # Arguments
+/// * `start` - start state
+/// * `turns` - number of turns played since `start`
+/// * `rules` - rules to be used
+/// * `value` - expectation value of each state
+fn layer(
+    start: &State,
+    turns: usize,
+    rules: &rules::Rules,
+    value: impl Fn(&State) -> Expectation + Sync,
+) -> Layer {
+    let mut used = start.used.clone();
+    let mut cards = Vec::new();
+    for_each_card(&mut used, &fields(&start.used), turns, rules, &mut |used| {
+        cards.push(State::used_index(used, rules))
+    });
+    cards.sort_unstable();
+
+    let block = State::card_index_count(rules);
+    let mut expectations = vec![Expectation::NAN; cards.len() * block];
+    let blocks = expectations.par_chunks_mut(block).zip(cards.par_iter());
+    blocks.for_each(|(expectations, &card)| {
+        let first = card * block;
+        let used = State::from_index(first, rules).used;
+        for index in indices_with_used(start, &used, rules) {
+            expectations[index - first] = value(&State::from_index(index, rules));
+        }
+    });
+    Layer {
+        cards,
+        block,
+        expectations,
+    }
+}
+
+/// Most states to hold while solving, see `layer_states` - 4 GiB of expectation values, enough for
+/// Yahtzee Extreme without a Yahtzee bonus
+pub const MAX_LAYER_STATES: StateIndex = 1 << 29;
+
+/// Number of states held at most while solving from a state, in two adjacent layers including all
+/// packed indices of their score cards, see `solve_layers`
+/// # Arguments
+/// * `start` - state to start from
+/// * `rules` - rules to be used
+pub fn layer_states(start: &State, rules: &rules::Rules) -> StateIndex {
+    // Numbers of score cards by turns played, as coefficients of the product of
+    // 1 + x + ... + x^left over all fields, also counting those out of a forced order
+    let mut cards: Vec<StateIndex> = vec![1];
+    for &used in start.used.iter().flatten().flatten() {
+        let left = (rules.capacity - used) as usize;
+        let mut next: Vec<StateIndex> = vec![0; cards.len() + left];
+        for (turns, &count) in cards.iter().enumerate() {
+            for uses in 0..=left {
+                next[turns + uses] = next[turns + uses].saturating_add(count);
+            }
+        }
+        cards = next;
+    }
+    let adjacent = cards.windows(2).map(|pair| pair[0].saturating_add(pair[1]));
+    let cards = adjacent.max().unwrap_or(cards[0]);
+    cards.saturating_mul(State::card_index_count(rules))
+}
+
+/// Solve all turn-start states reachable from a state, layer by layer
+/// Rather than recursing from `start`, states are solved in layers by number of turns played, from
+/// all fields used back to the fields used in `start`. Every state is evaluated exactly once from
+/// the already solved next layer. Only the layer being solved and the next one are held, so memory
+/// is bounded by `layer_states`, which is checked against `MAX_LAYER_STATES` before solving. Each
+/// layer is visited as soon as it is solved, e.g. to persist it, and dropped after the one before.
+/// # Arguments
+/// * `start` - state to start from, e.g. `State::new_from_rules` to solve the entire game
+/// * `rules` - rules to be used
+/// * `visit` - called with each layer solved, beginning with the end of the game and ending with
+///   the layer containing `start`
+/// # Returns
+/// Result - fails if the layers would not fit into memory, or if `visit` fails
+pub fn solve_layers(
+    start: &State,
+    rules: &rules::Rules,
+    mut visit: impl FnMut(&Layer) -> Result<()>,
+) -> Result<()> {
+    let states = layer_states(start, rules);
+    ensure!(
+        states <= MAX_LAYER_STATES,
+        "Solving would hold {} states, at most {} fit into memory",
+        states,
+        MAX_LAYER_STATES
+    );
+    let rolls = rolls(&rules.dice);
+    let turns = start.turns_left(rules);
+
+    // End of game
+    let mut next = layer(start, turns, rules, |state| {
+        strategy::end_of_game_value(state, rules)
+    });
+    visit(&next)?;
+    for turns in (0..turns).rev() {
+        let lookup = |state: &State| {
+            let next = next.get(state.index(rules));
+            next.expect("States reachable after the turn are in the next layer")
+        };
+        next = layer(start, turns, rules, |state| {
+            solve_turn(state, rules, &rolls, lookup)
+        });
+        visit(&next)?;
+    }
+    Ok(())
+}
+
+/// Most states to solve during play for an objective other than expected score, which is not
+/// pre-cached, see `state_count`, so that solving takes seconds at most - with more, turn-start
+/// states are estimated, see `strategy::turn_start_expectation`
+pub const MAX_SOLVED_STATES: StateIndex = 1 << 20;
+
+/// Number of states solved at most from a state, to check beforehand that solving is feasible
+/// # Arguments
+/// * `start` - state to start from
+/// * `rules` - rules to be used
+pub fn state_count(start: &State, rules: &rules::Rules) -> StateIndex {
+    let used = start.used.iter().flatten().flatten();
+    let cards = used.map(|&used| (rules.capacity - used) as StateIndex + 1);
+    let threshold = rules.us_bonus.threshold;
+    let upper = (start.upper_scores.iter()).map(|&score| (threshold - score) as StateIndex + 1);
+    let scored_yahtzees = match rules.yahtzee_bonus == bonus::NONE || start.scored_yahtzee {
+        true => 1,
+        _ => 2,
+    };
+    let banked =
+        (rules.objective.banked_cap()).map_or(1, |cap| (cap - start.banked) as StateIndex + 1);
+    let rest = [
+        scored_yahtzees,
+        start.chips as StateIndex + 1,
+        rules.carry_over.cap as StateIndex + 1,
+        banked,
+    ];
+    cards
+        .chain(upper)
+        .chain(rest)
+        .fold(1, StateIndex::saturating_mul)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::strategy::tests::{simple_rules, very_simple_rules, very_simple_state};
+
+    use float_cmp::approx_eq;
+
+    /// Solve all turn-start states reachable from a state into memory, see `solve_layers`
+    pub fn solve(start: &State, rules: &rules::Rules) -> Table {
+        let mut expectations = vec![Expectation::NAN; State::index_count(rules)];
+        solve_layers(start, rules, |layer| {
+            for (first, block) in layer.blocks() {
+                expectations[first..first + block.len()].copy_from_slice(block);
+            }
+            Ok(())
+        })
+        .unwrap();
+        Table::Memory(expectations)
+    }
+
+    /// Compare against recursive top-down calculation
+    fn assert_matches_recursion(start: &State, table: &Table, rules: &rules::Rules) {
+        let hand = PartialHand(Vec::new());
+        assert!(table.get(start.index(rules)).is_some());
+        for (index, expectation) in table.iter() {
+            let state = State::from_index(index, rules);
+            if state.is_game_over(rules) {
+                assert_eq!(expectation, strategy::end_of_game_value(&state, rules));
+                continue;
+            }
+            let rerolls = state.turn_rerolls(rules) + 1;
+            let recursive = strategy::choose_reroll(&state, &hand, rerolls, rules).expectation;
+            assert!(
+                approx_eq!(Expectation, expectation, recursive, epsilon = 1e-9),
+                "{:?}: {} != {}",
+                state,
+                expectation,
+                recursive
+            );
+        }
+    }
+
+    #[test]
+    fn test_solve_very_simple() {
+        let rules = very_simple_rules();
+        let state = very_simple_state();
+        let table = solve(&state, &rules);
+        assert_eq!(state_count(&state, &rules), 2 * 3 * 3);
+        // 3 chip counts for 1 field unused and used each, but upper section score is always 0
+        assert_eq!(State::index_count(&rules), 2 * 3 * 3);
+        assert_eq!(table.iter().count(), 2 * 3);
+        let mut unused = state.clone();
+        unused.upper_scores = vec![1];
+        assert_eq!(table.get(unused.index(&rules)), None);
+        // Three throws and one more with a chip to throw a 2 -- only fail if all four are 1
+        assert!(approx_eq!(
+            Expectation,
+            table.get(state.index(&rules)).unwrap(),
+            1.0 - 0.5_f64.powi(4)
+        ));
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_solve_rerolls() {
+        // Same short name, but differently configured games must not share caches
+        let mut rules = very_simple_rules();
+        rules.chips = 0;
+        let state = State::new_from_rules(&rules);
+        for rerolls in [0, 1, 3] {
+            rules.rerolls = rerolls;
+            let table = solve(&state, &rules);
+            assert!(approx_eq!(
+                Expectation,
+                table.get(state.index(&rules)).unwrap(),
+                1.0 - 0.5_f64.powi(rerolls as i32 + 1)
+            ));
+            assert_matches_recursion(&state, &table, &rules);
+        }
+    }
+
+    #[test]
+    fn test_solve_simple() {
+        let rules = simple_rules();
+        let state = State::new_from_rules(&rules);
+        let table = solve(&state, &rules);
+        assert!(table.get(state.index(&rules)).is_some());
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_solve_forced_order() {
+        let mut rules = simple_rules();
+        rules.forced_order = true;
+        let state = State::new_from_rules(&rules);
+        let table = solve(&state, &rules);
+        // Fewer choices than with free order
+        let free = solve(&state, &simple_rules());
+        let free = free.get(state.index(&rules)).unwrap();
+        assert!(table.get(state.index(&rules)).unwrap() < free);
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_solve_target() {
+        let mut rules = simple_rules();
+        rules.objective = rules::Objective::Target(8);
+        let state = State::new_from_rules(&rules);
+        let table = solve(&state, &rules);
+        assert!(table.iter().count() <= state_count(&state, &rules));
+        let probability = table.get(state.index(&rules)).unwrap();
+        assert!(probability > 0.0 && probability < 1.0);
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_solve_utility() {
+        // Risk-seeking utility
+        let mut rules = simple_rules();
+        let utility: Vec<_> = (0..=20)
+            .map(|score| (score as Expectation / 20.0).powi(2))
+            .collect();
+        rules.objective = rules::Objective::Win(utility.into());
+        let state = State::new_from_rules(&rules);
+        let table = solve(&state, &rules);
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_solve_by_rerolls() {
+        let mut rules = very_simple_rules();
+        // Throwing a 2 is worth less the more rerolls were used, including chips
+        rules.fields[LS][0].by_rerolls = Some(Arc::new(|hand, rerolls_used| {
+            (hand[0] - 1) as Score * (REROLLS + 2 - rerolls_used) as Score
+        }));
+        let rules = rules.fingerprinted();
+        let state = very_simple_state();
+        let table = solve(&state, &rules);
+        // Stop on the first 2, rerolling up to three times using a chip
+        let expectation = (0..=3).map(|used| (4 - used) as Expectation * 0.5_f64.powi(used + 1));
+        assert!(approx_eq!(
+            Expectation,
+            table.get(state.index(&rules)).unwrap(),
+            expectation.sum()
+        ));
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_solve_capacity() {
+        let mut rules = very_simple_rules();
+        rules.capacity = 3;
+        let state = very_simple_state();
+        // Layers from the end of the game back to the start, 3 chip counts for each of 4 use counts
+        let mut layers = Vec::new();
+        solve_layers(&state, &rules, |layer| {
+            layers.push(layer.clone());
+            Ok(())
+        })
+        .unwrap();
+        let counts = layers.iter().map(|layer| layer.iter().count());
+        assert_eq!(counts.collect::<Vec<_>>(), [3; 4]);
+        for (turns, layer) in layers.iter().rev().enumerate() {
+            let used = layer
+                .iter()
+                .map(|(index, _)| State::from_index(index, &rules).used);
+            assert!(used.into_iter().all(|used| used[0][LS][0] == turns as Uses));
+        }
+        let table = solve(&state, &rules);
+        assert_eq!(table.iter().count(), 4 * 3);
+        // Without chips, every turn scores with the same probability
+        let mut no_chips = state.clone();
+        no_chips.chips = 0;
+        assert!(approx_eq!(
+            Expectation,
+            table.get(no_chips.index(&rules)).unwrap(),
+            3.0 * (1.0 - 0.5_f64.powi(3))
+        ));
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_layer_states() {
+        let mut rules = very_simple_rules();
+        rules.capacity = 3;
+        // Two adjacent layers of a single score card each, with all 3 upper section scores and
+        // 3 chip counts packed
+        assert_eq!(layer_states(&very_simple_state(), &rules), 2 * 3 * 3);
+
+        // Yahtzee Extreme fits into memory, but not with a Yahtzee bonus, which is checked
+        let rules = rules::build_rules(true, bonus::NONE);
+        let state = State::new_from_rules(&rules);
+        assert!(layer_states(&state, &rules) <= MAX_LAYER_STATES);
+        let rules = rules::build_rules(true, bonus::KNIFFEL);
+        let state = State::new_from_rules(&rules);
+        assert!(layer_states(&state, &rules) > MAX_LAYER_STATES);
+        assert!(solve_layers(&state, &rules, |_| unreachable!()).is_err());
+    }
+
+    #[test]
+    fn test_solve_carry_over() {
+        let mut rules = very_simple_rules();
+        rules.capacity = 3;
+        rules.chips = 0;
+        rules.carry_over = rules::CarryOverRules {
+            cap: 2,
+            max_per_turn: 3,
+        };
+        let state = State::new_from_rules(&rules);
+        let table = solve(&state, &rules);
+        // Banking rerolls helps, but not beyond the maximum per turn
+        let mut none = rules.clone();
+        none.carry_over = rules::CarryOverRules::NONE;
+        let without = solve(&state, &none).get(state.index(&none)).unwrap();
+        assert!(approx_eq!(
+            Expectation,
+            without,
+            3.0 * (1.0 - 0.5_f64.powi(3))
+        ));
+        let expectation = table.get(state.index(&rules)).unwrap();
+        assert!(expectation > without && expectation < 3.0 * (1.0 - 0.5_f64.powi(4)));
+        assert_matches_recursion(&state, &table, &rules);
+    }
+
+    #[test]
+    fn test_solve_endgame() {
+        let rules = rules::build_rules(false, bonus::FORCED_JOKER);
+
+        // Only Full House left, but a Yahtzee would score as a joker
+        let mut state = State::new_from_rules(&rules);
+        state.used = vec![[
+            vec![1; rules.fields[US].len()],
+            [vec![1; 2], vec![0], vec![1; 4]].concat(),
+        ]];
+        state.upper_scores = vec![rules.us_bonus.threshold];
+        state.scored_yahtzee = true;
+        let table = solve(&state, &rules);
+        assert_matches_recursion(&state, &table, &rules);
+    }
+}
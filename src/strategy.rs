@@ -1,25 +1,32 @@
+use crate::distribution;
 use crate::global::*;
 use crate::rules;
+use crate::solver::{self, Table};
 use crate::yahtzee_bonus_rules as bonus;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 use cached::proc_macro::cached;
 use float_cmp::approx_eq;
+use once_cell::sync::Lazy;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
 #[cfg(target_pointer_width = "64")]
-type ArchFloat = f64;
+pub type ArchFloat = f64;
 #[cfg(target_pointer_width = "32")]
-type ArchFloat = f32;
+pub type ArchFloat = f32;
 /// Expectation value
-type Expectation = ArchFloat;
+pub type Expectation = ArchFloat;
+/// Packed index of a state, see `State::index`
+pub type StateIndex = usize;
 
 /// Statistical probability
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Probability(ArchFloat);
+pub struct Probability(pub ArchFloat);
 
 // For the tests, only using `approx_eq!` for these probabilities (but not expectation values)
 // Works On My Machine(tm), but similar implementations for expectation values might be required.
@@ -30,21 +37,30 @@ impl PartialEq for Probability {
 }
 
 /// State with everything relevant to strategy
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// Points already banked are not part of the state when maximizing expected score, only the upper
+/// section score as far as it matters for the upper section bonus, see Glenn 2006
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct State {
-    pub score: [Score; 2],
+    /// Upper section score per column, capped at the upper section bonus threshold
+    pub upper_scores: Vec<Score>,
     pub used: ScoreCard,
     pub scored_yahtzee: bool,
     pub chips: Chips,
+    /// Rerolls banked from earlier turns - only tracked when rerolls are carried over
+    pub pool: Rerolls,
+    /// Points banked, capped at the target - only tracked when playing for a target score
+    pub banked: Score,
 }
 
 impl Clone for State {
     fn clone(&self) -> Self {
         State {
-            score: self.score,
+            upper_scores: self.upper_scores.clone(),
             used: self.used.clone(),
             scored_yahtzee: self.scored_yahtzee,
             chips: self.chips,
+            pool: self.pool,
+            banked: self.banked,
         }
     }
 }
@@ -52,34 +68,206 @@ impl Clone for State {
 impl State {
     /// Build initial state from rules
     pub fn new_from_rules(rules: &rules::Rules) -> Self {
+        let column = [
+            vec![0; rules.fields[0].len()],
+            vec![0; rules.fields[1].len()],
+        ];
         State {
-            score: [0, 0],
-            used: [
-                [false].repeat(rules.fields[0].len()),
-                [false].repeat(rules.fields[1].len()),
-            ],
+            upper_scores: vec![0; rules.columns.len()],
+            used: vec![column; rules.columns.len()],
             scored_yahtzee: false,
             chips: rules.chips,
+            pool: 0,
+            banked: 0,
         }
     }
 
-    /// Compact format for cache keys
-    pub fn compact_fmt(&self) -> String {
-        format!(
-            "{},{}{},{}",
-            format!("{},{}", self.score[0], self.score[1]),
-            self.used
-                .iter()
-                .map(|section| section
-                    .iter()
-                    .map(|&field| field as i8)
-                    .fold(String::from(""), |a, b| format!("{}{}", a, b)))
-                .reduce(|a, b| format!("{},{}", a, b))
-                .unwrap(),
-            self.scored_yahtzee as i8,
+    /// Rerolls at the beginning of a turn, including those drawn from the pool
+    /// The pool is only drawn from at the end of the turn, see `score_field`.
+    /// # Arguments
+    /// * `rules` - rules the state belongs to
+    pub fn turn_rerolls(&self, rules: &rules::Rules) -> Rerolls {
+        let drawn = self.pool.min(rules.carry_over.max_per_turn - rules.rerolls);
+        rules.rerolls + drawn.max(0)
+    }
+
+    /// Radices of packed index besides used fields: upper section scores, Yahtzee scored, chips,
+    /// rerolls banked, points banked
+    fn radices(rules: &rules::Rules) -> [StateIndex; 5] {
+        let upper_radix = rules.us_bonus.threshold as StateIndex + 1;
+        [
+            upper_radix.pow(rules.columns.len() as u32),
+            // Only tracked when there are Yahtzee bonus rules
+            if rules.yahtzee_bonus == bonus::NONE {
+                1
+            } else {
+                2
+            },
+            rules.chips as StateIndex + 1,
+            rules.carry_over.cap as StateIndex + 1,
+            rules
+                .objective
+                .banked_cap()
+                .map_or(1, |cap| cap as StateIndex + 1),
+        ]
+    }
+
+    /// Packed index, uses of fields × upper section scores × Yahtzee scored × chips × rerolls
+    /// banked × points banked
+    /// Uses are packed in base `rules.capacity + 1`, the last field most significant, so that with
+    /// a capacity of 1, they form a bitmask.
+    /// # Arguments
+    /// * `rules` - rules the state belongs to
+    /// # Returns
+    /// Index in `0..State::index_count(rules)`
+    pub fn index(&self, rules: &rules::Rules) -> StateIndex {
+        Self::pack(
+            Self::used_index(&self.used, rules),
+            &self.upper_scores,
+            self.scored_yahtzee,
             self.chips,
+            self.pool,
+            self.banked,
+            rules,
         )
     }
+
+    /// Packed uses of fields, the most significant part of `index`
+    /// # Arguments
+    /// * `used` - score card
+    /// * `rules` - rules the score card belongs to
+    pub fn used_index(used: &ScoreCard, rules: &rules::Rules) -> StateIndex {
+        let uses_radix = rules.capacity as StateIndex + 1;
+        let used = used.iter().flatten().flatten().rev();
+        used.fold(0, |mask, &used| mask * uses_radix + used as StateIndex)
+    }
+
+    /// Packed index from the parts of a state, see `index`, without building the state
+    /// # Arguments
+    /// * `used_index` - packed uses of fields, see `used_index`
+    /// * `upper_scores`, `scored_yahtzee`, `chips`, `pool`, `banked` - see architecture of
+    ///   structure above
+    /// * `rules` - rules the state belongs to
+    pub fn pack(
+        used_index: StateIndex,
+        upper_scores: &[Score],
+        scored_yahtzee: bool,
+        chips: Chips,
+        pool: Rerolls,
+        banked: Score,
+        rules: &rules::Rules,
+    ) -> StateIndex {
+        let [upper_radix, yahtzee_radix, chips_radix, pool_radix, banked_radix] =
+            Self::radices(rules);
+        let column_radix = rules.us_bonus.threshold as StateIndex + 1;
+        let upper = upper_scores.iter();
+        let upper = upper.fold(0, |upper, &score| {
+            upper * column_radix + score as StateIndex
+        });
+        let index = used_index * upper_radix + upper;
+        let index = index * yahtzee_radix + scored_yahtzee as StateIndex;
+        let index = index * chips_radix + chips as StateIndex;
+        let index = index * pool_radix + pool as StateIndex;
+        index * banked_radix + banked as StateIndex
+    }
+
+    /// Decode a packed index, see `index`
+    /// # Arguments
+    /// * `index` - index in `0..State::index_count(rules)`
+    /// * `rules` - rules the state belongs to
+    pub fn from_index(index: StateIndex, rules: &rules::Rules) -> Self {
+        let [upper_radix, yahtzee_radix, chips_radix, pool_radix, banked_radix] =
+            Self::radices(rules);
+        debug_assert!(index < Self::index_count(rules));
+        let mut state = Self::new_from_rules(rules);
+        let mut index = index;
+        let mut digit = |radix: StateIndex| {
+            let digit = index % radix;
+            index /= radix;
+            digit
+        };
+        state.banked = digit(banked_radix) as Score;
+        state.pool = digit(pool_radix) as Rerolls;
+        state.chips = digit(chips_radix) as Chips;
+        state.scored_yahtzee = digit(yahtzee_radix) == 1;
+        let mut upper = digit(upper_radix);
+        let column_radix = rules.us_bonus.threshold as StateIndex + 1;
+        for score in state.upper_scores.iter_mut().rev() {
+            *score = (upper % column_radix) as Score;
+            upper /= column_radix;
+        }
+        let uses_radix = rules.capacity as StateIndex + 1;
+        for used in state.used.iter_mut().flatten().flatten() {
+            *used = digit(uses_radix) as Uses;
+        }
+        state
+    }
+
+    /// Fields available to score in, i.e. not used up yet, and in forced order, only the first
+    /// such field of each column
+    /// # Arguments
+    /// * `rules` - rules the state belongs to, for the capacity of fields and forced order
+    pub fn open_fields(&self, rules: &rules::Rules) -> Vec<(Column, Section, Field)> {
+        let columns = self.used.iter().enumerate();
+        columns
+            .flat_map(|(column, sections)| {
+                let sections = sections.iter().enumerate();
+                let open = sections.flat_map(move |(section, fields)| {
+                    let fields = fields.iter().enumerate();
+                    fields
+                        .filter(|(_, &used)| used < rules.capacity)
+                        .map(move |(field, _)| (column, section, field))
+                });
+                open.take(if rules.forced_order { 1 } else { usize::MAX })
+            })
+            .collect()
+    }
+
+    /// Number of turns left, i.e. uses of fields left
+    /// # Arguments
+    /// * `rules` - rules the state belongs to, for the capacity of fields
+    pub fn turns_left(&self, rules: &rules::Rules) -> usize {
+        let used = self.used.iter().flatten().flatten();
+        used.map(|&used| (rules.capacity - used) as usize).sum()
+    }
+
+    /// Decide whether all fields are used up
+    /// # Arguments
+    /// * `rules` - rules the state belongs to, for the capacity of fields
+    pub fn is_game_over(&self, rules: &rules::Rules) -> bool {
+        self.turns_left(rules) == 0
+    }
+
+    /// Number of packed indices, see `index`
+    pub fn index_count(rules: &rules::Rules) -> StateIndex {
+        let fields = rules
+            .fields
+            .iter()
+            .map(|section| section.len())
+            .sum::<usize>()
+            * rules.columns.len();
+        let uses_radix = rules.capacity as StateIndex + 1;
+        Self::card_index_count(rules) * uses_radix.pow(fields as u32)
+    }
+
+    /// Number of packed indices with the same uses of fields, which are consecutive, see `index`
+    pub fn card_index_count(rules: &rules::Rules) -> StateIndex {
+        Self::radices(rules).iter().product()
+    }
+}
+
+/// Decide whether a score card can be reached in forced order, i.e. in each column, all fields
+/// after the first one not used up are unused
+/// # Arguments
+/// * `used` - score card
+/// * `rules` - rules the score card belongs to, for the capacity of fields
+pub fn is_in_order(used: &ScoreCard, rules: &rules::Rules) -> bool {
+    used.iter().all(|column| {
+        let uses = column.iter().flatten();
+        let mut uses = uses.skip_while(|&&used| used == rules.capacity);
+        uses.next();
+        uses.all(|&used| used == 0)
+    })
 }
 
 /// Hash map of all reachable hands and probabilities
@@ -98,7 +286,7 @@ pub struct RerollRecomm {
     pub hand: PartialHand,
     /// State - passed on unchanged
     pub state: State,
-    /// Expectation value when keeping this hand
+    /// Expectation value when keeping this hand, see `rules::Objective`
     pub expectation: Expectation,
 }
 
@@ -106,27 +294,74 @@ pub struct RerollRecomm {
 // TODO should indicate whether this was a bonus
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct FieldRecomm {
+    /// Column to choose
+    pub column: Column,
     /// Section to choose
     pub section: Section,
     /// Field to choose
     pub field: Field,
     /// State after choosing this field
     pub state: State,
-    /// Expectation value when choosing this field
+    /// Points gained in upper and lower section by choosing this field, including bonuses
+    pub score: [Score; 2],
+    /// Expectation value when choosing this field, see `rules::Objective` - when maximizing
+    /// expected score, this is the future score including `score`
     pub expectation: Expectation,
 }
 
 impl Clone for FieldRecomm {
     fn clone(&self) -> Self {
         FieldRecomm {
+            column: self.column,
             section: self.section,
             field: self.field,
             state: self.state.clone(),
+            score: self.score,
             expectation: self.expectation,
         }
     }
 }
 
+/// Number of ordered outcomes per sorted multiset
+type Multiplicity = u64;
+
+/// Sorted multisets of pips rolled with dice of one kind
+/// # Arguments
+/// * `faces` - faces of the dice rolled
+/// * `frequency` - number of dice rolled
+/// # Returns
+/// Pairs of sorted pips and the probability to roll them, i.e. the multinomial coefficient
+/// times the probabilities of the faces
+fn roll_multisets(faces: &Faces, frequency: Frequency) -> Vec<(Hand, ArchFloat)> {
+    // Start with the empty multiset, then decide how many dice show each pip in turn
+    let mut multisets = vec![(Hand::new(), 1.0)];
+    for (i, &(pip, face_probability)) in faces.0.iter().enumerate() {
+        let last = i + 1 == faces.0.len();
+        multisets = multisets
+            .into_iter()
+            .flat_map(|(hand, probability)| {
+                let left = frequency - hand.len() as Frequency;
+                // The last pip must take all dice left
+                let counts = if last { left..=left } else { 0..=left };
+                counts.map(move |count| {
+                    let mut new_hand = hand.clone();
+                    new_hand.extend([pip].repeat(count as usize));
+                    // Choose which of the dice left show this pip
+                    let ways = binomial(left, count) as ArchFloat;
+                    let faces = (face_probability as ArchFloat).powi(count as i32);
+                    (new_hand, probability * ways * faces)
+                })
+            })
+            .collect();
+    }
+    multisets
+}
+
+/// Binomial coefficient
+fn binomial(n: Frequency, k: Frequency) -> Multiplicity {
+    (0..k as Multiplicity).fold(1, |acc, i| acc * (n as Multiplicity - i) / (i + 1))
+}
+
 /// Probability to roll hands given hand
 /// # Arguments
 /// * `have` - partial hand to start with
@@ -134,15 +369,15 @@ impl Clone for FieldRecomm {
 /// # Returns
 /// Probabilities to roll - see architecture of structure above, hands sorted
 #[cached(
-    key = "String",
-    convert = r#"{ format!("{}{}", have.compact_fmt(), rules.short_name ) }"#
+    key = "(PartialHand, rules::Fingerprint)",
+    convert = r#"{ (have.clone(), rules::fingerprint(&rules.dice)) }"#
 )]
 pub fn probability_to_roll(have: PartialHand, rules: &rules::DiceRules) -> ProbabilitiesToRoll {
     // Calculate dice left to use
     let mut leftover = rules.dice.0.to_owned();
     'next_have: for &(have_die, _) in &have.0 {
-        for (left_die, freq) in &mut leftover {
-            if have_die == *left_die {
+        for (left_die, _, freq) in &mut leftover {
+            if have_die == *left_die && *freq > 0 {
                 *freq -= 1;
                 continue 'next_have;
             }
@@ -150,41 +385,63 @@ pub fn probability_to_roll(have: PartialHand, rules: &rules::DiceRules) -> Proba
         panic!("Mismatch between hand and rules");
     }
 
-    // Calculate all possible hands
-    let mut hands = vec![have];
-    for &((min, max), frequency) in &leftover {
-        for _ in 0..frequency {
-            hands = hands
-                .iter()
-                .flat_map(|hand| {
-                    (min..(max + 1)).map(move |pip| {
-                        // Append possible pip to hand
-                        let mut new_hand = hand.clone();
-                        new_hand.0.push(((min, max), pip));
-                        new_hand
-                    })
+    // Calculate all possible hands, as sorted multisets per kind of dice
+    let mut hands = vec![(have, 1.0)];
+    for (die, faces, frequency) in &leftover {
+        let multisets = roll_multisets(faces, *frequency);
+        hands = hands
+            .iter()
+            .flat_map(|(hand, probability)| {
+                multisets.iter().map(move |(pips, multiset_probability)| {
+                    let mut new_hand = hand.clone();
+                    new_hand.0.extend(pips.iter().map(|&pip| (*die, pip)));
+                    (new_hand, probability * multiset_probability)
                 })
-                .collect();
-        }
+            })
+            .collect();
     }
 
-    // Calculate total possible hands by multiplication
-    let total = leftover.iter().fold(1, |total, ((min, max), frequency)| {
-        total * i32::pow((max - min + 1) as i32, *frequency as u32)
-    });
-    let probability_per_hand = 1.0 / total as ArchFloat;
-
     // Sort hands and add up probabilities
     let mut probabilities = HashMap::new();
-    for mut hand in hands {
-        hand.0.sort_unstable_by_key(|&(_, pip)| pip);
-        probabilities.entry(hand).or_insert(Probability(0.0)).0 += probability_per_hand;
+    for (mut hand, probability) in hands {
+        // In canonical order, breaking ties between different dice
+        hand.sort();
+        probabilities.entry(hand).or_insert(Probability(0.0)).0 += probability;
     }
     ProbabilitiesToRoll {
         table: probabilities,
     }
 }
 
+/// Possible choices of dice to keep for rerolling
+/// # Arguments
+/// * `hand` - hand to choose from, assumed to be sorted
+/// # Returns
+/// All distinct sub-multisets of `hand`, preserving order, including empty and full hand
+pub fn possible_keeps(hand: &PartialHand) -> Vec<PartialHand> {
+    let mut possible_keeps = vec![PartialHand(Vec::new())];
+    let mut iter = hand.0.iter().peekable();
+    while let Some(&el) = iter.next() {
+        // Identical dice are interchangeable, so only their count matters
+        let mut count = 1;
+        while iter.peek().map(|&&peek| el == peek).unwrap_or(false) {
+            iter.next();
+            count += 1;
+        }
+        possible_keeps = possible_keeps
+            .into_iter()
+            .flat_map(|keep| {
+                (0..=count).map(move |n| {
+                    let mut keep = keep.clone();
+                    keep.0.extend([el].repeat(n));
+                    keep
+                })
+            })
+            .collect();
+    }
+    possible_keeps
+}
+
 /// Calculate best reroll
 /// # Arguments
 /// * `state` - see architecture of structure above
@@ -194,8 +451,8 @@ pub fn probability_to_roll(have: PartialHand, rules: &rules::DiceRules) -> Proba
 /// # Returns
 /// Reroll recommendation - see architecture of structure above
 #[cached(
-    key = "String",
-    convert = r#"{ format!("{}{}{},{}", state.compact_fmt(), rules.short_name, hand.compact_fmt(), rerolls) }"#
+    key = "(StateIndex, rules::CacheKey, PartialHand, Rerolls)",
+    convert = r#"{ (state.index(rules), rules.cache_key(), hand.clone(), rerolls) }"#
 )]
 pub fn choose_reroll(
     state: &State,
@@ -203,71 +460,206 @@ pub fn choose_reroll(
     rerolls: Rerolls,
     rules: &rules::Rules,
 ) -> RerollRecomm {
+    reroll_alternatives(state, hand, rerolls, rules).remove(0)
+}
+
+/// Sort alternatives by expectation value, best first
+/// Sorting is stable, so alternatives should be passed in order of preference for ties.
+fn sort_alternatives<T>(alternatives: &mut [T], expectation: fn(&T) -> Expectation) {
+    alternatives.sort_by(|a, b| expectation(b).total_cmp(&expectation(a)));
+}
+
+/// Calculate all choices of dice to keep for rerolling
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `have` - hand to start with, assumed to be sorted
+/// * `rerolls` - rerolls left, e.g. three at beginning of turn
+/// * `rules` - rules to be used
+/// # Returns
+/// Reroll recommendations - see architecture of structure above, best first
+pub fn reroll_alternatives(
+    state: &State,
+    hand: &PartialHand,
+    rerolls: Rerolls,
+    rules: &rules::Rules,
+) -> Vec<RerollRecomm> {
     // End of turn or chip used
     if rerolls == 0 || rerolls == -2 {
-        let stop_now = choose_field(state, hand, rules);
+        let stop_now = choose_field(state, hand, rerolls, rules);
+        let mut alternatives = vec![RerollRecomm {
+            hand: hand.clone(),
+            state: stop_now.state,
+            expectation: stop_now.expectation,
+        }];
         // Try chip if we have some left and have not used one already
         if state.chips > 0 && rerolls == 0 {
             let mut chip_off = state.clone();
             chip_off.chips -= 1;
-            let use_chip = choose_reroll(&chip_off, hand, rerolls - 1, rules);
-            if use_chip.expectation > stop_now.expectation {
-                return use_chip;
-            }
+            let use_chip = reroll_alternatives(&chip_off, hand, rerolls - 1, rules);
+            // Keeping all dice would only waste the chip
+            let dice_rules = &rules.dice.dice;
+            alternatives.extend(
+                use_chip
+                    .into_iter()
+                    .filter(|recomm| !recomm.hand.has_full_hand_length(dice_rules)),
+            );
         }
-        return RerollRecomm {
-            hand: hand.clone(),
-            state: stop_now.state,
-            expectation: stop_now.expectation,
-        };
-    }
-
-    struct HandChance {
-        hand: PartialHand,
-        expectation: Expectation,
+        sort_alternatives(&mut alternatives, |recomm| recomm.expectation);
+        return alternatives;
     }
 
     let dice_rules = &rules.dice;
-    let mut possible_hands = vec![PartialHand(Vec::new())];
-    for &el in &hand.0 {
-        possible_hands.extend(
-            possible_hands
-                .clone()
-                .into_iter()
-                .map(|mut hand| {
-                    hand.0.push(el);
-                    hand
-                })
-                .collect::<Vec<_>>(),
-        )
-    }
-    let best = possible_hands
+    let mut possible_keeps = possible_keeps(hand);
+    // Prefer keeping more dice on ties
+    possible_keeps.reverse();
+    let mut alternatives: Vec<_> = possible_keeps
         .into_par_iter()
-        .map(|partial_hand| HandChance {
+        .map(|partial_hand| RerollRecomm {
             hand: partial_hand.clone(),
+            state: state.clone(),
             expectation: if partial_hand.has_full_hand_length(&dice_rules.dice) {
                 // recommendation to stop, no need to recalculate
-                choose_field(state, hand, rules).expectation
+                choose_field(state, hand, rerolls, rules).expectation
             } else {
-                // expectation of this choice is all chances of hands multiplied with their
-                // expecation values summed up
-                probability_to_roll(partial_hand, dice_rules)
-                    .table
-                    .iter()
-                    .map(|(hand, probability)| {
-                        let reroll = choose_reroll(state, hand, rerolls - 1, rules);
-                        probability.0 * reroll.expectation
-                    })
-                    .sum()
+                keep_expectation(state, &partial_hand, rerolls, rules)
             },
         })
-        // need explicit reduce, `Ord` is not implemented for floats
-        .reduce_with(|a, b| if a.expectation > b.expectation { a } else { b })
-        .unwrap();
-    RerollRecomm {
-        hand: best.hand,
-        state: state.clone(),
-        expectation: best.expectation,
+        .collect();
+    sort_alternatives(&mut alternatives, |recomm| recomm.expectation);
+    alternatives
+}
+
+/// Calculate expectation value of keeping a partial hand for rerolling
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `keep` - partial hand to keep, sorted
+/// * `rerolls` - rerolls left before this one, see `choose_reroll`
+/// * `rules` - rules to be used
+/// # Returns
+/// Expectation value, see `rules::Objective`
+pub fn keep_expectation(
+    state: &State,
+    keep: &PartialHand,
+    rerolls: Rerolls,
+    rules: &rules::Rules,
+) -> Expectation {
+    // expectation of this choice is all chances of hands multiplied with their
+    // expecation values summed up
+    probability_to_roll(keep.clone(), &rules.dice)
+        .table
+        .iter()
+        .map(|(hand, probability)| {
+            let reroll = choose_reroll(state, hand, rerolls - 1, rules);
+            probability.0 * reroll.expectation
+        })
+        .sum()
+}
+
+/// Rerolls used in a turn
+/// # Arguments
+/// * `state` - state during the turn
+/// * `rerolls` - rerolls left, see `choose_reroll`, `-2` after using a chip
+/// * `rules` - rules to be used
+pub fn rerolls_used(state: &State, rerolls: Rerolls, rules: &rules::Rules) -> Rerolls {
+    let turn_rerolls = state.turn_rerolls(rules);
+    match rerolls {
+        -2 => turn_rerolls + 1,
+        _ => turn_rerolls - rerolls,
+    }
+}
+
+/// Apply scoring a hand in a field to a state
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `hand` - pips of hand to score, sorted
+/// * `column`, `section`, `field` - field to score in, assumed to be not used up
+/// * `rerolls` - rerolls left when stopping, see `choose_reroll`
+/// * `rules` - rules to be used
+/// # Returns
+/// * State after scoring
+/// * Points gained in upper and lower section, including bonuses and the column multiplier
+pub fn score_field(
+    state: &State,
+    hand: &HandSlice,
+    column: Column,
+    section: Section,
+    field: Field,
+    rerolls: Rerolls,
+    rules: &rules::Rules,
+) -> (State, [Score; 2]) {
+    let fields_rules = &rules.fields;
+    let rerolls_used = rerolls_used(state, rerolls, rules);
+    // Only looked up if it is relevant for a bonus
+    let yahtzee_field = match rules.yahtzee_bonus == bonus::NONE {
+        true => None,
+        _ => rules.yahtzee_field(),
+    };
+    let yahtzee_bonus = state.scored_yahtzee
+        && yahtzee_field
+            .is_some_and(|yahtzee| fields_rules[LS][yahtzee].score(hand, rerolls_used) > 0);
+    let natural = fields_rules[section][field].score(hand, rerolls_used);
+    let (score, bonus) = match yahtzee_bonus {
+        true => {
+            let score_card = &state.used[column];
+            let joker = bonus::joker(rules, score_card, hand, section, field, natural);
+            (rules.yahtzee_bonus.rules)(&joker, section)
+        }
+        _ => (natural, 0),
+    };
+
+    let mut new_state = state.clone();
+    let mut gained = [0, 0];
+    gained[section] += score;
+    gained[LS] += bonus;
+    new_state.used[column][section][field] += 1;
+    if section == US {
+        // Apply upper section bonus of the column as soon as the threshold is crossed
+        let threshold = rules.us_bonus.threshold;
+        let upper_score = state.upper_scores[column] + score;
+        if state.upper_scores[column] < threshold && upper_score >= threshold {
+            gained[US] += rules.us_bonus.bonus;
+        }
+        new_state.upper_scores[column] = upper_score.min(threshold);
+    }
+    let gained = gained.map(|score| score * rules.columns[column]);
+    if score > 0 && section == LS && yahtzee_field == Some(field) {
+        // Mark Yahtzee bonus available
+        new_state.scored_yahtzee = true
+    }
+    if rules.carry_over.cap > 0 {
+        // Draw from the pool for the turn, and bank rerolls not used
+        let drawn = state.turn_rerolls(rules) - rules.rerolls;
+        let pool = state.pool - drawn + rerolls.max(0);
+        new_state.pool = pool.min(rules.carry_over.cap);
+    }
+    if let Some(cap) = rules.objective.banked_cap() {
+        let banked = state.banked + gained.iter().sum::<Score>();
+        new_state.banked = banked.min(cap);
+    }
+    (new_state, gained)
+}
+
+/// Value of points gained by scoring a field, see `rules::Objective`
+/// # Arguments
+/// * `score` - points gained in upper and lower section
+/// * `rules` - rules to be used
+pub fn gained_value(score: [Score; 2], rules: &rules::Rules) -> Expectation {
+    match rules.objective {
+        rules::Objective::Expectation => score.iter().sum::<Score>() as Expectation,
+        // Already accounted for in points banked
+        rules::Objective::Target(_) | rules::Objective::Win(_) => 0.0,
+    }
+}
+
+/// Value of a state at the end of the game, see `rules::Objective`
+/// # Arguments
+/// * `state` - state with all fields used
+/// * `rules` - rules to be used
+pub fn end_of_game_value(state: &State, rules: &rules::Rules) -> Expectation {
+    match &rules.objective {
+        rules::Objective::Expectation => 0.0,
+        rules::Objective::Target(target) => (state.banked >= *target) as u8 as Expectation,
+        rules::Objective::Win(utility) => utility.0[state.banked as usize],
     }
 }
 
@@ -275,157 +667,222 @@ pub fn choose_reroll(
 /// # Arguments
 /// * `state` - see architecture of structure above
 /// * `hand` - hand to work with
+/// * `rerolls` - rerolls left when stopping, see `choose_reroll`
 /// * `rules` - rules to be used
 /// # Returns
 /// Field recommendation - see architecture of structure above
 #[cached(
-    key = "String",
-    convert = r#"{ format!("{}{}{}", state.compact_fmt(), rules.short_name, have.compact_fmt()) }"#
+    key = "(StateIndex, rules::CacheKey, PartialHand, Option<Rerolls>)",
+    convert = r#"{ (
+        state.index(rules),
+        rules.cache_key(),
+        have.clone(),
+        rules.depends_on_rerolls().then_some(rerolls),
+    ) }"#
 )]
-pub fn choose_field(state: &State, have: &PartialHand, rules: &rules::Rules) -> FieldRecomm {
-    let fields_rules = &rules.fields;
+pub fn choose_field(
+    state: &State,
+    have: &PartialHand,
+    rerolls: Rerolls,
+    rules: &rules::Rules,
+) -> FieldRecomm {
+    field_alternatives(state, have, rerolls, rules).remove(0)
+}
 
-    let hand: Hand = have.0.iter().map(|&(_, pip)| pip).collect();
-    let enumerated = state.used.iter().enumerate();
-    let mut available_fields: Vec<_> = enumerated
-        .flat_map(|(section_idx, section)| {
-            let new_state = state.clone();
-            let enumerated = section.iter().enumerate();
-            enumerated.filter_map(move |(field_idx, field)| {
-                // Consider only if field is unused
-                (!field).then(|| FieldRecomm {
-                    section: section_idx,
-                    field: field_idx,
-                    expectation: 0.0,
-                    state: new_state.clone(),
-                })
-            })
+/// Calculate all choices of fields at end of turn
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `hand` - hand to work with
+/// * `rerolls` - rerolls left when stopping, see `choose_reroll`
+/// * `rules` - rules to be used
+/// # Returns
+/// Field recommendations - see architecture of structure above, best first
+pub fn field_alternatives(
+    state: &State,
+    have: &PartialHand,
+    rerolls: Rerolls,
+    rules: &rules::Rules,
+) -> Vec<FieldRecomm> {
+    let mut hand: Hand = have.0.iter().map(|&(_, pip)| pip).collect();
+    hand.sort_unstable();
+    // Consider only if field is not used up
+    let mut available_fields = state.open_fields(rules);
+    // Prefer later fields on ties
+    available_fields.reverse();
+    let mut alternatives: Vec<_> = available_fields
+        .into_par_iter()
+        .map(|(column, section, field)| {
+            evaluate_field(state, &hand, column, section, field, rerolls, rules)
         })
         .collect();
-    let yahtzee_bonus = state.scored_yahtzee
-        && rules.yahtzee_bonus != bonus::NONE
-        && (fields_rules[LS][YAHTZEE_INDEX].function)(&hand) > 0;
-    if available_fields.len() == 1 {
-        // End of game
-        let last_field = available_fields.pop().unwrap();
-        let section = last_field.section;
-        let field = last_field.field;
-
-        let mut final_state = state.clone();
-        let (score, bonus) = match yahtzee_bonus {
-            true => (rules.yahtzee_bonus.rules)(&state.used, hand[0], section, field),
-            _ => ((fields_rules[section][field].function)(&hand), 0),
-        };
-        final_state.score[section] += score;
-        final_state.score[LS] += bonus;
-        // Apply upper section bonus
-        if final_state.score[US] >= rules.us_bonus.threshold {
-            final_state.score[US] += rules.us_bonus.bonus;
-        }
-        final_state.used[section][field] = true;
-
-        let expectation = final_state.score.iter().sum::<Score>() as Expectation;
-        return FieldRecomm {
-            section,
-            field,
-            state: final_state,
-            expectation,
+    sort_alternatives(&mut alternatives, |recomm| recomm.expectation);
+    alternatives
+}
+
+/// Evaluate choosing a field at end of turn
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `hand` - pips of hand to score, sorted
+/// * `column`, `section`, `field` - field to score in, assumed to be not used up
+/// * `rerolls` - rerolls left when stopping, see `choose_reroll`
+/// * `rules` - rules to be used
+/// # Returns
+/// Field recommendation - see architecture of structure above
+pub fn evaluate_field(
+    state: &State,
+    hand: &HandSlice,
+    column: Column,
+    section: Section,
+    field: Field,
+    rerolls: Rerolls,
+    rules: &rules::Rules,
+) -> FieldRecomm {
+    let (new_state, score) = score_field(state, hand, column, section, field, rerolls, rules);
+    let expectation = gained_value(score, rules)
+        + match new_state.is_game_over(rules) {
+            true => end_of_game_value(&new_state, rules),
+            _ => turn_start_expectation(&new_state, rules),
         };
+    FieldRecomm {
+        column,
+        section,
+        field,
+        state: new_state,
+        score,
+        expectation,
     }
-    available_fields
-        .into_par_iter()
-        .map(|option| {
-            let section = option.section;
-            let field = option.field;
+}
 
-            let (score, bonus) = match yahtzee_bonus {
-                true => (rules.yahtzee_bonus.rules)(&state.used, hand[0], section, field),
-                _ => ((fields_rules[section][field].function)(&hand), 0),
-            };
-            let mut new_state = state.clone();
-            new_state.score[section] += score;
-            new_state.score[LS] += bonus;
-            new_state.used[section][field] = true;
-            if score > 0 && section == LS && field == YAHTZEE_INDEX {
-                // Mark Yahtzee bonus available
-                new_state.scored_yahtzee = true
-            }
+/// Key of turn-start tables, see `rules::Rules::cache_key`
+pub type TableKey = rules::CacheKey;
 
-            let hand = PartialHand(Vec::new());
-            let expectation = choose_reroll(&new_state, &hand, REROLLS, rules).expectation;
-            FieldRecomm {
-                section,
-                field,
-                state: new_state,
-                expectation,
-            }
-        })
-        .reduce_with(|a, b| if a.expectation > b.expectation { a } else { b })
-        .unwrap()
+/// Turn-start tables solved by `crate::solver`
+static TABLES: Lazy<RwLock<HashMap<TableKey, Arc<Table>>>> = Lazy::new(Default::default);
+
+/// Look up expectation value at the beginning of a turn from the tables, without calculating it
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `rules` - rules to be used
+/// # Returns
+/// Expectation value, if a table was solved for `state`
+pub fn look_up(state: &State, rules: &rules::Rules) -> Option<Expectation> {
+    let tables = TABLES.read().unwrap();
+    let table = tables.get(&rules.cache_key())?;
+    table.get(state.index(rules))
 }
 
-/// Logic for dumping and restoring caches (necessary parts only, no disk; see crate::caching)
-pub mod persistent_caches {
-    use super::*;
+/// Calculate expectation value at the beginning of a turn, see `rules::Objective`
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `rules` - rules to be used
+/// # Returns
+/// Expectation value, looked up from table if one was solved for `state`, calculated otherwise,
+/// estimated for several columns, see `column_estimate`, and for an objective other than expected
+/// score with too many states to solve, see `objective_estimate`
+pub fn turn_start_expectation(state: &State, rules: &rules::Rules) -> Expectation {
+    if rules.columns.len() > 1 {
+        return column_estimate(state, rules);
+    }
+    if let Some(expectation) = look_up(state, rules) {
+        return expectation;
+    }
+    if rules.objective != rules::Objective::Expectation
+        && solver::state_count(state, rules) > solver::MAX_SOLVED_STATES
+    {
+        return objective_estimate(state, rules);
+    }
+    solve_turn_start(state, rules)
+}
 
-    use cached::Cached;
-    use once_cell::sync::Lazy;
-    use rayon::join;
+/// Calculate expectation value at the beginning of a turn over all rolls at once, see
+/// `solver::solve_turn`, evaluating the states after the turn lazily in turn
+/// Only the states reachable from `state` are evaluated, each once, keyed by packed index, which
+/// includes points banked capped by the objective.
+/// # Arguments
+/// * `state` - see architecture of structure above, with at least one field unused
+/// * `rules` - rules to be used
+#[cached(
+    key = "(StateIndex, rules::CacheKey)",
+    convert = r#"{ (state.index(rules), rules.cache_key()) }"#
+)]
+fn solve_turn_start(state: &State, rules: &rules::Rules) -> Expectation {
+    let rolls = solver::rolls(&rules.dice);
+    solver::solve_turn(state, rules, &rolls, after_turn_expectation(rules))
+}
 
-    /// Caches to be stored
-    #[derive(Debug, PartialEq, Serialize, Deserialize)]
-    pub struct Caches {
-        pub probability_to_roll: HashMap<String, ProbabilitiesToRoll>,
-        pub choose_reroll: HashMap<String, RerollRecomm>,
-        pub choose_field: HashMap<String, FieldRecomm>,
+/// Expectation value of states after a turn, e.g. for `solver::solve_turn`
+/// These recur for many hands, so each is evaluated once per call of this function.
+/// # Arguments
+/// * `rules` - rules to be used
+pub fn after_turn_expectation(rules: &rules::Rules) -> impl Fn(&State) -> Expectation + '_ {
+    let values = RefCell::new(HashMap::new());
+    move |state| {
+        let mut values = values.borrow_mut();
+        let value = values.entry(state.index(rules));
+        *value.or_insert_with(|| match state.is_game_over(rules) {
+            true => end_of_game_value(state, rules),
+            _ => turn_start_expectation(state, rules),
+        })
     }
+}
 
-    pub fn dump_caches() -> Caches {
-        macro_rules! dump {
-            ($cache:ident) => {
-                Lazy::force(&$cache).lock().unwrap().get_store().clone()
+/// Estimate expectation value at the beginning of a turn for an objective other than expected
+/// score, from an estimate of the distribution of future score, see `distribution::estimate`
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `rules` - rules to be used
+fn objective_estimate(state: &State, rules: &rules::Rules) -> Expectation {
+    let estimate = distribution::estimate(state, rules);
+    let cap = rules.objective.banked_cap().unwrap_or(Score::MAX);
+    let mut end = state.clone();
+    let outcomes = estimate.pmf.iter().enumerate();
+    outcomes
+        .map(|(points, p)| {
+            end.banked = (state.banked + points as Score).min(cap);
+            p * end_of_game_value(&end, rules)
+        })
+        .sum()
+}
+
+/// Estimate expectation value at the beginning of a turn with several columns, where solving is
+/// infeasible, as the sum of playing each column on its own, see `rules::Rules::column_rules`
+/// This is only meaningful when maximizing expected score.
+/// # Arguments
+/// * `state` - see architecture of structure above
+/// * `rules` - rules to be used
+fn column_estimate(state: &State, rules: &rules::Rules) -> Expectation {
+    let column_rules = rules.column_rules();
+    let columns = state
+        .used
+        .iter()
+        .zip(&state.upper_scores)
+        .zip(&rules.columns);
+    columns
+        .filter(|((used, _), _)| used.iter().flatten().any(|&used| used < rules.capacity))
+        .map(|((used, &upper_score), &multiplier)| {
+            let column_state = State {
+                upper_scores: vec![upper_score],
+                used: vec![used.clone()],
+                scored_yahtzee: state.scored_yahtzee,
+                chips: 0,
+                pool: 0,
+                banked: 0,
             };
-        }
-        let (probability_to_roll, (choose_reroll, choose_field)) = join(
-            || dump!(PROBABILITY_TO_ROLL),
-            || join(|| dump!(CHOOSE_REROLL), || dump!(CHOOSE_FIELD)),
-        );
-        Caches {
-            probability_to_roll,
-            choose_reroll,
-            choose_field,
-        }
-    }
+            let expectation = turn_start_expectation(&column_state, &column_rules);
+            multiplier as Expectation * expectation
+        })
+        .sum()
+}
+
+/// Logic for dumping and restoring caches (necessary parts only, no disk; see crate::caching)
+pub mod persistent_caches {
+    use super::*;
+
+    /// Caches to be stored, turn-start tables by rules, see `rules::Rules::cache_key`
+    pub type Caches = HashMap<TableKey, Arc<Table>>;
 
     pub fn populate_caches(caches: Caches) {
-        macro_rules! populate {
-            ($cache:ident, $dump:expr) => {
-                let mut locked = Lazy::force(&$cache).lock().unwrap();
-                for (k, v) in $dump {
-                    locked.cache_set(k, v);
-                }
-            };
-        }
-        let (probability_to_roll, choose_reroll, choose_field) = (
-            caches.probability_to_roll,
-            caches.choose_reroll,
-            caches.choose_field,
-        );
-        join(
-            || {
-                populate!(PROBABILITY_TO_ROLL, probability_to_roll);
-            },
-            || {
-                join(
-                    || {
-                        populate!(CHOOSE_REROLL, choose_reroll);
-                    },
-                    || {
-                        populate!(CHOOSE_FIELD, choose_field);
-                    },
-                )
-            },
-        );
+        TABLES.write().unwrap().extend(caches);
     }
 }
 
@@ -440,15 +897,16 @@ pub mod tests {
         rules::Rules {
             short_name: 'y',
             dice: rules::DiceRules {
-                short_name: 'y',
-                dice: Dice(vec![((1, 2), 1)]),
+                dice: Dice::single(COIN, Faces::fair(1, 2), 1),
             },
             chips: 2,
             fields: [
                 Vec::new(),
                 vec![rules::SectionRule {
                     name: "Throw 2".to_string(),
-                    function: |hand| (hand[0] - 1) as Score,
+                    kind: rules::FieldKind::UpperFace(2),
+                    function: Arc::new(|hand| (hand[0] - 1) as Score),
+                    by_rerolls: None,
                 }],
             ],
             us_bonus: rules::USBonusRules {
@@ -456,7 +914,15 @@ pub mod tests {
                 bonus: 0,
             },
             yahtzee_bonus: bonus::NONE,
+            objective: rules::Objective::Expectation,
+            columns: vec![1],
+            rerolls: REROLLS,
+            capacity: 1,
+            forced_order: false,
+            carry_over: rules::CarryOverRules::NONE,
+            fingerprint: 0,
         }
+        .fingerprinted()
     }
 
     /// Very simple state corresponding to [`very_simple_rules()`](very_simple_rules)
@@ -464,31 +930,148 @@ pub mod tests {
         State::new_from_rules(&very_simple_rules())
     }
 
+    /// Simple game rules for testing:
+    /// Two coins, LS has blanks, chance, and Yahtzee
+    /// US bonus of 1 for 1
+    /// Yahtzee bonus is 1, always counts as 4
+    pub fn simple_rules() -> rules::Rules {
+        // Dummy section rule to fill rules before Yahtzee
+        fn dummy_section_rule() -> rules::SectionRule {
+            rules::SectionRule {
+                name: String::from("Dummy"),
+                // Never reached
+                kind: rules::FieldKind::SumThreshold {
+                    min: None,
+                    max: Some(0),
+                },
+                function: Arc::new(|_| 0),
+                by_rerolls: None,
+            }
+        }
+
+        rules::Rules {
+            short_name: 'z',
+            dice: rules::DiceRules {
+                dice: Dice::single(COIN, Faces::fair(1, 2), 2),
+            },
+            chips: 0,
+            fields: [
+                vec![
+                    rules::SectionRule {
+                        name: String::from("Count and Add Only Aces"),
+                        kind: rules::FieldKind::UpperFace(1),
+                        function: Arc::new(|hand| hands::generic_upper_section(1, hand)),
+                        by_rerolls: None,
+                    },
+                    rules::SectionRule {
+                        name: String::from("Count and Add Only Twos"),
+                        kind: rules::FieldKind::UpperFace(2),
+                        function: Arc::new(|hand| hands::generic_upper_section(2, hand)),
+                        by_rerolls: None,
+                    },
+                ],
+                vec![
+                    // Cloning is more complicated
+                    dummy_section_rule(),
+                    dummy_section_rule(),
+                    dummy_section_rule(),
+                    dummy_section_rule(),
+                    rules::SectionRule {
+                        name: String::from("Chance"),
+                        kind: rules::FieldKind::Chance,
+                        function: Arc::new(hands::total),
+                        by_rerolls: None,
+                    },
+                    // Yahtzee field
+                    rules::SectionRule {
+                        name: String::from("All Twos"),
+                        kind: rules::FieldKind::Yahtzee,
+                        function: Arc::new(|hand| if hands::total(hand) == 4 { 4 } else { 0 }),
+                        by_rerolls: None,
+                    },
+                ],
+            ],
+            us_bonus: rules::USBonusRules {
+                threshold: 1,
+                bonus: 1,
+            },
+            yahtzee_bonus: bonus::Rules {
+                short_name: 'z',
+                rules: |_, _| (4, 1),
+            },
+            objective: rules::Objective::Expectation,
+            columns: vec![1],
+            rerolls: REROLLS,
+            capacity: 1,
+            forced_order: false,
+            carry_over: rules::CarryOverRules::NONE,
+            fingerprint: 0,
+        }
+        .fingerprinted()
+    }
+
     #[test]
     fn test_new_state_from_rules() {
         assert_eq!(
             State::new_from_rules(&very_simple_rules()),
             State {
-                score: [0, 0],
-                used: [Vec::new(), vec![false]],
+                upper_scores: vec![0],
+                used: vec![[Vec::new(), vec![0]]],
                 scored_yahtzee: false,
                 chips: 2,
+                pool: 0,
+                banked: 0,
             }
         );
     }
 
     #[test]
-    fn test_compact_fmt_state() {
-        assert_eq!(
-            State {
-                score: [0, 0],
-                used: [vec![false], vec![false, false]],
-                scored_yahtzee: false,
-                chips: 0,
-            }
-            .compact_fmt(),
-            "0,0,0,000,0",
-        );
+    fn test_state_index() {
+        let rules = simple_rules();
+        let mut state = State::new_from_rules(&rules);
+        assert_eq!(state.index(&rules), 0);
+
+        // 8 fields, upper section score up to 1, Yahtzee scored or not, no chips
+        let count = State::index_count(&rules);
+        assert_eq!(count, 256 * 2 * 2);
+
+        state.used[0][US][1] = 1;
+        state.used[0][LS][5] = 1;
+        state.upper_scores = vec![1];
+        state.scored_yahtzee = true;
+        assert_eq!(state.index(&rules), ((0b10000010 * 2) + 1) * 2 + 1);
+
+        state.used = vec![[vec![1; 2], vec![1; 6]]];
+        assert_eq!(state.index(&rules), count - 1);
+
+        // Uses packed in base 3 with each field used up to twice
+        let mut rules = rules;
+        rules.capacity = 2;
+        let mut state = State::new_from_rules(&rules);
+        assert_eq!(State::index_count(&rules), 3_usize.pow(8) * 2 * 2);
+        state.used[0][US][1] = 2;
+        state.used[0][LS][0] = 1;
+        assert_eq!(state.index(&rules), (2 * 3 + 9) * 2 * 2);
+        assert_eq!(state.open_fields(&rules).len(), 7);
+        assert_eq!(state.turns_left(&rules), 16 - 3);
+        state.used = vec![[vec![2; 2], vec![2; 6]]];
+        assert!(state.is_game_over(&rules));
+        assert_eq!(state.index(&rules), State::index_count(&rules) - 4);
+
+        // Decoding inverts packing
+        state.used[0][LS][3] = 1;
+        state.upper_scores = vec![1];
+        assert_eq!(State::from_index(state.index(&rules), &rules), state);
+        let mut rules = very_simple_rules();
+        rules.columns = vec![1, 2];
+        rules.carry_over = rules::CarryOverRules {
+            cap: 2,
+            max_per_turn: 4,
+        };
+        rules.objective = rules::Objective::Target(5);
+        for index in 0..State::index_count(&rules) {
+            assert_eq!(State::from_index(index, &rules).index(&rules), index);
+        }
     }
 
     #[test]
@@ -497,28 +1080,27 @@ pub mod tests {
         // comparing probabilities for equality is okay when comparing eighths
         assert_eq!(
             probability_to_roll(
-                PartialHand(vec![((1, 2), 1)]),
+                PartialHand(vec![(D6, 1)]),
                 &rules::DiceRules {
-                    short_name: 'w',
-                    dice: Dice(vec![((1, 2), 4)]),
+                    dice: Dice::single(COIN, Faces::fair(1, 2), 4),
                 }
             ),
             ProbabilitiesToRoll {
                 table: HashMap::from([
                     (
-                        PartialHand(vec![((1, 2), 1), ((1, 2), 1), ((1, 2), 1), ((1, 2), 1)]),
+                        PartialHand(vec![(D6, 1), (D6, 1), (D6, 1), (D6, 1)]),
                         Probability(0.125),
                     ),
                     (
-                        PartialHand(vec![((1, 2), 1), ((1, 2), 1), ((1, 2), 1), ((1, 2), 2)]),
+                        PartialHand(vec![(D6, 1), (D6, 1), (D6, 1), (D6, 2)]),
                         Probability(0.375),
                     ),
                     (
-                        PartialHand(vec![((1, 2), 1), ((1, 2), 1), ((1, 2), 2), ((1, 2), 2)]),
+                        PartialHand(vec![(D6, 1), (D6, 1), (D6, 2), (D6, 2)]),
                         Probability(0.375),
                     ),
                     (
-                        PartialHand(vec![((1, 2), 1), ((1, 2), 2), ((1, 2), 2), ((1, 2), 2)]),
+                        PartialHand(vec![(D6, 1), (D6, 2), (D6, 2), (D6, 2)]),
                         Probability(0.125),
                     ),
                 ])
@@ -526,26 +1108,174 @@ pub mod tests {
         );
     }
 
+    /// Count ordered outcomes of rolling fair dice by brute force
+    fn count_ordered(dice: &Dice) -> HashMap<PartialHand, Multiplicity> {
+        let mut hands = vec![PartialHand(Vec::new())];
+        for &(die, ref faces, frequency) in &dice.0 {
+            for _ in 0..frequency {
+                hands = hands
+                    .iter()
+                    .flat_map(|hand| {
+                        faces.0.iter().map(move |&(pip, _)| {
+                            let mut new_hand = hand.clone();
+                            new_hand.0.push((die, pip));
+                            new_hand
+                        })
+                    })
+                    .collect();
+            }
+        }
+        let mut counts = HashMap::new();
+        for mut hand in hands {
+            hand.sort();
+            *counts.entry(hand).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_roll_multisets() {
+        assert_eq!(binomial(5, 0), 1);
+        assert_eq!(binomial(5, 2), 10);
+        assert_eq!(binomial(6, 6), 1);
+
+        let (d6, d10) = (Faces::fair(1, 6), Faces::fair(0, 9));
+        for (faces, frequency) in [(&d6, 0), (&d6, 1), (&d6, 5), (&d6, 6), (&d10, 3)] {
+            let multisets = roll_multisets(faces, frequency);
+            let counts = count_ordered(&Dice::single(D6, faces.clone(), frequency));
+            let total = counts.values().sum::<Multiplicity>() as ArchFloat;
+            assert_eq!(multisets.len(), counts.len());
+            for (pips, probability) in multisets {
+                let hand = PartialHand(pips.iter().map(|&pip| (D6, pip)).collect());
+                assert_eq!(
+                    Probability(counts[&hand] as ArchFloat / total),
+                    Probability(probability)
+                );
+            }
+        }
+
+        // Fibonacci die, with 1 on two faces
+        let fibonacci = Faces::weighted([1, 1, 2, 3, 5, 8].map(|pip| (pip, 1.0)));
+        let multisets = roll_multisets(&fibonacci, 2);
+        // 5 pairs and 10 pairs of different pips
+        assert_eq!(multisets.len(), 15);
+        let probability = |pips: Hand| {
+            let (_, probability) = multisets.iter().find(|(hand, _)| *hand == pips).unwrap();
+            Probability(*probability)
+        };
+        assert_eq!(probability(vec![1, 1]), Probability(1.0 / 9.0));
+        assert_eq!(probability(vec![1, 8]), Probability(1.0 / 9.0));
+        assert_eq!(probability(vec![5, 8]), Probability(1.0 / 18.0));
+    }
+
+    /// Dice as in Yahtzee Extreme, with other frequencies
+    fn extreme(d6s: Frequency, d10s: Frequency) -> Dice {
+        Dice(vec![
+            (D6, Faces::fair(1, 6), d6s),
+            (D10, Faces::fair(0, 9), d10s),
+        ])
+    }
+
+    #[test]
+    fn test_probability_to_roll_multinomial() {
+        for (dice, have) in [
+            // Yahtzee Extreme
+            (extreme(5, 1), vec![(D6, 3), (D10, 3)]),
+            // Maxi Yatzy
+            (
+                Dice::single(D6, Faces::fair(1, 6), 6),
+                vec![(D6, 1), (D6, 1)],
+            ),
+            // Heavy on d10
+            (extreme(2, 3), vec![(D10, 9)]),
+        ] {
+            let rules = rules::DiceRules { dice };
+            let mut have = PartialHand(have);
+            for _ in 0..2 {
+                // Compare against brute force with all dice left to roll
+                let mut leftover = rules.dice.clone();
+                for (die, _) in &have.0 {
+                    let (_, _, freq) = leftover.0.iter_mut().find(|(d, _, _)| d == die).unwrap();
+                    *freq -= 1;
+                }
+                let counts = count_ordered(&leftover);
+                let total = counts.values().sum::<Multiplicity>() as ArchFloat;
+                let mut expected = HashMap::new();
+                for (hand, count) in counts {
+                    let mut hand = PartialHand([have.0.clone(), hand.0].concat());
+                    hand.sort();
+                    expected.insert(hand, Probability(count as ArchFloat / total));
+                }
+                assert_eq!(
+                    probability_to_roll(have.clone(), &rules),
+                    ProbabilitiesToRoll { table: expected }
+                );
+                // Again from scratch
+                have = PartialHand(Vec::new());
+            }
+        }
+    }
+
+    #[test]
+    fn test_probability_to_roll_loaded() {
+        // Two coins loaded towards 2, one of them kept
+        let rules = rules::DiceRules {
+            dice: Dice::single(COIN, Faces::weighted([(1, 1.0), (2, 3.0)]), 2),
+        };
+        assert_eq!(
+            probability_to_roll(PartialHand(vec![(COIN, 2)]), &rules),
+            ProbabilitiesToRoll {
+                table: HashMap::from([
+                    (PartialHand(vec![(COIN, 1), (COIN, 2)]), Probability(0.25)),
+                    (PartialHand(vec![(COIN, 2), (COIN, 2)]), Probability(0.75)),
+                ])
+            }
+        );
+        let table = probability_to_roll(PartialHand(Vec::new()), &rules).table;
+        assert_eq!(
+            table[&PartialHand(vec![(COIN, 1), (COIN, 2)])],
+            Probability(0.375)
+        );
+    }
+
     #[test]
     #[should_panic]
     fn test_probability_to_roll_panic() {
         // Running with a mismatch between `have` and `rules` should fail
         probability_to_roll(
-            PartialHand(vec![((1, 6), 1)]),
+            PartialHand(vec![(D6, 1)]),
             &rules::DiceRules {
-                short_name: 'x',
                 dice: Dice(Vec::new()),
             },
         );
     }
 
+    #[test]
+    fn test_possible_keeps() {
+        let distinct = PartialHand([1, 2, 3, 4, 5].iter().map(|&pip| (D6, pip)).collect());
+        assert_eq!(possible_keeps(&distinct).len(), 32);
+
+        // Six counts of Aces times keeping the Two or not
+        let mut aces = PartialHand([(D6, 1)].repeat(5));
+        aces.0.push((D6, 2));
+        let keeps = possible_keeps(&aces);
+        assert_eq!(keeps.len(), 6 * 2);
+        assert!(keeps.contains(&PartialHand(Vec::new())));
+        assert!(keeps.contains(&aces));
+        assert!(keeps.contains(&PartialHand(vec![(D6, 1), (D6, 1), (D6, 2)])));
+
+        // Same pip on different dice is not identical
+        let mixed = PartialHand(vec![(D6, 3), (D10, 3)]);
+        assert_eq!(possible_keeps(&mixed).len(), 4);
+    }
+
     #[test]
     fn test_choose_reroll() {
         let rules = very_simple_rules();
         let mut state = very_simple_state();
 
-        let ready_hand = PartialHand(vec![((1, 2), 2)]);
-        let unready_hand = PartialHand(vec![((1, 2), 1)]);
+        let ready_hand = PartialHand(vec![(COIN, 2)]);
+        let unready_hand = PartialHand(vec![(COIN, 1)]);
         let empty_hand = PartialHand(Vec::new());
 
         // With a reroll and a 2 thrown, no reroll should happen
@@ -575,113 +1305,260 @@ pub mod tests {
     }
 
     #[test]
-    fn test_choose_field() {
-        // Dummy section rule to fill rules before Yahtzee
-        fn dummy_section_rule() -> rules::SectionRule {
-            rules::SectionRule {
-                name: String::from("Dummy"),
-                function: |_| 0,
-            }
-        }
+    fn test_choose_reroll_target() {
+        // One three-sided die, scoring its pips except for 1
+        let mut rules = very_simple_rules();
+        rules.dice = rules::DiceRules {
+            dice: Dice::single(COIN, Faces::fair(1, 3), 1),
+        };
+        rules.chips = 0;
+        rules.fields[LS][0].function =
+            Arc::new(|hand| if hand[0] == 1 { 0 } else { hand[0] as Score });
+        rules = rules.fingerprinted();
+        let state = State::new_from_rules(&rules);
+        let two = PartialHand(vec![(COIN, 2)]);
 
-        // Simple game rules for testing
-        // Two coins, LS has blanks, chance, and Yahtzee
-        // US bonus of 1 for 1
-        // Yahtzee bonus is 1, always counts as 4
-        let simple_rules = rules::Rules {
-            short_name: 'z',
-            dice: rules::DiceRules {
-                short_name: 'z',
-                dice: Dice(vec![((1, 2), 2)]),
-            },
-            chips: 0,
-            fields: [
-                vec![
-                    rules::SectionRule {
-                        name: String::from("Count and Add Only Aces"),
-                        function: |hand| hands::generic_upper_section(1, hand),
-                    },
-                    rules::SectionRule {
-                        name: String::from("Count and Add Only Twos"),
-                        function: |hand| hands::generic_upper_section(2, hand),
-                    },
-                ],
-                vec![
-                    // Cloning is more complicated
-                    dummy_section_rule(),
-                    dummy_section_rule(),
-                    dummy_section_rule(),
-                    dummy_section_rule(),
-                    rules::SectionRule {
-                        name: String::from("Chance"),
-                        function: hands::total,
-                    },
-                    // Yahtzee field
-                    rules::SectionRule {
-                        name: String::from("All Twos"),
-                        function: |hand| if hands::total(hand) == 4 { 4 } else { 0 },
-                    },
-                ],
-            ],
-            us_bonus: rules::USBonusRules {
-                threshold: 1,
-                bonus: 1,
-            },
-            yahtzee_bonus: bonus::Rules {
-                short_name: 'z',
-                rules: |_, _, _, _| (4, 1),
-            },
+        // Keeping 2 is better than 5/3 on average...
+        let rec = choose_reroll(&state, &two, 1, &rules);
+        assert_eq!(rec.hand, two);
+        assert_eq!(rec.expectation, 2.0);
+
+        // ...but only a reroll can reach 3
+        rules.objective = rules::Objective::Target(3);
+        let state = State::new_from_rules(&rules);
+        let rec = choose_reroll(&state, &two, 1, &rules);
+        assert_eq!(rec.hand, PartialHand(Vec::new()));
+        assert_eq!(Probability(rec.expectation), Probability(1.0 / 3.0));
+    }
+
+    #[test]
+    fn test_alternatives() {
+        let rules = very_simple_rules();
+        let state = very_simple_state();
+        let unready_hand = PartialHand(vec![(COIN, 1)]);
+
+        // Without rerolls, use a chip or stop without points
+        let alternatives = reroll_alternatives(&state, &unready_hand, 0, &rules);
+        let expectations: Vec<_> = alternatives.iter().map(|rec| rec.expectation).collect();
+        assert_eq!(expectations, vec![0.5, 0.0]);
+        assert_eq!(alternatives[0].hand, PartialHand(Vec::new()));
+        assert_eq!(alternatives[1].hand, unready_hand);
+
+        // All fields, sorted, the best one chosen
+        let rules = simple_rules();
+        let state = State::new_from_rules(&rules);
+        let pair_of_twos = PartialHand([(COIN, 2)].repeat(2));
+        let alternatives = field_alternatives(&state, &pair_of_twos, 0, &rules);
+        assert_eq!(alternatives.len(), 2 + 6);
+        let mut expectations = alternatives.iter().map(|rec| rec.expectation);
+        let first = expectations.next().unwrap();
+        assert!(expectations
+            .try_fold(first, |a, b| if a >= b { Some(b) } else { None })
+            .is_some());
+        assert_eq!(
+            alternatives[0],
+            choose_field(&state, &pair_of_twos, 0, &rules)
+        );
+    }
+
+    #[test]
+    fn test_columns() {
+        let mut rules = simple_rules();
+        rules.columns = vec![1, 2];
+        let state = State::new_from_rules(&rules);
+        assert_eq!(state.used.len(), 2);
+        assert_eq!(state.open_fields(&rules).len(), 16);
+
+        // Same field in different columns, with multiplier
+        let (first, score) = score_field(&state, &[2, 2], 0, LS, 4, 0, &rules);
+        assert_eq!(score, [0, 4]);
+        let (second, score) = score_field(&state, &[2, 2], 1, LS, 4, 0, &rules);
+        assert_eq!(score, [0, 8]);
+        assert_ne!(first.index(&rules), second.index(&rules));
+        // Upper section bonus per column
+        let (first, score) = score_field(&state, &[1, 2], 1, US, 0, 0, &rules);
+        assert_eq!(score, [2 * (1 + 1), 0]);
+        assert_eq!(first.upper_scores, vec![0, 1]);
+        let (second, score) = score_field(&first, &[1, 2], 0, US, 0, 0, &rules);
+        assert_eq!(score, [1 + 1, 0]);
+        assert_eq!(second.upper_scores, vec![1, 1]);
+        assert!(second.index(&rules) < State::index_count(&rules));
+
+        // Estimated as the columns played on their own
+        let column_rules = rules.column_rules();
+        let column_state = State::new_from_rules(&column_rules);
+        let column = turn_start_expectation(&column_state, &column_rules);
+        assert!(approx_eq!(
+            Expectation,
+            turn_start_expectation(&state, &rules),
+            3.0 * column
+        ));
+        // Chance is worth double in the second column
+        let hand = PartialHand(vec![(COIN, 2); 2]);
+        let alternatives = field_alternatives(&state, &hand, 0, &rules);
+        let chance = |column| {
+            alternatives
+                .iter()
+                .find(|rec| (rec.column, rec.section, rec.field) == (column, LS, 5))
+                .unwrap()
+                .score
         };
+        assert_eq!(chance(0), [0, 4]);
+        assert_eq!(chance(1), [0, 8]);
+    }
+
+    #[test]
+    fn test_forced_order() {
+        let mut rules = simple_rules();
+        rules.forced_order = true;
+        let mut state = State::new_from_rules(&rules);
+        assert_eq!(state.open_fields(&rules), vec![(0, US, 0)]);
+
+        // Twos would score more, but Aces are next
+        let pair_of_twos = PartialHand([(COIN, 2)].repeat(2));
+        let alternatives = field_alternatives(&state, &pair_of_twos, 0, &rules);
+        assert_eq!(alternatives.len(), 1);
+        let rec = choose_field(&state, &pair_of_twos, 0, &rules);
+        assert_eq!((rec.section, rec.field), (US, 0));
+        assert_eq!(rec.score, [0, 0]);
 
-        let pair_of_twos = PartialHand([((1, 2), 2)].repeat(2));
-        let ls_full_except_chance = [[true].repeat(4), vec![false], vec![true]].concat();
+        state.used = vec![[vec![1; 2], vec![1, 0, 0, 0, 0, 0]]];
+        assert!(is_in_order(&state.used, &rules));
+        assert_eq!(state.open_fields(&rules), vec![(0, LS, 1)]);
+        state.used[0][LS][3] = 1;
+        assert!(!is_in_order(&state.used, &rules));
+
+        // Each column in order on its own
+        rules.columns = vec![1, 2];
+        let state = State::new_from_rules(&rules);
+        assert_eq!(state.open_fields(&rules), vec![(0, US, 0), (1, US, 0)]);
+    }
+
+    #[test]
+    fn test_carry_over() {
+        let mut rules = very_simple_rules();
+        rules.capacity = 3;
+        rules.carry_over = rules::CarryOverRules {
+            cap: 3,
+            max_per_turn: 4,
+        };
+        let mut state = State::new_from_rules(&rules);
+        assert_eq!(state.turn_rerolls(&rules), REROLLS);
+
+        // Stopping on the first throw banks both rerolls
+        let (state_after, _) = score_field(&state, &[2], 0, LS, 0, REROLLS, &rules);
+        assert_eq!(state_after.pool, 2);
+        assert_eq!(state_after.turn_rerolls(&rules), 4);
+
+        // Only two rerolls can be drawn, and the cap applies to the rest
+        state.pool = 3;
+        assert_eq!(state.turn_rerolls(&rules), 4);
+        let (state_after, _) = score_field(&state, &[2], 0, LS, 0, 4, &rules);
+        assert_eq!(state_after.pool, 3);
+        let (state_after, _) = score_field(&state, &[2], 0, LS, 0, -2, &rules);
+        assert_eq!(state_after.pool, 1);
+        assert_eq!(rerolls_used(&state, 1, &rules), 3);
+    }
+
+    #[test]
+    fn test_extreme_joker() {
+        let rules = rules::build_rules(true, bonus::FORCED_JOKER);
+        let names: Vec<_> = rules.fields[LS]
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect();
+        let field = |name| names.iter().position(|&n| n == name).unwrap();
+        let mut state = State::new_from_rules(&rules);
+        state.scored_yahtzee = true;
+        // Yahtzee of Fours with the d10 showing 3
+        let hand = [3, 4, 4, 4, 4, 4];
+
+        // Fours must be used first
+        let (_, gained) = score_field(&state, &hand, 0, US, 3, 0, &rules);
+        assert_eq!(gained, [20, bonus::YAHTZEE_BONUS]);
+        let (_, gained) = score_field(&state, &hand, 0, LS, field("Highway"), 0, &rules);
+        assert_eq!(gained, [0, 0]);
+
+        state.used[0][US][3] = 1;
+        let (_, gained) = score_field(&state, &hand, 0, LS, field("Highway"), 0, &rules);
+        assert_eq!(gained, [0, 50 + bonus::YAHTZEE_BONUS]);
+        let (_, gained) = score_field(&state, &hand, 0, LS, field("Two Triples"), 0, &rules);
+        assert_eq!(gained, [0, 45 + bonus::YAHTZEE_BONUS]);
+        let (_, gained) = score_field(&state, &hand, 0, LS, field("Two Pairs"), 0, &rules);
+        assert_eq!(gained, [0, 23 + bonus::YAHTZEE_BONUS]);
+        let (_, gained) = score_field(&state, &hand, 0, LS, field("Super Chance"), 0, &rules);
+        assert_eq!(gained, [0, 46 + bonus::YAHTZEE_BONUS]);
+        let (_, gained) = score_field(&state, &hand, 0, LS, field("10 or less"), 0, &rules);
+        assert_eq!(gained, [0, bonus::YAHTZEE_BONUS]);
+
+        // Scoring the Yahtzee makes the bonus available
+        let state = State::new_from_rules(&rules);
+        let (state, _) = score_field(&state, &hand, 0, LS, field("Yahtzee"), 0, &rules);
+        assert!(state.scored_yahtzee);
+    }
+
+    #[test]
+    fn test_choose_field() {
+        let simple_rules = simple_rules();
+
+        let pair_of_twos = PartialHand([(COIN, 2)].repeat(2));
+        let ls_full_except_chance = [vec![1; 4], vec![0], vec![1]].concat();
 
         // Pair of Twos hits lower expectation value with All Twos, but it is not available,
         // but it also scores higher than Count Aces, so Chance should be used.
         let mut state = State::new_from_rules(&simple_rules);
-        // Some base score out of thin air to ensure it is really added
-        state.score[1] = 1;
-        state.used = [vec![false, true], ls_full_except_chance.clone()];
-        let rec = choose_field(&state, &pair_of_twos, &simple_rules);
+        state.used = vec![[vec![0, 1], ls_full_except_chance.clone()]];
+        let rec = choose_field(&state, &pair_of_twos, 0, &simple_rules);
         assert_eq!(rec.section, LS);
         assert_eq!(rec.field, 4);
-        assert_eq!(rec.state.score[LS], 4 + 1);
-        assert_eq!(rec.state.used[LS][4], true);
+        assert_eq!(rec.score, [0, 4]);
+        assert_eq!(rec.state.used[0][LS][4], 1);
 
         // Pair of Twos hits lower expectation value with All Twos,
         // so it should be used over Chance
-        state.used = [
-            [true].repeat(2),
-            [[true].repeat(4), [false].repeat(2)].concat(),
-        ];
-        let rec = choose_field(&state, &pair_of_twos, &simple_rules);
+        state.used = vec![[vec![1; 2], [vec![1; 4], vec![0; 2]].concat()]];
+        let rec = choose_field(&state, &pair_of_twos, 0, &simple_rules);
         assert_eq!(rec.section, LS);
-        assert_eq!(rec.field, YAHTZEE_INDEX);
-        assert_eq!(rec.state.score[LS], 4 + 1);
-        assert_eq!(rec.state.used[LS][YAHTZEE_INDEX], true);
-        assert_eq!(rec.state.scored_yahtzee, true);
+        let yahtzee = simple_rules.yahtzee_field().unwrap();
+        assert_eq!(rec.field, yahtzee);
+        assert_eq!(rec.score, [0, 4]);
+        assert_eq!(rec.state.used[0][LS][yahtzee], 1);
+        assert!(rec.state.scored_yahtzee);
 
         // Test awardation of upper section bonus
-        state.used = [vec![false, true], [true].repeat(6)];
-        let hand = PartialHand(vec![((1, 2), 1), ((1, 2), 2)]);
-        let rec = choose_field(&state, &hand, &simple_rules);
+        state.used = vec![[vec![0, 1], vec![1; 6]]];
+        let hand = PartialHand(vec![(COIN, 1), (COIN, 2)]);
+        let rec = choose_field(&state, &hand, 0, &simple_rules);
         assert_eq!(rec.section, US);
         assert_eq!(rec.field, 0);
-        assert_eq!(rec.state.score[US], 2);
+        assert_eq!(rec.score, [1 + 1, 0]);
+        assert_eq!(rec.state.upper_scores, vec![1]);
+        assert_eq!(rec.expectation, 2.0);
         // not asserting rec.state.used -- don't care at this point
 
         // Test no awardation of upper section bonus
-        let rec = choose_field(&state, &pair_of_twos, &simple_rules);
+        let rec = choose_field(&state, &pair_of_twos, 0, &simple_rules);
         assert_eq!(rec.section, US);
         assert_eq!(rec.field, 0);
-        assert_eq!(rec.state.score[US], 0);
+        assert_eq!(rec.score, [0, 0]);
+        assert_eq!(rec.state.upper_scores, vec![0]);
+
+        // Test capping of upper section score at threshold, bonus only awarded once
+        state.used = vec![[vec![1, 0], vec![1; 6]]];
+        let rec = choose_field(&state, &pair_of_twos, 0, &simple_rules);
+        assert_eq!(rec.score, [4 + 1, 0]);
+        assert_eq!(rec.state.upper_scores, vec![1]);
+        state.upper_scores = vec![1];
+        let rec = choose_field(&state, &pair_of_twos, 0, &simple_rules);
+        assert_eq!(rec.score, [4, 0]);
+        assert_eq!(rec.state.upper_scores, vec![1]);
 
         // Test awardation of Yahtzee bonus
-        state.used = [[true].repeat(2), ls_full_except_chance.clone()];
+        state.used = vec![[vec![1; 2], ls_full_except_chance.clone()]];
         state.scored_yahtzee = true;
-        let rec = choose_field(&state, &pair_of_twos, &simple_rules);
+        let rec = choose_field(&state, &pair_of_twos, 0, &simple_rules);
         assert_eq!(rec.section, LS);
         assert_eq!(rec.field, 4);
-        assert_eq!(rec.state.score[LS], 4 + 1 + 1);
+        assert_eq!(rec.score, [0, 4 + 1]);
     }
 }
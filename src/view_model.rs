@@ -1,16 +1,38 @@
+use crate::distribution;
 use crate::global::*;
 use crate::rules;
+use crate::solver;
 use crate::strategy;
 
 use anyhow::{anyhow, Result};
 
-/// Recommendation for player, can be to...
-#[derive(Debug, PartialEq)]
+/// Recommendation for player, with expected final score (including points already banked) or
+/// probability to reach the target, see `rules::Objective`, can be to...
+#[derive(Clone, Debug, PartialEq)]
 pub enum Recommendation {
     /// ...reroll a specific hand, or
+    Reroll(PartialHand, strategy::Expectation),
+    /// ...choose a field
+    Field(Column, Section, Field, strategy::Expectation),
+}
+
+impl Recommendation {
+    /// Expected final score or probability, see above
+    pub fn expectation(&self) -> strategy::Expectation {
+        match *self {
+            Recommendation::Reroll(_, expectation)
+            | Recommendation::Field(_, _, _, expectation) => expectation,
+        }
+    }
+}
+
+/// Action actually taken by player, can be to...
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// ...keep a specific hand for rerolling, or
     Reroll(PartialHand),
     /// ...choose a field
-    Field(Section, Field),
+    Field(Column, Section, Field),
 }
 
 /// Stateful ViewModel to adapt strategy and user interfaces
@@ -20,46 +42,292 @@ pub struct ViewModel {
     pub rules: rules::Rules,
     /// Strategy state player is in
     pub state: strategy::State,
+    /// Points banked in upper and lower section
+    pub score: [Score; 2],
     /// Rerolls in ongoing turn
     pub rerolls: Rerolls,
 }
 
 impl ViewModel {
-    /// Recommend an action
+    /// Recommend an action, and advance as if it was taken - see `apply` otherwise
     /// # Arguments
     /// * `hand` - base recommendation on this hand, _not_ assumed to be sorted
     /// # Returns
     /// Recommendation - see architecture of structure above
-    pub fn recommend(&mut self, mut hand: PartialHand) -> Result<Recommendation> {
-        hand.0.sort_unstable_by_key(|&(_, pip)| pip);
-        hand.0.sort_by_key(|&(die, _)| die);
-
-        // This check is also done in `strategy::probability_to_roll`, but it panics instead of
-        // returning a Result to make caching and propagation easier
+    pub fn recommend(&mut self, hand: PartialHand) -> Result<Recommendation> {
+        let hand = self.sort_hand(hand)?;
         let dice_rules = &self.rules.dice.dice;
-        if !hand.is_full_hand(dice_rules) {
-            return Err(anyhow!("Hand does not match selected rules"));
-        }
-
+        let banked = self.banked();
         let reroll_recomm = strategy::choose_reroll(&self.state, &hand, self.rerolls, &self.rules);
         if reroll_recomm.hand.has_full_hand_length(dice_rules) {
-            let field_recomm = strategy::choose_field(&self.state, &hand, &self.rules);
+            let field_recomm =
+                strategy::choose_field(&self.state, &hand, self.rerolls, &self.rules);
             self.state = field_recomm.state;
-            self.rerolls = REROLLS;
+            for (banked, gained) in self.score.iter_mut().zip(field_recomm.score) {
+                *banked += gained;
+            }
+            self.rerolls = self.state.turn_rerolls(&self.rules);
             return Ok(Recommendation::Field(
+                field_recomm.column,
                 field_recomm.section,
                 field_recomm.field,
+                banked + field_recomm.expectation,
             ));
         }
         self.state = reroll_recomm.state;
-        self.rerolls -= 1;
-        Ok(Recommendation::Reroll(reroll_recomm.hand))
+        self.rerolls = next_rerolls(self.rerolls);
+        Ok(Recommendation::Reroll(
+            reroll_recomm.hand,
+            banked + reroll_recomm.expectation,
+        ))
+    }
+
+    /// Apply the action actually taken, and compare it with the recommendation
+    /// # Arguments
+    /// * `hand` - hand the action was taken on, _not_ assumed to be sorted
+    /// * `action` - action taken, a hand to keep is _not_ assumed to be sorted
+    /// # Returns
+    /// * Recommendation - see architecture of structure above
+    /// * Expectation value lost against the recommendation
+    pub fn apply(
+        &mut self,
+        hand: PartialHand,
+        action: Action,
+    ) -> Result<(Recommendation, strategy::Expectation)> {
+        let hand = self.sort_hand(hand)?;
+        let recommendation = self.clone().recommend(hand.clone())?;
+        let banked = self.banked();
+        let expectation = match action {
+            Action::Reroll(mut keep) => {
+                keep.sort();
+                if !is_sub_hand(&keep, &hand) {
+                    return Err(anyhow!("Dice to keep are not in hand"));
+                }
+                if keep.has_full_hand_length(&self.rules.dice.dice) {
+                    return Err(anyhow!("Keeping all dice, choose a field instead"));
+                }
+                let mut state = self.state.clone();
+                match self.rerolls {
+                    -2 => return Err(anyhow!("No rerolls left")),
+                    0 if state.chips == 0 => return Err(anyhow!("No rerolls or chips left")),
+                    0 => state.chips -= 1,
+                    _ => (),
+                }
+                let rerolls = next_rerolls(self.rerolls);
+                // Rerolls left before this one, as with a chip after rerolls are used up
+                let before = rerolls + 1;
+                let expectation = strategy::keep_expectation(&state, &keep, before, &self.rules);
+                self.state = state;
+                self.rerolls = rerolls;
+                expectation
+            }
+            Action::Field(column, section, field) => {
+                let open = self.state.open_fields(&self.rules);
+                if !open.contains(&(column, section, field)) {
+                    return Err(anyhow!("Field is not available"));
+                }
+                let mut pips: Hand = hand.0.iter().map(|&(_, pip)| pip).collect();
+                pips.sort_unstable();
+                let field_recomm = strategy::evaluate_field(
+                    &self.state,
+                    &pips,
+                    column,
+                    section,
+                    field,
+                    self.rerolls,
+                    &self.rules,
+                );
+                self.state = field_recomm.state;
+                for (banked, gained) in self.score.iter_mut().zip(field_recomm.score) {
+                    *banked += gained;
+                }
+                self.rerolls = self.state.turn_rerolls(&self.rules);
+                field_recomm.expectation
+            }
+        };
+        let loss = recommendation.expectation() - (banked + expectation);
+        Ok((recommendation, loss))
+    }
+
+    /// List all alternatives for an action
+    /// # Arguments
+    /// * `hand` - base alternatives on this hand, _not_ assumed to be sorted
+    /// # Returns
+    /// Recommendations - see architecture of structure above, best first, each with the
+    /// expectation value lost against the best one
+    pub fn alternatives(
+        &self,
+        hand: PartialHand,
+    ) -> Result<Vec<(Recommendation, strategy::Expectation)>> {
+        let hand = self.sort_hand(hand)?;
+        let dice_rules = &self.rules.dice.dice;
+        let banked = self.banked();
+        let can_reroll = match self.rerolls {
+            0 => self.state.chips > 0,
+            -2 => false,
+            _ => true,
+        };
+
+        let alternatives: Vec<_> = match can_reroll {
+            true => strategy::reroll_alternatives(&self.state, &hand, self.rerolls, &self.rules)
+                .into_iter()
+                .map(|reroll_recomm| {
+                    let expectation = banked + reroll_recomm.expectation;
+                    if !reroll_recomm.hand.has_full_hand_length(dice_rules) {
+                        return Recommendation::Reroll(reroll_recomm.hand, expectation);
+                    }
+                    // Stopping, with the best field
+                    let field_recomm =
+                        strategy::choose_field(&self.state, &hand, self.rerolls, &self.rules);
+                    Recommendation::Field(
+                        field_recomm.column,
+                        field_recomm.section,
+                        field_recomm.field,
+                        expectation,
+                    )
+                })
+                .collect(),
+            _ => strategy::field_alternatives(&self.state, &hand, self.rerolls, &self.rules)
+                .into_iter()
+                .map(|field_recomm| {
+                    let expectation = banked + field_recomm.expectation;
+                    Recommendation::Field(
+                        field_recomm.column,
+                        field_recomm.section,
+                        field_recomm.field,
+                        expectation,
+                    )
+                })
+                .collect(),
+        };
+        let best = alternatives[0].expectation();
+        Ok(alternatives
+            .into_iter()
+            .map(|recomm| {
+                let loss = best - recomm.expectation();
+                (recomm, loss)
+            })
+            .collect())
+    }
+
+    /// Sort hand and check it against the rules, and that there is a field left to use it
+    fn sort_hand(&self, mut hand: PartialHand) -> Result<PartialHand> {
+        if self.state.is_game_over(&self.rules) {
+            return Err(anyhow!("Game is over"));
+        }
+        hand.sort();
+
+        // This check is also done in `strategy::probability_to_roll`, but it panics instead of
+        // returning a Result to make caching and propagation easier
+        if !hand.is_full_hand(&self.rules.dice.dice) {
+            return Err(anyhow!("Hand does not match selected rules"));
+        }
+        Ok(hand)
+    }
+
+    /// Strategy only knows about future score, add back what is already banked
+    fn banked(&self) -> strategy::Expectation {
+        (match self.rules.objective {
+            rules::Objective::Expectation => self.score.iter().sum::<Score>(),
+            // Banked points are part of the state
+            _ => 0,
+        }) as strategy::Expectation
+    }
+
+    /// Set state at the beginning of a turn, e.g. to continue a game in progress
+    /// # Arguments
+    /// * `state` - strategy state, points banked in it are set from `score`
+    /// * `score` - points banked in upper and lower section
+    pub fn set_state(&mut self, state: strategy::State, score: [Score; 2]) {
+        self.state = state;
+        self.score = score;
+        self.rerolls = self.state.turn_rerolls(&self.rules);
+        self.update_banked();
+    }
+
+    /// Play against an opponent, maximizing probability to win rather than expected score
+    /// The opponent is assumed to maximize their expected score for the rest of the game, which is
+    /// estimated far from its end, see `distribution::MAX_PROPAGATED_STATES`. Recommendations
+    /// evaluate the objective lazily from the state they are made in.
+    /// # Arguments
+    /// * `state` - strategy state of opponent at the beginning of a turn
+    /// * `score` - points banked by opponent in upper and lower section
+    /// # Returns
+    /// Result - probability distribution of final score of opponent, only available with a single
+    /// column
+    pub fn set_opponent(
+        &mut self,
+        state: &strategy::State,
+        score: [Score; 2],
+    ) -> Result<distribution::Distribution> {
+        self.check_single_column()?;
+        let mut rules = self.rules.clone();
+        rules.objective = rules::Objective::Expectation;
+        let mut state = state.clone();
+        state.banked = 0;
+        let banked = score.iter().sum();
+        let opponent = match solver::state_count(&state, &rules) {
+            count if count <= distribution::MAX_PROPAGATED_STATES => {
+                distribution::distribution(&state, &rules)
+            }
+            _ => distribution::estimate(&state, &rules),
+        };
+        let opponent = opponent.shift(banked);
+        self.rules.objective = rules::Objective::Win(opponent.win_utility());
+        self.update_banked();
+        Ok(opponent)
+    }
+
+    /// Update points banked in strategy state after a change of score or objective
+    fn update_banked(&mut self) {
+        let banked = self.score.iter().sum::<Score>();
+        let cap = self.rules.objective.banked_cap();
+        self.state.banked = cap.map_or(0, |cap| banked.min(cap));
+    }
+
+    /// Calculate probability distribution of final score, including points already banked
+    /// # Returns
+    /// Distribution - only available at the beginning of a turn
+    pub fn distribution(&self) -> Result<distribution::Distribution> {
+        self.check_single_column()?;
+        if self.rerolls != self.state.turn_rerolls(&self.rules) {
+            return Err(anyhow!(
+                "Distribution is only available at the beginning of a turn"
+            ));
+        }
+        let banked = self.score.iter().sum();
+        Ok(distribution::distribution(&self.state, &self.rules).shift(banked))
+    }
+
+    /// Check that the game has a single column, as games with several columns are only estimated,
+    /// see `strategy::turn_start_expectation`
+    fn check_single_column(&self) -> Result<()> {
+        match self.rules.columns.len() {
+            1 => Ok(()),
+            _ => Err(anyhow!("Only available for games with a single column")),
+        }
+    }
+}
+
+/// Whether a sorted partial hand can be kept from a sorted hand
+fn is_sub_hand(keep: &PartialHand, hand: &PartialHand) -> bool {
+    let mut hand = hand.0.iter();
+    keep.0.iter().all(|kept| hand.any(|die| die == kept))
+}
+
+/// Rerolls left after a reroll
+fn next_rerolls(rerolls: Rerolls) -> Rerolls {
+    match rerolls {
+        // A chip was used for one more reroll, which is the last
+        0 => -2,
+        _ => rerolls - 1,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::yahtzee_bonus_rules;
 
     #[test]
     fn test_recommend() {
@@ -70,15 +338,17 @@ mod tests {
         let mut view_model = ViewModel {
             rules,
             state: state.clone(),
+            score: [0, 1],
             rerolls: 1,
         };
 
         // This hand does not win points
-        let hand = PartialHand(vec![((1, 2), 1)]);
+        let hand = PartialHand(vec![(COIN, 1)]);
 
         let mut expected_view_model_after_reroll = view_model.clone();
         let mut recommendation = view_model.recommend(hand.clone());
-        let expected_recommendation = Recommendation::Reroll(PartialHand(Vec::new()));
+        // One point banked already, 50:50 chance to get another one
+        let expected_recommendation = Recommendation::Reroll(PartialHand(Vec::new()), 1.5);
         // We should reroll
         assert_eq!(recommendation.unwrap(), expected_recommendation);
         expected_view_model_after_reroll.rerolls = 0;
@@ -89,10 +359,267 @@ mod tests {
 
         recommendation = view_model.recommend(hand);
         // We must use a field now
-        assert_eq!(recommendation.unwrap(), Recommendation::Field(1, 0));
-        expected_state_after_finish.used[1][0] = true;
+        assert_eq!(recommendation.unwrap(), Recommendation::Field(0, 1, 0, 1.0));
+        expected_state_after_finish.used[0][1][0] = 1;
         expected_view_model_after_finish.state = expected_state_after_finish;
+        // No points gained from this hand
+        expected_view_model_after_finish.score = [0, 1];
         expected_view_model_after_finish.rerolls = REROLLS;
         assert_eq!(view_model, expected_view_model_after_finish);
     }
+
+    #[test]
+    fn test_recommend_carry_over() {
+        let mut rules = strategy::tests::very_simple_rules();
+        rules.capacity = 2;
+        rules.carry_over = rules::CarryOverRules {
+            cap: 2,
+            max_per_turn: 4,
+        };
+        let mut view_model = ViewModel {
+            state: strategy::State::new_from_rules(&rules),
+            rules,
+            score: [0, 0],
+            rerolls: REROLLS,
+        };
+
+        // Stopping on the first throw banks the rerolls for the next turn
+        let hand = PartialHand(vec![(COIN, 2)]);
+        let recommendation = view_model.recommend(hand).unwrap();
+        assert!(matches!(recommendation, Recommendation::Field(0, LS, 0, _)));
+        assert_eq!(view_model.state.pool, 2);
+        assert_eq!(view_model.rerolls, REROLLS + 2);
+    }
+
+    #[test]
+    fn test_recommend_chip() {
+        let mut view_model = ViewModel {
+            rules: strategy::tests::very_simple_rules(),
+            state: strategy::tests::very_simple_state(),
+            score: [0, 0],
+            rerolls: 0,
+        };
+        let hand = PartialHand(vec![(COIN, 1)]);
+
+        // Only one chip per turn, no more rerolls after it
+        let recommendation = view_model.recommend(hand).unwrap();
+        assert_eq!(
+            recommendation,
+            Recommendation::Reroll(PartialHand(Vec::new()), 0.5)
+        );
+        assert_eq!(view_model.state.chips, 1);
+        assert_eq!(view_model.rerolls, -2);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut view_model = ViewModel {
+            rules: strategy::tests::very_simple_rules(),
+            state: strategy::tests::very_simple_state(),
+            score: [0, 0],
+            rerolls: 0,
+        };
+        let unready_hand = PartialHand(vec![(COIN, 1)]);
+        let ready_hand = PartialHand(vec![(COIN, 2)]);
+        let empty_hand = PartialHand(Vec::new());
+        let chip = Recommendation::Reroll(empty_hand.clone(), 0.5);
+
+        // Following the recommendation loses nothing
+        let mut following = view_model.clone();
+        let applied = following.apply(unready_hand.clone(), Action::Reroll(empty_hand.clone()));
+        assert_eq!(applied.unwrap(), (chip.clone(), 0.0));
+        assert_eq!(following.state.chips, 1);
+        assert_eq!(following.rerolls, -2);
+        let applied = following.apply(unready_hand.clone(), Action::Reroll(empty_hand.clone()));
+        assert!(applied.is_err());
+        let applied = following.apply(ready_hand.clone(), Action::Field(0, LS, 0));
+        let expected = (Recommendation::Field(0, LS, 0, 1.0), 0.0);
+        assert_eq!(applied.unwrap(), expected);
+        assert_eq!(following.score, [0, 1]);
+
+        // Not using the chip loses the chance to score
+        let applied = view_model.apply(unready_hand.clone(), Action::Field(0, LS, 0));
+        assert_eq!(applied.unwrap(), (chip, 0.5));
+        assert_eq!(view_model.state.chips, 2);
+        assert_eq!(view_model.state.used[0][LS][0], 1);
+        assert_eq!(view_model.rerolls, REROLLS);
+
+        // Invalid actions
+        let applied = view_model.apply(unready_hand.clone(), Action::Field(0, LS, 0));
+        assert!(applied.is_err());
+        view_model.state.used[0][LS][0] = 0;
+        let applied = view_model.apply(unready_hand.clone(), Action::Reroll(ready_hand));
+        assert!(applied.is_err());
+        let applied = view_model.apply(unready_hand.clone(), Action::Reroll(unready_hand.clone()));
+        assert!(applied.is_err());
+
+        // Only the next field in forced order
+        let mut rules = strategy::tests::simple_rules();
+        rules.forced_order = true;
+        let mut view_model = ViewModel {
+            state: strategy::State::new_from_rules(&rules),
+            rules,
+            score: [0, 0],
+            rerolls: 0,
+        };
+        let hand = PartialHand(vec![(COIN, 1), (COIN, 2)]);
+        let applied = view_model.apply(hand.clone(), Action::Field(0, US, 1));
+        assert!(applied.is_err());
+        let applied = view_model.apply(hand, Action::Field(0, US, 0));
+        assert_eq!(applied.unwrap().1, 0.0);
+    }
+
+    #[test]
+    fn test_alternatives() {
+        let rules = strategy::tests::very_simple_rules();
+        let mut state = strategy::tests::very_simple_state();
+        state.chips = 0;
+        let mut view_model = ViewModel {
+            rules,
+            state,
+            score: [0, 1],
+            rerolls: 1,
+        };
+        let hand = PartialHand(vec![(COIN, 1)]);
+
+        // Rerolling is better than stopping by half a point
+        assert_eq!(
+            view_model.alternatives(hand.clone()).unwrap(),
+            vec![
+                (Recommendation::Reroll(PartialHand(Vec::new()), 1.5), 0.0),
+                (Recommendation::Field(0, 1, 0, 1.0), 0.5),
+            ]
+        );
+
+        // Only one field left
+        view_model.rerolls = 0;
+        assert_eq!(
+            view_model.alternatives(hand).unwrap(),
+            vec![(Recommendation::Field(0, 1, 0, 1.0), 0.0)]
+        );
+    }
+
+    #[test]
+    fn test_recommend_target() {
+        let mut rules = strategy::tests::very_simple_rules();
+        rules.objective = rules::Objective::Target(1);
+        let mut state = strategy::tests::very_simple_state();
+        state.chips = 0;
+
+        let mut view_model = ViewModel {
+            rules,
+            state,
+            score: [0, 0],
+            rerolls: 1,
+        };
+
+        // 50:50 chance to reach the target
+        let recommendation = view_model.recommend(PartialHand(vec![(COIN, 1)]));
+        let expected_recommendation = Recommendation::Reroll(PartialHand(Vec::new()), 0.5);
+        assert_eq!(recommendation.unwrap(), expected_recommendation);
+
+        // Target reached
+        let recommendation = view_model.recommend(PartialHand(vec![(COIN, 2)]));
+        assert_eq!(recommendation.unwrap(), Recommendation::Field(0, 1, 0, 1.0));
+        assert_eq!(view_model.state.banked, 1);
+        assert_eq!(view_model.score, [0, 1]);
+    }
+
+    #[test]
+    fn test_set_opponent() {
+        let rules = strategy::tests::very_simple_rules();
+        let mut state = strategy::tests::very_simple_state();
+        state.chips = 0;
+        let mut view_model = ViewModel {
+            rules,
+            state: state.clone(),
+            score: [0, 0],
+            rerolls: REROLLS,
+        };
+
+        // Opponent scores 1 unless all four throws are 1
+        let opponent = view_model.set_opponent(&strategy::tests::very_simple_state(), [0, 0]);
+        let opponent = opponent.unwrap();
+        assert_eq!(opponent.pmf, vec![1.0 / 16.0, 15.0 / 16.0]);
+        let win = [1.0 / 32.0, 17.0 / 32.0];
+
+        // 50:50 chance to throw a 2 with the last throw
+        let hand = PartialHand(vec![(COIN, 1)]);
+        view_model.rerolls = 1;
+        let recommendation = view_model.clone().recommend(hand.clone()).unwrap();
+        let expectation = (win[0] + win[1]) / 2.0;
+        let expected = Recommendation::Reroll(PartialHand(Vec::new()), expectation);
+        assert_eq!(recommendation, expected);
+
+        // With a point banked already, a 2 means a sure win
+        view_model.set_state(state, [0, 1]);
+        assert_eq!(view_model.state.banked, 1);
+        view_model.rerolls = 1;
+        let recommendation = view_model.recommend(hand).unwrap();
+        let expectation = (win[1] + 1.0) / 2.0;
+        let expected = Recommendation::Reroll(PartialHand(Vec::new()), expectation);
+        assert_eq!(recommendation, expected);
+    }
+
+    #[test]
+    fn test_set_opponent_mid_game() {
+        let rules = rules::build_rules(false, yahtzee_bonus_rules::FORCED_JOKER);
+        // Three of a Kind, Four of a Kind and Full House used
+        let mut state = strategy::State::new_from_rules(&rules);
+        state.used = vec![[vec![0; 6], [vec![1; 3], vec![0; 4]].concat()]];
+        let mut view_model = ViewModel {
+            rules,
+            state: state.clone(),
+            score: [0, 0],
+            rerolls: REROLLS,
+        };
+        view_model.set_state(state.clone(), [0, 60]);
+        view_model.rerolls = 0;
+
+        // Estimated this far from the end of the game
+        let opponent = view_model.set_opponent(&state, [0, 70]).unwrap();
+        let total: strategy::Expectation = opponent.pmf.iter().sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!(opponent.mean() > 70.0);
+        assert_eq!(view_model.state.banked, 60);
+
+        // A Yahtzee on the last roll goes into its field
+        let hand = PartialHand(vec![(D6, 6); 5]);
+        let recommendation = view_model.recommend(hand).unwrap();
+        assert!(matches!(recommendation, Recommendation::Field(0, LS, 5, _)));
+        let probability = recommendation.expectation();
+        assert!(probability > 0.0 && probability < 1.0);
+    }
+
+    #[test]
+    fn test_target_objective() {
+        let mut rules = rules::build_rules(false, yahtzee_bonus_rules::FORCED_JOKER);
+        rules.objective = rules::Objective::Target(300);
+        let state = strategy::State::new_from_rules(&rules);
+        let mut view_model = ViewModel {
+            rules,
+            state: state.clone(),
+            score: [0, 0],
+            rerolls: 0,
+        };
+
+        // Far too many states to solve from the start of the game, so later turns are estimated
+        let probability = strategy::turn_start_expectation(&state, &view_model.rules);
+        assert!(probability > 0.0 && probability < 1.0);
+        let hand = PartialHand([1, 2, 3, 4, 6].map(|pip| (D6, pip)).to_vec());
+        let recommendation = view_model.clone().recommend(hand).unwrap();
+        let probability = recommendation.expectation();
+        assert!(probability > 0.0 && probability < 1.0);
+
+        // Only Chance left, which must score at least 22, solved exactly
+        let mut late = state;
+        late.used = vec![[vec![1; 6], [vec![1; 6], vec![0]].concat()]];
+        late.upper_scores = vec![63];
+        view_model.set_state(late, [98, 180]);
+        let rules = &view_model.rules;
+        let probability = strategy::turn_start_expectation(&view_model.state, rules);
+        assert!(probability > 0.0 && probability < 1.0);
+        let table = solver::tests::solve(&view_model.state, rules);
+        assert_eq!(table.get(view_model.state.index(rules)), Some(probability));
+    }
 }
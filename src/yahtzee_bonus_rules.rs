@@ -1,15 +1,30 @@
 use crate::global::*;
+use crate::hands;
+use crate::rules::{self, FieldKind};
+
+use cached::proc_macro::cached;
+
+/// Yahtzee to be scored with a bonus in a field, see `joker`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Joker {
+    /// Score of the hand in the field as is, counting all dice
+    pub natural: Score,
+    /// Score if the field needs the Yahtzee as a joker, see `joker_score`
+    pub joker: Option<Score>,
+    /// The field is the upper section one for the pip of the Yahtzee
+    pub pip_field: bool,
+    /// The upper section field for the pip of the Yahtzee is still open
+    pub upper_open: bool,
+}
 
 /// Rules function for Yahtzee bonus
 /// # Arguments
-/// * Score card
-/// * Pip that the Yahtzee was (e.g. a Yahtzee of Fives)
+/// * Yahtzee to be scored in a field (assumed to be free)
 /// * Section where score is applied
-/// * Field where score is applied within section (assumed to be free)
 /// # Returns
-/// * Score awarded in specified field
+/// * Score awarded in the field
 /// * Bonus awarded
-pub type RulesFn = fn(&ScoreCard, Pip, Section, Field) -> (Score, Score);
+pub type RulesFn = fn(&Joker, Section) -> (Score, Score);
 
 /// Rules for Yahtzee bonus
 #[derive(Clone)]
@@ -29,17 +44,89 @@ impl PartialEq for Rules {
 /// Score of a Yahtzee bonus
 pub const YAHTZEE_BONUS: Score = 100;
 
-/// Scores of fields where Yahtzee might be used as a joker
-/// (Full House, Small & Large Straight)
-const JOKER_FIELDS: [Option<Score>; 7] = [
-    None,
-    None,
-    Some(FULL_HOUSE_SCORE),
-    Some(SMALL_STRAIGHT_SCORE),
-    Some(LARGE_STRAIGHT_SCORE),
-    None,
-    None,
-];
+/// Score of a lower section field if it is the same for every hand scoring in it, e.g. 25 for
+/// Full House in regular Yahtzee, see `joker_score`
+/// # Arguments
+/// * `rules` - rules of the game
+/// * `field` - lower section field
+#[cached(
+    key = "(rules::CacheKey, Field)",
+    convert = r#"{ (rules.cache_key(), field) }"#
+)]
+pub fn fixed_score(rules: &rules::Rules, field: Field) -> Option<Score> {
+    let function = &rules.fields[LS][field].function;
+    let hands = rules.full_hands();
+    let mut scores = hands
+        .iter()
+        .map(|hand| function(hand))
+        .filter(|&score| score > 0);
+    let first = scores.next()?;
+    scores.all(|score| score == first).then_some(first)
+}
+
+/// Score of a Yahtzee used as a joker in a lower section field, by kind of field
+/// Full House, straights and other groups score the fixed score of the field, e.g. as specified in
+/// the rules or in a TOML card. Fields scoring by the dice, e.g. Two Pairs in Extreme or Full
+/// House in Yatzy, count all dice instead.
+/// # Arguments
+/// * `rules` - rules of the game
+/// * `field` - lower section field
+/// * `hand` - sorted, containing a Yahtzee
+/// # Returns
+/// Score, or `None` if the field does not need a joker for the Yahtzee
+pub fn joker_score(rules: &rules::Rules, field: Field, hand: &HandSlice) -> Option<Score> {
+    match rules.fields[LS][field].kind {
+        FieldKind::FullHouse
+        | FieldKind::Straight(_)
+        | FieldKind::ExactStraight { .. }
+        | FieldKind::Groups(_) => {
+            Some(fixed_score(rules, field).unwrap_or_else(|| hands::total(hand)))
+        }
+        _ => None,
+    }
+}
+
+/// Pip that a Yahtzee was, the most frequent one, e.g. the d6 in Extreme while the d10 differs
+/// # Arguments
+/// * `hand` - sorted, containing a Yahtzee
+pub fn yahtzee_pip(hand: &HandSlice) -> Pip {
+    let mut best = (0, hand[0]);
+    for chunk in hand.chunk_by(|a, b| a == b) {
+        if chunk.len() > best.0 {
+            best = (chunk.len(), chunk[0]);
+        }
+    }
+    best.1
+}
+
+/// Yahtzee to be scored in a field, looking up fields by kind
+/// # Arguments
+/// * `rules` - rules of the game
+/// * `score_card` - column where the score is applied
+/// * `hand` - sorted, containing a Yahtzee
+/// * `section`, `field` - field where the score is applied
+/// * `natural` - score of the hand in the field as is
+pub fn joker(
+    rules: &rules::Rules,
+    score_card: &ScoreColumn,
+    hand: &HandSlice,
+    section: Section,
+    field: Field,
+    natural: Score,
+) -> Joker {
+    let pip = yahtzee_pip(hand);
+    let upper_field = rules.upper_field(pip);
+    Joker {
+        natural,
+        joker: match section {
+            US => None,
+            _ => joker_score(rules, field, hand),
+        },
+        pip_field: section == US && upper_field == Some(field),
+        // Without such a field, e.g. for a Yahtzee of Ones in Balut, it cannot be used first
+        upper_open: upper_field.is_some_and(|upper| score_card[US][upper] == 0),
+    }
+}
 
 /// Array of all variants
 pub const ALL_VARIANTS: [Rules; 5] = [FORCED_JOKER, FREE_JOKER, ORIGINAL, KNIFFEL, NONE];
@@ -49,21 +136,20 @@ pub const ALL_VARIANTS_NAMES: [&str; 5] = ["forced", "free", "original", "kniffe
 /// Forced Joker rules, used in regular Yahtzee
 pub const FORCED_JOKER: Rules = Rules {
     short_name: 'a',
-    rules: |score_card, pip, section, field| {
+    rules: |yahtzee, section| {
         match section {
-            US => match pip {
-                // `field` is zero-indexed
-                pip if pip as usize == field + 1 => ((YAHTZEE_SIZE * pip) as Score, YAHTZEE_BONUS),
+            US => match yahtzee.pip_field {
+                true => (yahtzee.natural, YAHTZEE_BONUS),
                 _ => (0, YAHTZEE_BONUS),
             },
-            _ => match score_card[US][pip as usize - 1] {
+            _ => match yahtzee.upper_open {
                 // Upper section unused, not allowed to use, zeroing
-                false => (0, 0),
-                _ => match JOKER_FIELDS[field] {
+                true => (0, 0),
+                _ => match yahtzee.joker {
                     // Joker
                     Some(score) => (score, YAHTZEE_BONUS),
                     // Count all
-                    None => ((YAHTZEE_SIZE * pip) as Score, YAHTZEE_BONUS),
+                    None => (yahtzee.natural, YAHTZEE_BONUS),
                 },
             },
         }
@@ -73,19 +159,19 @@ pub const FORCED_JOKER: Rules = Rules {
 /// Free Joker rules, a popular alternative
 pub const FREE_JOKER: Rules = Rules {
     short_name: 'b',
-    rules: |score_card, pip, section, field| {
+    rules: |yahtzee, section| {
         match section {
-            US => match pip {
-                pip if pip as usize == field + 1 => ((YAHTZEE_SIZE * pip) as Score, YAHTZEE_BONUS),
+            US => match yahtzee.pip_field {
+                true => (yahtzee.natural, YAHTZEE_BONUS),
                 _ => (0, YAHTZEE_BONUS),
             },
-            _ => match JOKER_FIELDS[field] {
-                Some(score) => match score_card[US][pip as usize - 1] {
+            _ => match yahtzee.joker {
+                Some(score) => match yahtzee.upper_open {
                     // Upper section unused, not allowed to use joker
-                    false => (0, 0),
+                    true => (0, 0),
                     _ => (score, YAHTZEE_BONUS),
                 },
-                None => ((YAHTZEE_SIZE * pip) as Score, YAHTZEE_BONUS),
+                None => (yahtzee.natural, YAHTZEE_BONUS),
             },
         }
     },
@@ -94,16 +180,16 @@ pub const FREE_JOKER: Rules = Rules {
 /// Original 1956 rules
 pub const ORIGINAL: Rules = Rules {
     short_name: 'c',
-    rules: |_, pip, section, field| {
+    rules: |yahtzee, section| {
         match section {
             // Upper section cannot be used
-            US => match pip {
-                pip if pip as usize == field + 1 => ((YAHTZEE_SIZE * pip) as Score, 0),
+            US => match yahtzee.pip_field {
+                true => (yahtzee.natural, 0),
                 _ => (0, 0),
             },
-            _ => match JOKER_FIELDS[field] {
+            _ => match yahtzee.joker {
                 Some(score) => (score, YAHTZEE_BONUS),
-                None => ((YAHTZEE_SIZE * pip) as Score, YAHTZEE_BONUS),
+                None => (yahtzee.natural, YAHTZEE_BONUS),
             },
         }
     },
@@ -112,28 +198,40 @@ pub const ORIGINAL: Rules = Rules {
 /// Kniffel rules, as published in German-speaking countries
 pub const KNIFFEL: Rules = Rules {
     short_name: 'd',
-    rules: |score_card, pip, section, field| {
+    rules: |yahtzee, section| {
         match section {
-            US => match pip {
-                pip if pip as usize == field + 1 => ((YAHTZEE_SIZE * pip) as Score, YAHTZEE_SCORE),
+            US => match yahtzee.pip_field {
+                true => (yahtzee.natural, YAHTZEE_SCORE),
                 _ => (0, YAHTZEE_SCORE),
             },
-            _ => match score_card[US][pip as usize - 1] {
-                false => (0, 0),
-                _ => match JOKER_FIELDS[field] {
+            _ => match yahtzee.upper_open {
+                true => (0, 0),
+                _ => match yahtzee.joker {
                     // No joker
                     Some(_) => (0, 0),
-                    None => ((YAHTZEE_SIZE * pip) as Score, YAHTZEE_SCORE),
+                    None => (yahtzee.natural, YAHTZEE_SCORE),
                 },
             },
         }
     },
 };
 
-/// No Yahtzee bonus, Yahtzee extreme
+/// Score of a Double Generala, see `DOUBLE_GENERALA`
+pub const DOUBLE_GENERALA_BONUS: Score = 100;
+
+/// Double Generala rules, used in Generala
+/// Traditionally, a second Generala is written once in its own category. Here, it is awarded as
+/// a bonus for every further Generala, with the hand scored as is in any field, like the
+/// Yahtzee bonus.
+pub const DOUBLE_GENERALA: Rules = Rules {
+    short_name: 'f',
+    rules: |generala, _| (generala.natural, DOUBLE_GENERALA_BONUS),
+};
+
+/// No Yahtzee bonus, Yahtzee Extreme unless combined with other rules
 pub const NONE: Rules = Rules {
     short_name: 'e',
-    rules: |_, _, _, _| {
+    rules: |_, _| {
         panic!("Rules NONE should not be applied");
     },
 };
@@ -142,42 +240,136 @@ pub const NONE: Rules = Rules {
 mod tests {
     use super::*;
 
-    fn have_yahtzee() -> ScoreCard {
-        let mut have_yahtzee = [vec![false; US_LENGTH], vec![false; LS_LENGTH]];
-        have_yahtzee[LS][YAHTZEE_INDEX] = true;
-        have_yahtzee
+    use crate::strategy;
+
+    use std::sync::Arc;
+
+    /// Yahtzee of Aces, scoring 5 as is or `joker` if used as one, with Aces still open or not
+    fn aces(joker: Option<Score>, upper_open: bool) -> Joker {
+        Joker {
+            natural: 5,
+            joker,
+            pip_field: false,
+            upper_open,
+        }
+    }
+
+    /// Yahtzee of Aces scored as Aces
+    fn in_aces() -> Joker {
+        Joker {
+            pip_field: true,
+            ..aces(None, true)
+        }
     }
 
     fn test_generic_upper_section(rules: RulesFn, bonus: Score) {
         // Upper section should award points when available
-        assert_eq!(rules(&have_yahtzee(), 1, 0, 0), (5, bonus));
+        assert_eq!(rules(&in_aces(), US), (5, bonus));
 
         // Attempt bonus in upper section for wrong field, should not award points but give bonus
-        assert_eq!(rules(&have_yahtzee(), 1, 0, 1), (0, bonus));
+        assert_eq!(rules(&aces(None, true), US), (0, bonus));
     }
 
-    fn test_generic_lower_section(rules: RulesFn, bonus: Score) -> ScoreCard {
+    fn test_generic_lower_section(rules: RulesFn, bonus: Score) {
         // Attempt score in lower section when upper section is still available,
         // should not award points
-        assert_eq!(rules(&have_yahtzee(), 1, 1, 0), (0, 0));
+        assert_eq!(rules(&aces(None, true), LS), (0, 0));
 
         // Lower section should award points when upper section is full
-        let mut upper_section_used = have_yahtzee();
-        upper_section_used[US][0] = true;
-        assert_eq!(rules(&upper_section_used, 1, 1, 0), (5, bonus));
+        assert_eq!(rules(&aces(None, false), LS), (5, bonus));
+    }
+
+    #[test]
+    fn test_joker() {
+        let rules = rules::build_rules(false, FORCED_JOKER);
+        let mut score_card = [vec![0; US_LENGTH], vec![0; LS_LENGTH]];
+        let hand = [3, 3, 3, 3, 3];
+        let yahtzee = joker(&rules, &score_card, &hand, US, 2, 15);
+        assert!(yahtzee.pip_field && yahtzee.upper_open);
+        assert_eq!(yahtzee.joker, None);
 
-        // Return for use afterwards
-        upper_section_used
+        // Full House and straights are found by kind
+        score_card[US][2] = 1;
+        let yahtzee = joker(&rules, &score_card, &hand, LS, 2, 0);
+        assert!(!yahtzee.pip_field && !yahtzee.upper_open);
+        assert_eq!(yahtzee.joker, Some(FULL_HOUSE_SCORE));
+        assert_eq!(joker(&rules, &score_card, &hand, LS, 4, 0).joker, Some(40));
+        assert_eq!(joker(&rules, &score_card, &hand, LS, 6, 15).joker, None);
+    }
+
+    #[test]
+    fn test_joker_score() {
+        let rules = rules::build_rules(false, FORCED_JOKER);
+        let hand = [1, 1, 1, 1, 1];
+        let field = |kind| rules.fields[LS].iter().position(|field| field.kind == kind);
+        let full_house = field(FieldKind::FullHouse).unwrap();
+        assert_eq!(fixed_score(&rules, full_house), Some(FULL_HOUSE_SCORE));
+        assert_eq!(
+            joker_score(&rules, full_house, &hand),
+            Some(FULL_HOUSE_SCORE)
+        );
+        let large_straight = field(FieldKind::Straight(5)).unwrap();
+        assert_eq!(
+            joker_score(&rules, large_straight, &hand),
+            Some(LARGE_STRAIGHT_SCORE)
+        );
+        assert_eq!(
+            joker_score(&rules, field(FieldKind::Chance).unwrap(), &hand),
+            None
+        );
+        let three_of_a_kind = field(FieldKind::NOfAKind(3)).unwrap();
+        assert_eq!(fixed_score(&rules, three_of_a_kind), None);
+        assert_eq!(joker_score(&rules, three_of_a_kind, &hand), None);
+
+        // Fixed score of the field as specified, e.g. in a TOML card
+        let mut custom = rules.clone();
+        custom.fields[LS][full_house].function =
+            Arc::new(|hand| hands::generic_identical(vec![2, 3], |_| 28, hand));
+        custom = custom.fingerprinted();
+        assert_eq!(joker_score(&custom, full_house, &hand), Some(28));
+        // Scoring by the dice, as in Yatzy
+        custom.fields[LS][full_house].function =
+            Arc::new(|hand| hands::generic_identical(vec![2, 3], hands::total, hand));
+        custom = custom.fingerprinted();
+        assert_eq!(fixed_score(&custom, full_house), None);
+        assert_eq!(joker_score(&custom, full_house, &hand), Some(5));
+
+        // Extreme, with the d10 differing
+        let rules = rules::build_rules(true, FORCED_JOKER);
+        let field = |kind| rules.fields[LS].iter().position(|field| field.kind == kind);
+        let hand = [3, 4, 4, 4, 4, 4];
+        assert_eq!(yahtzee_pip(&hand), 4);
+        let highway = field(FieldKind::Straight(6)).unwrap();
+        assert_eq!(joker_score(&rules, highway, &hand), Some(HIGHWAY_SCORE));
+        let two_triples = field(FieldKind::Groups(vec![3, 3])).unwrap();
+        assert_eq!(
+            joker_score(&rules, two_triples, &hand),
+            Some(TWO_TRIPLES_SCORE)
+        );
+        let two_pairs = field(FieldKind::Groups(vec![2, 2])).unwrap();
+        assert_eq!(joker_score(&rules, two_pairs, &hand), Some(23));
+        assert_eq!(yahtzee_pip(&[0, 2, 2, 2, 2, 2]), 2);
+
+        // Yatzy, with both straights of five dice
+        let rules = rules::build_yatzy_rules(false);
+        let field = |kind| rules.fields[LS].iter().position(|field| field.kind == kind);
+        let straight = |lowest, highest| FieldKind::ExactStraight { lowest, highest };
+        let small_straight = field(straight(1, 5)).unwrap();
+        let large_straight = field(straight(2, 6)).unwrap();
+        assert_eq!(field(FieldKind::Straight(5)), None);
+        assert_eq!(fixed_score(&rules, small_straight), Some(15));
+        assert_eq!(fixed_score(&rules, large_straight), Some(20));
+        assert_eq!(joker_score(&rules, large_straight, &hand), Some(20));
     }
 
     #[test]
     fn test_forced_joker() {
         test_generic_upper_section(FORCED_JOKER.rules, YAHTZEE_BONUS);
-        let upper_section_used = test_generic_lower_section(FORCED_JOKER.rules, YAHTZEE_BONUS);
+        test_generic_lower_section(FORCED_JOKER.rules, YAHTZEE_BONUS);
 
         // should also work with bonus
         assert_eq!(
-            (FORCED_JOKER.rules)(&upper_section_used, 1, 1, 2),
+            (FORCED_JOKER.rules)(&aces(Some(FULL_HOUSE_SCORE), false), LS),
             (FULL_HOUSE_SCORE, YAHTZEE_BONUS)
         );
     }
@@ -188,37 +380,35 @@ mod tests {
 
         // Lower section should award points even when upper section is still available
         assert_eq!(
-            (FREE_JOKER.rules)(&have_yahtzee(), 1, 1, 0),
+            (FREE_JOKER.rules)(&aces(None, true), LS),
             (5, YAHTZEE_BONUS)
         );
 
         // should also work with bonus, but only when upper section is full
-        let mut upper_section_used = have_yahtzee();
-        upper_section_used[US][0] = true;
         assert_eq!(
-            (FREE_JOKER.rules)(&upper_section_used, 1, 1, 3),
+            (FREE_JOKER.rules)(&aces(Some(SMALL_STRAIGHT_SCORE), false), LS),
             (SMALL_STRAIGHT_SCORE, YAHTZEE_BONUS)
         );
 
         // Attempt bonus in lower section when upper section is still available,
         // should not award points
-        assert_eq!((FREE_JOKER.rules)(&have_yahtzee(), 1, 1, 2), (0, 0));
+        assert_eq!(
+            (FREE_JOKER.rules)(&aces(Some(FULL_HOUSE_SCORE), true), LS),
+            (0, 0)
+        );
     }
 
     #[test]
     fn test_original() {
         // Upper section should not award bonus, but points
-        assert_eq!((ORIGINAL.rules)(&have_yahtzee(), 1, 0, 0), (5, 0));
+        assert_eq!((ORIGINAL.rules)(&in_aces(), US), (5, 0));
 
         // Lower section should award points
-        assert_eq!(
-            (ORIGINAL.rules)(&have_yahtzee(), 1, 1, 0),
-            (5, YAHTZEE_BONUS)
-        );
+        assert_eq!((ORIGINAL.rules)(&aces(None, true), LS), (5, YAHTZEE_BONUS));
 
         // should also work with bonus
         assert_eq!(
-            (ORIGINAL.rules)(&have_yahtzee(), 1, 1, 4),
+            (ORIGINAL.rules)(&aces(Some(LARGE_STRAIGHT_SCORE), true), LS),
             (LARGE_STRAIGHT_SCORE, YAHTZEE_BONUS)
         );
     }
@@ -226,21 +416,45 @@ mod tests {
     #[test]
     fn test_kniffel() {
         test_generic_upper_section(KNIFFEL.rules, YAHTZEE_SCORE);
-        let upper_section_used = test_generic_lower_section(KNIFFEL.rules, YAHTZEE_SCORE);
+        test_generic_lower_section(KNIFFEL.rules, YAHTZEE_SCORE);
 
         // should not work with bonus
-        assert_eq!((KNIFFEL.rules)(&upper_section_used, 1, 1, 2), (0, 0));
+        assert_eq!(
+            (KNIFFEL.rules)(&aces(Some(FULL_HOUSE_SCORE), false), LS),
+            (0, 0)
+        );
+    }
 
-        // but should work with Chance
+    #[test]
+    fn test_double_generala() {
+        // Scored as is anywhere, without jokers
         assert_eq!(
-            (KNIFFEL.rules)(&upper_section_used, 1, 1, 6),
-            (5, YAHTZEE_SCORE)
+            (DOUBLE_GENERALA.rules)(&in_aces(), US),
+            (5, DOUBLE_GENERALA_BONUS)
         );
+        assert_eq!(
+            (DOUBLE_GENERALA.rules)(&aces(Some(FULL_HOUSE_SCORE), true), LS),
+            (5, DOUBLE_GENERALA_BONUS)
+        );
+
+        // Only after a Generala was scored
+        let rules = rules::build_generala_rules();
+        let generala = rules.yahtzee_field().unwrap();
+        let state = strategy::State::new_from_rules(&rules);
+        let hand = [4, 4, 4, 4, 4];
+        let (state, score) = strategy::score_field(&state, &hand, 0, LS, generala, REROLLS, &rules);
+        assert!(state.scored_yahtzee);
+        // Served
+        assert_eq!(score, [0, 100]);
+        let (_, score) = strategy::score_field(&state, &hand, 0, US, 3, 0, &rules);
+        assert_eq!(score, [20, DOUBLE_GENERALA_BONUS]);
+        let (_, score) = strategy::score_field(&state, &hand, 0, LS, 0, 0, &rules);
+        assert_eq!(score, [0, DOUBLE_GENERALA_BONUS]);
     }
 
     #[test]
     #[should_panic]
     fn test_none() {
-        (NONE.rules)(&mut have_yahtzee(), 1, 0, 0);
+        (NONE.rules)(&in_aces(), US);
     }
 }